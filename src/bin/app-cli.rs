@@ -1,32 +1,72 @@
-use citadel_apps::composegenerator::convert_config;
-#[cfg(any(feature = "dev-tools", feature = "preprocess"))]
+use citadel_apps::composegenerator::convert_config_with_diagnostics;
 use citadel_apps::composegenerator::load_config;
-#[cfg(all(feature = "umbrel", feature = "dev-tools"))]
-use citadel_apps::composegenerator::umbrel::types::Metadata as UmbrelMetadata;
-#[cfg(feature = "preprocess")]
-use citadel_apps::{
-    composegenerator::v4::{permissions::is_allowed_by_permissions, utils::derive_entropy},
-    utils::flatten,
+use citadel_apps::composegenerator::output::render::{
+    render_compose, RenderOptions, DEFAULT_NETWORK_NAME,
+};
+use citadel_apps::composegenerator::types::ResultYml;
+use citadel_apps::composegenerator::v3::convert::v3_to_v4;
+use citadel_apps::composegenerator::v4::ip_addresses::{
+    allocate_ip_addresses, load_ip_allocation, to_env_file, IpAllocation, Subnet,
+    DEFAULT_RESERVED_RANGE, DEFAULT_SUBNET,
+};
+use citadel_apps::composegenerator::v4::permissions::{
+    load_permission_registry, PermissionRegistry,
+};
+use citadel_apps::composegenerator::v4::portmap::{
+    generate_port_map, load_port_map, PortMap, RESERVED_PORTS,
+};
+use citadel_apps::composegenerator::v4::resources::{load_resource_policy, ResourcePolicy};
+use citadel_apps::composegenerator::v4::types::AppYml;
+#[cfg(feature = "dev-tools")]
+use citadel_apps::composegenerator::validate_config;
+#[cfg(feature = "umbrel")]
+use citadel_apps::composegenerator::{
+    compose::types::ComposeSpecification,
+    umbrel::{
+        convert::{check_metadata, convert_compose_with_diagnostics},
+        mapping::{load_mapping, Mapping},
+        types::Metadata as UmbrelMetadata,
+    },
 };
 #[cfg(feature = "dev-tools")]
+use citadel_apps::composegenerator::{types::Permissions, v4::permissions::explain_permissions};
+#[cfg(feature = "preprocess")]
+use citadel_apps::preprocess::{render_app_template, render_template, Context};
+#[cfg(feature = "dev-tools")]
+use citadel_apps::report::{to_json, to_sarif, AppReport};
+#[cfg(feature = "preprocess")]
+use citadel_apps::{composegenerator::v4::utils::derive_entropy, utils::flatten};
+#[cfg(feature = "dev-tools")]
 use citadel_apps::{
     composegenerator::{
-        compose::types::ComposeSpecification,
-        types::{Metadata, ResultYml},
-        v3::{convert::v3_to_v4, types::SchemaItemContainers},
-        v4::types::AppYml,
+        types::Metadata, v3::types::SchemaItemContainers, v4::port_conflicts::find_port_conflicts,
+        AppYmlFile,
     },
+    source_map::SourceMap,
     updates::update_app,
 };
+#[cfg(feature = "umbrel")]
+use citadel_apps::{diagnostics::Diagnostic, error::Error};
+#[cfg(feature = "dev-tools")]
+use clap::ValueEnum;
 use clap::{Parser, Subcommand};
+use std::collections::BTreeMap;
 #[cfg(feature = "preprocess")]
 use std::io::{Read, Write};
-#[cfg(any(feature = "umbrel", feature = "preprocess"))]
 use std::path::Path;
-#[cfg(any(feature = "dev-tools", feature = "preprocess"))]
 use std::process::exit;
-#[cfg(feature = "preprocess")]
-use tera::{Context, Tera};
+
+/// The format validation results are printed in
+#[cfg(feature = "dev-tools")]
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum OutputFormat {
+    /// Human-readable diagnostics with snippets of the app.yml
+    Text,
+    /// A JSON report listing every diagnostic
+    Json,
+    /// A SARIF 2.1.0 log, for example for GitHub code scanning
+    Sarif,
+}
 
 #[derive(Subcommand, Debug)]
 enum SubCommand {
@@ -45,6 +85,68 @@ enum SubCommand {
         /// The services that are installed as a list of comma separated values
         #[clap(long)]
         services: Option<String>,
+        /// Replace env vars in the result with their values from this env file (usually the node's .env)
+        /// Fails if an env var the app uses is not set
+        #[clap(long)]
+        resolve_env: Option<String>,
+        /// Also write a complete docker-compose.yml for the app to this file
+        #[clap(long)]
+        compose: Option<String>,
+        /// The Docker network the app's containers are connected to in the docker-compose.yml
+        #[clap(long, default_value = DEFAULT_NETWORK_NAME)]
+        network: String,
+        /// Env files every container in the docker-compose.yml loads
+        #[clap(long)]
+        env_file: Vec<String>,
+        /// The node's resource policy, if not set, the recommended defaults are used
+        #[clap(long)]
+        resource_policy: Option<String>,
+        /// What each permission grants, if not set, the built-in permissions are used
+        #[clap(long)]
+        permission_registry: Option<String>,
+    },
+    /// Generate the port map for all apps in a directory
+    /// Ports assigned in an existing port map file are kept
+    PortMap {
+        /// The directory containing the installed apps, each in a subdirectory with an app.yml
+        dir: String,
+        /// The port map file to write, if it already exists it is used as the previous port map
+        output: String,
+        /// Only assign ports to these apps, as a list of comma separated values
+        #[clap(long)]
+        apps: Option<String>,
+    },
+    /// Assign an IP address to every container of the apps in a directory
+    /// Addresses assigned in an existing allocation file are kept
+    IpAddresses {
+        /// The directory containing the installed apps, each in a subdirectory with an app.yml
+        dir: String,
+        /// The file to save the allocation to, if it already exists it is used as the previous allocation
+        output: String,
+        /// The env file to write the APP_<APP>_<SERVICE>_IP env vars to
+        #[clap(long)]
+        env_file: String,
+        /// The subnet to assign addresses from
+        #[clap(long, default_value = DEFAULT_SUBNET)]
+        subnet: String,
+        /// Parts of the subnet that are not used for apps
+        #[clap(long, default_value = DEFAULT_RESERVED_RANGE)]
+        reserved: Vec<String>,
+        /// Only assign addresses to these apps, as a list of comma separated values
+        #[clap(long)]
+        apps: Option<String>,
+    },
+    /// Save the keys of the authorized clients of an app's hidden services
+    /// The private keys the clients need are printed to stdout
+    TorClientAuth {
+        /// The result.yml created by convert
+        result: String,
+        /// The file containing the node's seed
+        #[clap(long)]
+        seed_file: String,
+        /// The directory Tor's /var/lib/tor is stored in on the host
+        #[clap(long)]
+        tor_dir: String,
     },
     /// Get a JSON schema for the app.yml format
     #[cfg(feature = "dev-tools")]
@@ -101,15 +203,48 @@ enum SubCommand {
         /// The services that are installed as a list of comma separated values
         #[clap(long)]
         services: Option<String>,
+        /// What each permission grants, if not set, the built-in permissions are used
+        #[clap(long)]
+        permission_registry: Option<String>,
     },
     /// Convert an Umbrel app (by app directory path) to a Citadel app.yml file
-    /// Manual fixes may be required to make the app.yml work
+    /// Manual fixes may be required to make the app.yml work, every setting that was dropped or changed is reported
     #[cfg(feature = "umbrel")]
     UmbrelToCitadel {
         /// The app directory to run this on
         app: String,
         /// The output file to save the result to
         output: String,
+        /// How Umbrel env vars, dependencies and mounts are translated, if not set, the built-in mapping is used
+        #[clap(long)]
+        mapping: Option<String>,
+    },
+    /// Convert every app in a checkout of an Umbrel app store to Citadel app.yml files
+    /// The env vars that could not be translated are summarized for every app
+    #[cfg(feature = "umbrel")]
+    UmbrelStoreImport {
+        /// The app store directory, containing the apps in subdirectories
+        store: String,
+        /// The directory to save the apps to, each app is saved to <app>/app.yml
+        output: String,
+        /// How Umbrel env vars, dependencies and mounts are translated, if not set, the built-in mapping is used
+        #[clap(long)]
+        mapping: Option<String>,
+    },
+    /// Convert a Citadel app.yml to an Umbrel app directory with an umbrel-app.yml and docker-compose.yml
+    /// Settings Umbrel does not support are reported and dropped
+    #[cfg(feature = "umbrel")]
+    CitadelToUmbrel {
+        /// The app file to run this on
+        app: String,
+        /// The app's ID
+        #[clap(short, long)]
+        app_name: String,
+        /// The directory to save the Umbrel app to
+        output: String,
+        /// How Umbrel env vars, dependencies and mounts are translated, if not set, the built-in mapping is used
+        #[clap(long)]
+        mapping: Option<String>,
     },
     /// Validate a Citadel app.yml file and check if it could be parsed & converted
     #[cfg(feature = "dev-tools")]
@@ -119,6 +254,36 @@ enum SubCommand {
         /// The app's ID
         #[clap(short, long)]
         app_name: String,
+        /// The output format
+        #[clap(short, long, value_enum, default_value = "text")]
+        format: OutputFormat,
+    },
+    /// Explain which permission grants (or would grant) every env var, mount and capability an app uses
+    /// Permissions the app requests, but does not need, are listed too
+    #[cfg(feature = "dev-tools")]
+    ExplainPermissions {
+        /// The app file to run this on
+        app: String,
+        /// The app's ID
+        #[clap(short, long)]
+        app_name: String,
+        /// What each permission grants, if not set, the built-in permissions are used
+        #[clap(long)]
+        permission_registry: Option<String>,
+    },
+    /// Validate every app in an apps directory, the app ID is the name of the app's subdirectory
+    /// app.yml.jinja files are preprocessed first if app-cli was built with the preprocess feature,
+    /// problems in them are reported for the rendered app.yml
+    #[cfg(feature = "dev-tools")]
+    ValidateDir {
+        /// The directory to run this on
+        dir: String,
+        /// The services that are installed as a list of comma separated values (used for preprocessing)
+        #[clap(short, long)]
+        services: Option<String>,
+        /// The output format
+        #[clap(short, long, value_enum, default_value = "text")]
+        format: OutputFormat,
     },
     /// Update the app inside an app.yml to its latest version
     #[cfg(feature = "dev-tools")]
@@ -149,6 +314,36 @@ struct Cli {
     command: SubCommand,
 }
 
+/// Load the app.yml of every app in a directory, optionally only the apps in a comma separated list
+/// v3 apps are converted to v4, apps that fail to load are skipped
+fn load_apps_dir(dir: &Path, apps: &Option<String>) -> BTreeMap<String, AppYml> {
+    let app_filter: Option<Vec<&str>> = apps.as_ref().map(|apps| apps.split(',').collect());
+    let mut app_definitions = BTreeMap::new();
+    for entry in dir.read_dir().expect("Failed to read directory") {
+        let entry = entry.expect("Failed to read directory entry");
+        let app_file = entry.path().join("app.yml");
+        let app_name = entry.file_name().to_string_lossy().to_string();
+        if !app_file.is_file()
+            || app_filter
+                .as_ref()
+                .is_some_and(|app_filter| !app_filter.contains(&app_name.as_str()))
+        {
+            continue;
+        }
+        let app_yml = std::fs::File::open(&app_file).expect("Error opening app definition!");
+        let app_yml = match load_config(app_yml) {
+            Ok(citadel_apps::composegenerator::AppYmlFile::V4(app_yml)) => app_yml,
+            Ok(citadel_apps::composegenerator::AppYmlFile::V3(app_yml)) => v3_to_v4(app_yml, &None),
+            Err(error) => {
+                eprintln!("Skipping {}: {}", app_name, error);
+                continue;
+            }
+        };
+        app_definitions.insert(app_name, app_yml);
+    }
+    app_definitions
+}
+
 #[cfg(feature = "dev-tools")]
 async fn update_app_yml(path: &Path, include_prerelease: bool) {
     let app_yml = std::fs::File::open(path).expect("Error opening app definition!");
@@ -168,6 +363,208 @@ async fn update_app_yml(path: &Path, include_prerelease: bool) {
         }
     }
 }
+
+#[cfg(all(feature = "dev-tools", not(feature = "preprocess")))]
+fn render_app_template(
+    _template: &str,
+    _app_name: &str,
+    _services: &[&str],
+) -> Result<String, Error> {
+    Err(Error::Preprocess(
+        "app-cli was built without the preprocess feature".to_string(),
+    ))
+}
+
+/// The definition of an app in an apps directory and the problems found in it
+#[cfg(feature = "dev-tools")]
+struct ValidatedApp {
+    name: String,
+    file: String,
+    source: String,
+    diagnostics: Vec<Diagnostic>,
+}
+
+/// Load (and if required, preprocess) the app in an app directory and validate it
+/// Returns None if the directory does not contain an app definition
+#[cfg(feature = "dev-tools")]
+fn validate_app_dir(app_dir: &Path, services: &[&str]) -> Option<ValidatedApp> {
+    let name = app_dir.file_name()?.to_string_lossy().to_string();
+    let jinja_file = app_dir.join("app.yml.jinja");
+    let app_file = app_dir.join("app.yml");
+    let load_error = |file: &Path, error: Error| ValidatedApp {
+        name: name.clone(),
+        file: file.display().to_string(),
+        source: String::new(),
+        diagnostics: vec![Diagnostic::error("", error)],
+    };
+    let (file, source) =
+        if jinja_file.is_file() && (cfg!(feature = "preprocess") || !app_file.is_file()) {
+            let source = std::fs::read_to_string(&jinja_file)
+                .map_err(|error| Error::Io(error.to_string()))
+                .and_then(|template| render_app_template(&template, &name, services));
+            match source {
+                // Locations are in the rendered template, which preprocess-dir saves as app.yml
+                Ok(source) => (app_file, source),
+                Err(error) => return Some(load_error(&jinja_file, error)),
+            }
+        } else if app_file.is_file() {
+            match std::fs::read_to_string(&app_file) {
+                Ok(source) => (app_file, source),
+                Err(error) => return Some(load_error(&app_file, Error::Io(error.to_string()))),
+            }
+        } else {
+            return None;
+        };
+    let diagnostics = validate_config(&name, source.as_bytes());
+    Some(ValidatedApp {
+        name,
+        file: file.display().to_string(),
+        source,
+        diagnostics,
+    })
+}
+
+/// Check which apps in a store want the same host ports and add diagnostics for the conflicts
+#[cfg(feature = "dev-tools")]
+fn add_port_conflicts(apps: &mut [ValidatedApp]) {
+    let app_definitions: BTreeMap<String, AppYml> = apps
+        .iter()
+        .filter_map(|app| match load_config(app.source.as_bytes()).ok()? {
+            AppYmlFile::V4(app_yml) => Some((app.name.clone(), app_yml)),
+            AppYmlFile::V3(app_yml) => Some((app.name.clone(), v3_to_v4(app_yml, &None))),
+        })
+        .collect();
+    for conflict in find_port_conflicts(&app_definitions) {
+        for (app_name, mut diagnostic) in conflict.diagnostics() {
+            let app = apps.iter_mut().find(|app| app.name == app_name).unwrap();
+            diagnostic.locate(&SourceMap::parse(&app.source));
+            app.diagnostics.push(diagnostic);
+        }
+    }
+}
+
+#[cfg(feature = "umbrel")]
+fn load_umbrel_mapping(mapping: &Option<String>) -> Mapping {
+    match mapping {
+        Some(mapping) => {
+            let file = std::fs::File::open(mapping.as_str()).expect("Error opening mapping!");
+            load_mapping(file).expect("Error loading mapping!")
+        }
+        None => Mapping::default(),
+    }
+}
+
+/// A file of an Umbrel app and the problems found in it
+#[cfg(feature = "umbrel")]
+struct ImportedFile {
+    file: String,
+    source: String,
+    diagnostics: Vec<Diagnostic>,
+}
+
+#[cfg(feature = "umbrel")]
+impl ImportedFile {
+    /// Print the diagnostics with the lines of the file they refer to
+    fn print_diagnostics(&mut self) {
+        let source_map = citadel_apps::source_map::SourceMap::parse(&self.source);
+        for diagnostic in &mut self.diagnostics {
+            diagnostic.locate(&source_map);
+            eprint!("{}", diagnostic.render(&self.file, &self.source));
+        }
+    }
+}
+
+/// An Umbrel app converted to a Citadel app and the problems found while converting it
+#[cfg(feature = "umbrel")]
+struct ImportedApp {
+    metadata: ImportedFile,
+    compose: ImportedFile,
+    result: AppYml,
+}
+
+#[cfg(feature = "umbrel")]
+impl ImportedApp {
+    fn print_diagnostics(&mut self) {
+        self.metadata.print_diagnostics();
+        self.compose.print_diagnostics();
+    }
+
+    fn diagnostics(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.metadata
+            .diagnostics
+            .iter()
+            .chain(self.compose.diagnostics.iter())
+    }
+
+    fn error_count(&self) -> usize {
+        self.diagnostics()
+            .filter(|diagnostic| diagnostic.is_error())
+            .count()
+    }
+}
+
+/// Get the names of the hooks of an Umbrel app
+#[cfg(feature = "umbrel")]
+fn umbrel_hooks(app_dir: &Path) -> Vec<String> {
+    let mut hooks: Vec<String> = std::fs::read_dir(app_dir.join("hooks"))
+        .map(|entries| {
+            entries
+                .filter_map(|entry| Some(entry.ok()?.file_name().to_string_lossy().to_string()))
+                .collect()
+        })
+        .unwrap_or_default();
+    hooks.sort();
+    hooks
+}
+
+/// Convert the Umbrel app in an app directory to a Citadel app
+#[cfg(feature = "umbrel")]
+fn import_umbrel_app(app_dir: &Path, mapping: &Mapping) -> Result<ImportedApp, String> {
+    let compose_file = app_dir.join("docker-compose.yml");
+    let compose_yml = std::fs::read_to_string(&compose_file)
+        .map_err(|error| format!("Error opening docker-compose.yml: {}", error))?;
+    let app_file = app_dir.join("umbrel-app.yml");
+    let app_yml = std::fs::read_to_string(&app_file)
+        .map_err(|error| format!("Error opening umbrel-app.yml: {}", error))?;
+    let app_yml_parsed: UmbrelMetadata = serde_yaml::from_str(&app_yml)
+        .map_err(|error| format!("Error parsing umbrel-app.yml: {}", error))?;
+    let compose_yml_parsed: ComposeSpecification = serde_yaml::from_str(&compose_yml)
+        .map_err(|error| format!("Error parsing docker-compose.yml: {}", error))?;
+    if compose_yml_parsed.services.is_none() {
+        return Err("docker-compose.yml does not contain any services".to_string());
+    }
+    let metadata_diagnostics = check_metadata(&app_yml_parsed, &umbrel_hooks(app_dir));
+    let mut diagnostics = Vec::new();
+    let result = convert_compose_with_diagnostics(
+        compose_yml_parsed,
+        app_yml_parsed,
+        mapping,
+        &mut diagnostics,
+    );
+    Ok(ImportedApp {
+        metadata: ImportedFile {
+            file: app_file.to_string_lossy().to_string(),
+            source: app_yml,
+            diagnostics: metadata_diagnostics,
+        },
+        compose: ImportedFile {
+            file: compose_file.to_string_lossy().to_string(),
+            source: compose_yml,
+            diagnostics,
+        },
+        result,
+    })
+}
+
+/// Load the permission registry from a file, None means the built-in registry is used
+fn load_registry(permission_registry: &Option<String>) -> Option<PermissionRegistry> {
+    permission_registry.as_ref().map(|permission_registry| {
+        let file = std::fs::File::open(permission_registry.as_str())
+            .expect("Error opening permission registry!");
+        load_permission_registry(file).expect("Error loading permission registry!")
+    })
+}
+
 #[tokio::main]
 async fn main() {
     env_logger::init();
@@ -179,8 +576,15 @@ async fn main() {
             output,
             port_map,
             services,
+            resolve_env,
+            compose,
+            network,
+            env_file,
+            resource_policy,
+            permission_registry,
         } => {
-            let app_yml = std::fs::File::open(app.as_str()).expect("Error opening app definition!");
+            let app_yml =
+                std::fs::read_to_string(app.as_str()).expect("Error opening app definition!");
             let port_map = std::fs::File::open(port_map.as_str()).expect("Error opening port map!");
             let port_map: serde_json::Map<String, serde_json::Value> =
                 serde_json::from_reader(port_map).expect("Error loading port map!");
@@ -189,9 +593,17 @@ async fn main() {
                 .as_object()
                 .expect("App definition in port map is invalid!")
                 .to_owned();
-            let result = convert_config(
+            let resource_policy = match resource_policy {
+                Some(resource_policy) => {
+                    let file = std::fs::File::open(resource_policy.as_str())
+                        .expect("Error opening resource policy!");
+                    load_resource_policy(file).expect("Error loading resource policy!")
+                }
+                None => ResourcePolicy::recommended(),
+            };
+            let (result, diagnostics) = convert_config_with_diagnostics(
                 &app_name,
-                &app_yml,
+                app_yml.as_bytes(),
                 &Some(port_map),
                 &Some(
                     services
@@ -200,11 +612,134 @@ async fn main() {
                         .map(|val| val.to_string())
                         .collect(),
                 ),
-            )
-            .expect("Failed to convert config!");
+                &Some(resource_policy),
+                &load_registry(&permission_registry),
+                &resolve_env.map(|env_file| {
+                    #[allow(deprecated)]
+                    let env_vars =
+                        dotenv::from_filename_iter(env_file).expect("Failed to load env file");
+                    env_vars
+                        .map(|item| item.expect("Env var invalid"))
+                        .collect()
+                }),
+            );
+            for diagnostic in &diagnostics {
+                eprint!("{}", diagnostic.render(&app, &app_yml));
+            }
+            let result = match result {
+                Some(result) => result,
+                None => {
+                    eprintln!("Failed to convert config!");
+                    exit(1);
+                }
+            };
+            if let Some(compose) = compose {
+                let options = RenderOptions {
+                    network_name: network,
+                    env_files: env_file,
+                };
+                let writer = std::fs::File::create(compose.as_str()).unwrap();
+                serde_yaml::to_writer(writer, &render_compose(&app_name, &result, &options))
+                    .expect("Failed to save");
+            }
             let writer = std::fs::File::create(output.as_str()).unwrap();
             serde_yaml::to_writer(writer, &result).expect("Failed to save");
         }
+        SubCommand::PortMap { dir, output, apps } => {
+            let dir_path = Path::new(dir.as_str());
+            if !dir_path.is_dir() {
+                log::error!("Directory not found!");
+                exit(1);
+            }
+            let app_definitions = load_apps_dir(dir_path, &apps);
+            let mut previous = match std::fs::File::open(output.as_str()) {
+                Ok(file) => load_port_map(file).expect("Error loading previous port map!"),
+                Err(_) => PortMap::new(),
+            };
+            // Apps that are not passed keep their ports, unless they were uninstalled
+            previous.retain(|app_name, _| dir_path.join(app_name).is_dir());
+            let port_map = match generate_port_map(&app_definitions, &previous, RESERVED_PORTS) {
+                Ok((port_map, diagnostics)) => {
+                    for (app_name, diagnostic) in diagnostics {
+                        eprintln!("Skipping {}: {}", app_name, diagnostic);
+                    }
+                    port_map
+                }
+                Err(error) => {
+                    eprintln!("Failed to generate port map: {}", error);
+                    exit(1);
+                }
+            };
+            let writer = std::fs::File::create(output.as_str()).expect("Error creating port map!");
+            serde_json::to_writer_pretty(writer, &port_map).expect("Error saving port map!");
+        }
+        SubCommand::IpAddresses {
+            dir,
+            output,
+            env_file,
+            subnet,
+            reserved,
+            apps,
+        } => {
+            let dir_path = Path::new(dir.as_str());
+            if !dir_path.is_dir() {
+                log::error!("Directory not found!");
+                exit(1);
+            }
+            let app_definitions = load_apps_dir(dir_path, &apps);
+            let subnet: Subnet = subnet.parse().expect("Invalid subnet!");
+            let reserved: Vec<Subnet> = reserved
+                .iter()
+                .map(|range| range.parse().expect("Invalid reserved range!"))
+                .collect();
+            let mut previous = match std::fs::File::open(output.as_str()) {
+                Ok(file) => load_ip_allocation(file).expect("Error loading previous allocation!"),
+                Err(_) => IpAllocation::new(),
+            };
+            // Apps that are not passed keep their addresses, unless they were uninstalled
+            previous.retain(|app_name, _| dir_path.join(app_name).is_dir());
+            let allocation =
+                match allocate_ip_addresses(&app_definitions, &previous, &subnet, &reserved) {
+                    Ok(allocation) => allocation,
+                    Err(error) => {
+                        eprintln!("Failed to assign IP addresses: {}", error);
+                        exit(1);
+                    }
+                };
+            let writer =
+                std::fs::File::create(output.as_str()).expect("Error creating allocation!");
+            serde_json::to_writer_pretty(writer, &allocation).expect("Error saving allocation!");
+            std::fs::write(env_file, to_env_file(&allocation)).expect("Error saving env file!");
+        }
+        SubCommand::TorClientAuth {
+            result,
+            seed_file,
+            tor_dir,
+        } => {
+            let result = std::fs::File::open(result.as_str()).expect("Error opening result.yml!");
+            let result: ResultYml =
+                serde_yaml::from_reader(result).expect("Error loading result.yml!");
+            let seed = std::fs::read_to_string(seed_file).expect("Error reading seed file!");
+            for hidden_service in &result.new_tor_entries.hidden_services {
+                for (file, content) in hidden_service.authorized_client_files(&seed) {
+                    let file = Path::new(&tor_dir).join(
+                        file.trim_start_matches("/var/lib/tor")
+                            .trim_start_matches('/'),
+                    );
+                    std::fs::create_dir_all(file.parent().unwrap())
+                        .expect("Error creating authorized_clients directory!");
+                    std::fs::write(file, content).expect("Error saving authorized client!");
+                }
+                for client in &hidden_service.authorized_clients {
+                    println!(
+                        "{} {} descriptor:x25519:{}",
+                        hidden_service.name(),
+                        client,
+                        hidden_service.client_auth_keys(&seed, client).private_key
+                    );
+                }
+            }
+        }
         #[cfg(feature = "dev-tools")]
         SubCommand::Schema { version } => match version.as_str() {
             "3" => {
@@ -259,13 +794,12 @@ async fn main() {
                         continue;
                     }
                     let app_definition = std::fs::read_to_string(app_file.as_path()).unwrap();
-                    let mut context = Context::new();
-                    context.insert("services", &service_list);
-                    context.insert("app_name", &app_name);
-                    let result = Tera::one_off(app_definition.as_str(), &context, false)
-                        .expect("Failed to preprocess");
-                    let writer = std::fs::File::create(entry.path().join("app.yml")).unwrap();
-                    serde_yaml::to_writer(writer, &result).expect("Failed to save!");
+                    let result = render_app_template(&app_definition, app_name, &service_list)
+                        .unwrap_or_else(|error| {
+                            log::error!("{}: {}", app_name, error);
+                            exit(1);
+                        });
+                    std::fs::write(entry.path().join("app.yml"), result).expect("Failed to save!");
                 }
             }
         }
@@ -280,15 +814,15 @@ async fn main() {
             let service_list: Vec<&str> = services.split(',').collect();
             let mut app_yml =
                 std::fs::File::open(app.as_str()).expect("Error opening app definition!");
-            let mut context = Context::new();
-            context.insert("services", &service_list);
-            context.insert("app_name", &app_name);
             let mut tmpl = String::new();
             app_yml
                 .read_to_string(&mut tmpl)
                 .expect("Error running templating engine on app definition!");
-            let tmpl_result = Tera::one_off(tmpl.as_str(), &context, false)
-                .expect("Error running templating engine on app definition!");
+            let tmpl_result =
+                render_app_template(&tmpl, &app_name, &service_list).unwrap_or_else(|error| {
+                    log::error!("{}", error);
+                    exit(1);
+                });
             let mut writer = std::fs::File::create(output.as_str()).unwrap();
             writer
                 .write_all(tmpl_result.as_bytes())
@@ -303,7 +837,9 @@ async fn main() {
             seed_file,
             output,
             services,
+            permission_registry,
         } => {
+            let registry = load_registry(&permission_registry).unwrap_or_default();
             let services = services.unwrap_or_default();
             let service_list: Vec<&str> = services.split(',').collect();
             #[allow(deprecated)]
@@ -323,7 +859,7 @@ async fn main() {
                     let app_id = app_name.as_str();
                     for item in env_vars {
                         let (key, val) = item.expect("Env var invalid");
-                        if is_allowed_by_permissions(app_id, &key, &permissions) {
+                        if registry.is_env_var_allowed(app_id, &key, &permissions) {
                             context.insert(key, &val);
                         }
                     }
@@ -355,36 +891,309 @@ async fn main() {
             config_file
                 .read_to_string(&mut tmpl)
                 .expect("Failed to load the config file!");
-            let tmpl_result = Tera::one_off(tmpl.as_str(), &context, false)
-                .expect("Error running templating engine on app definition!");
+            let tmpl_result = render_template(&tmpl, &context).unwrap_or_else(|error| {
+                log::error!("{}", error);
+                exit(1);
+            });
             let mut writer = std::fs::File::create(output.as_str()).unwrap();
             writer
                 .write_all(tmpl_result.as_bytes())
                 .expect("Failed to save file");
         }
         #[cfg(feature = "umbrel")]
-        SubCommand::UmbrelToCitadel { app, output } => {
-            let app_dir = Path::new(&app);
-            let compose_yml = std::fs::File::open(app_dir.join("docker-compose.yml"))
-                .expect("Error opening docker-compose.yml!");
-            let app_yml = std::fs::File::open(app_dir.join("umbrel-app.yml"))
-                .expect("Error opening umbrel-app.yml!");
-            let app_yml_parsed: citadel_apps::composegenerator::umbrel::types::Metadata =
-                serde_yaml::from_reader(app_yml).expect("Error parsing umbrel-app.yml!");
-            let compose_yml_parsed: citadel_apps::composegenerator::compose::types::ComposeSpecification
-             = serde_yaml::from_reader(compose_yml).expect("Error parsing docker-compose.yml!");
-            let result = citadel_apps::composegenerator::umbrel::convert::convert_compose(
-                compose_yml_parsed,
-                app_yml_parsed,
-            );
+        SubCommand::UmbrelToCitadel {
+            app,
+            output,
+            mapping,
+        } => {
+            let mapping = load_umbrel_mapping(&mapping);
+            let mut imported =
+                import_umbrel_app(Path::new(&app), &mapping).unwrap_or_else(|error| {
+                    eprintln!("{}", error);
+                    exit(1);
+                });
+            imported.print_diagnostics();
             let writer = std::fs::File::create(output).expect("Error creating output file");
-            serde_yaml::to_writer(writer, &result).expect("Error saving file!");
+            serde_yaml::to_writer(writer, &imported.result).expect("Error saving file!");
+            let error_count = imported.error_count();
+            if error_count > 0 {
+                eprintln!(
+                    "The app was converted, but {} setting(s) could not be imported and need manual fixes",
+                    error_count
+                );
+                exit(1);
+            }
+        }
+        #[cfg(feature = "umbrel")]
+        SubCommand::UmbrelStoreImport {
+            store,
+            output,
+            mapping,
+        } => {
+            let mapping = load_umbrel_mapping(&mapping);
+            let mut app_dirs: Vec<std::path::PathBuf> = std::fs::read_dir(&store)
+                .expect("Error reading app store directory!")
+                .filter_map(|entry| Some(entry.ok()?.path()))
+                .filter(|path| {
+                    path.join("umbrel-app.yml").is_file()
+                        && path.join("docker-compose.yml").is_file()
+                })
+                .collect();
+            app_dirs.sort();
+            let mut failed = Vec::<String>::new();
+            for app_dir in app_dirs {
+                let name = app_dir.file_name().unwrap().to_string_lossy().to_string();
+                let mut imported = match import_umbrel_app(&app_dir, &mapping) {
+                    Ok(imported) => imported,
+                    Err(error) => {
+                        eprintln!("{}: {}", name, error);
+                        failed.push(name);
+                        continue;
+                    }
+                };
+                imported.print_diagnostics();
+                let unmapped: std::collections::BTreeSet<&str> = imported
+                    .diagnostics()
+                    .filter_map(|diagnostic| match &diagnostic.error {
+                        Error::UnmappedEnvVar { env_var, .. } => Some(env_var.as_str()),
+                        _ => None,
+                    })
+                    .collect();
+                if !unmapped.is_empty() {
+                    eprintln!(
+                        "{}: unmapped env vars: {}",
+                        name,
+                        unmapped.into_iter().collect::<Vec<_>>().join(", ")
+                    );
+                }
+                let app_output = Path::new(&output).join(&name);
+                std::fs::create_dir_all(&app_output).expect("Error creating output directory");
+                let writer = std::fs::File::create(app_output.join("app.yml"))
+                    .expect("Error creating output file");
+                serde_yaml::to_writer(writer, &imported.result).expect("Error saving file!");
+                if imported.error_count() > 0 {
+                    failed.push(name);
+                }
+            }
+            if !failed.is_empty() {
+                eprintln!(
+                    "{} app(s) could not be imported completely and need manual fixes: {}",
+                    failed.len(),
+                    failed.join(", ")
+                );
+                exit(1);
+            }
+        }
+        #[cfg(feature = "umbrel")]
+        SubCommand::CitadelToUmbrel {
+            app,
+            app_name,
+            output,
+            mapping,
+        } => {
+            let mapping = load_umbrel_mapping(&mapping);
+            let app_yml = std::fs::read_to_string(&app).expect("Error opening app definition!");
+            let app_definition = match load_config(app_yml.as_bytes())
+                .expect("Failed to parse app.yml")
+            {
+                citadel_apps::composegenerator::AppYmlFile::V4(app_definition) => app_definition,
+                citadel_apps::composegenerator::AppYmlFile::V3(app_definition) => {
+                    v3_to_v4(app_definition, &None)
+                }
+            };
+            let mut diagnostics = Vec::new();
+            let result = citadel_apps::composegenerator::umbrel::export::convert_app(
+                &app_name,
+                app_definition,
+                &mapping,
+                &mut diagnostics,
+            );
+            let source_map = citadel_apps::source_map::SourceMap::parse(&app_yml);
+            for diagnostic in &mut diagnostics {
+                diagnostic.locate(&source_map);
+                eprint!("{}", diagnostic.render(&app, &app_yml));
+            }
+            let result = match result {
+                Some(result) => result,
+                None => {
+                    eprintln!("Failed to convert app!");
+                    exit(1);
+                }
+            };
+            let output_dir = Path::new(&output);
+            std::fs::create_dir_all(output_dir).expect("Error creating output directory");
+            let writer = std::fs::File::create(output_dir.join("umbrel-app.yml"))
+                .expect("Error creating umbrel-app.yml");
+            serde_yaml::to_writer(writer, &result.metadata).expect("Error saving file!");
+            let writer = std::fs::File::create(output_dir.join("docker-compose.yml"))
+                .expect("Error creating docker-compose.yml");
+            serde_yaml::to_writer(writer, &result.compose).expect("Error saving file!");
+        }
+        #[cfg(feature = "dev-tools")]
+        SubCommand::Validate {
+            app,
+            app_name,
+            format,
+        } => {
+            let app_yml = std::fs::read_to_string(&app).expect("Error opening app definition!");
+            let diagnostics = validate_config(&app_name, app_yml.as_bytes());
+            let error_count = diagnostics
+                .iter()
+                .filter(|diagnostic| diagnostic.is_error())
+                .count();
+            match format {
+                OutputFormat::Text => {
+                    for diagnostic in &diagnostics {
+                        eprint!("{}", diagnostic.render(&app, &app_yml));
+                    }
+                    if error_count > 0 {
+                        eprintln!("App is invalid: found {} error(s)", error_count);
+                    } else {
+                        println!("App is valid!");
+                    }
+                }
+                OutputFormat::Json | OutputFormat::Sarif => {
+                    let reports = [AppReport::new(&app_name, &app, &diagnostics)];
+                    let report = match format {
+                        OutputFormat::Sarif => to_sarif(&reports),
+                        _ => to_json(&reports),
+                    };
+                    println!("{}", serde_json::to_string_pretty(&report).unwrap());
+                }
+            }
+            if error_count > 0 {
+                exit(1);
+            }
+        }
+        #[cfg(feature = "dev-tools")]
+        SubCommand::ExplainPermissions {
+            app,
+            app_name,
+            permission_registry,
+        } => {
+            let app_yml = std::fs::File::open(&app).expect("Error opening app definition!");
+            let app_definition = match load_config(app_yml).expect("Failed to parse app.yml") {
+                AppYmlFile::V4(app_definition) => app_definition,
+                AppYmlFile::V3(app_definition) => v3_to_v4(app_definition, &None),
+            };
+            let registry = load_registry(&permission_registry).unwrap_or_default();
+            let report = explain_permissions(&app_name, &app_definition, &registry);
+            for permission_use in &report.uses {
+                println!("{}", permission_use);
+            }
+            if !report.unused.is_empty() {
+                let unused: Vec<String> = report
+                    .unused
+                    .iter()
+                    .map(|permission| match permission {
+                        Permissions::OneDependency(dependency) => dependency.to_owned(),
+                        Permissions::AlternativeDependency(alternatives) => {
+                            alternatives.join(" or ")
+                        }
+                    })
+                    .collect();
+                println!("Unused permissions: {}", unused.join(", "));
+            }
         }
         #[cfg(feature = "dev-tools")]
-        SubCommand::Validate { app, app_name } => {
-            let app_yml = std::fs::File::open(app).expect("Error opening app definition!");
-            convert_config(&app_name, &app_yml, &None, &None).expect("App is invalid");
-            println!("App is valid!");
+        SubCommand::ValidateDir {
+            dir,
+            services,
+            format,
+        } => {
+            let services = services.unwrap_or_default();
+            let service_list: Vec<&str> = services.split(',').collect();
+            let dir_path = Path::new(dir.as_str());
+            if !dir_path.is_dir() {
+                log::error!("Directory not found!");
+                exit(1);
+            }
+            let mut app_dirs: Vec<_> = dir_path
+                .read_dir()
+                .expect("Failed to read directory")
+                .map(|entry| entry.expect("Failed to read directory entry").path())
+                .filter(|path| path.is_dir())
+                .collect();
+            app_dirs.sort();
+            let threads = std::thread::available_parallelism().map_or(1, |threads| threads.get());
+            let chunk_size = app_dirs.len().div_ceil(threads).max(1);
+            let mut apps: Vec<ValidatedApp> = std::thread::scope(|scope| {
+                let workers: Vec<_> = app_dirs
+                    .chunks(chunk_size)
+                    .map(|chunk| {
+                        let service_list = &service_list;
+                        scope.spawn(move || {
+                            chunk
+                                .iter()
+                                .filter_map(|app_dir| validate_app_dir(app_dir, service_list))
+                                .collect::<Vec<_>>()
+                        })
+                    })
+                    .collect();
+                workers
+                    .into_iter()
+                    .flat_map(|worker| worker.join().expect("Failed to validate apps"))
+                    .collect()
+            });
+            add_port_conflicts(&mut apps);
+            let invalid_apps = apps
+                .iter()
+                .filter(|app| app.diagnostics.iter().any(Diagnostic::is_error))
+                .count();
+            match format {
+                OutputFormat::Text => {
+                    for app in &apps {
+                        for diagnostic in &app.diagnostics {
+                            eprint!("{}", diagnostic.render(&app.file, &app.source));
+                        }
+                    }
+                    let name_width = apps
+                        .iter()
+                        .map(|app| app.name.len())
+                        .chain(std::iter::once("App".len()))
+                        .max()
+                        .unwrap_or_default();
+                    println!(
+                        "{:<width$}  {:<7}  {:>6}  {:>8}",
+                        "App",
+                        "Status",
+                        "Errors",
+                        "Warnings",
+                        width = name_width
+                    );
+                    for app in &apps {
+                        let errors = app.diagnostics.iter().filter(|d| d.is_error()).count();
+                        let warnings = app.diagnostics.len() - errors;
+                        println!(
+                            "{:<width$}  {:<7}  {:>6}  {:>8}",
+                            app.name,
+                            if errors > 0 { "invalid" } else { "valid" },
+                            errors,
+                            warnings,
+                            width = name_width
+                        );
+                    }
+                    println!(
+                        "{} of {} apps are valid",
+                        apps.len() - invalid_apps,
+                        apps.len()
+                    );
+                }
+                OutputFormat::Json | OutputFormat::Sarif => {
+                    let reports: Vec<AppReport> = apps
+                        .iter()
+                        .map(|app| AppReport::new(&app.name, &app.file, &app.diagnostics))
+                        .collect();
+                    let report = match format {
+                        OutputFormat::Sarif => to_sarif(&reports),
+                        _ => to_json(&reports),
+                    };
+                    println!("{}", serde_json::to_string_pretty(&report).unwrap());
+                }
+            }
+            if invalid_apps > 0 {
+                exit(1);
+            }
         }
         #[cfg(feature = "dev-tools")]
         SubCommand::Update {
@@ -1,4 +1,5 @@
 pub mod compose;
+pub mod tor;
 pub mod types;
 #[cfg(feature = "umbrel")]
 pub mod umbrel;
@@ -7,79 +8,268 @@ pub mod v4;
 // A subset of compose
 pub mod output;
 
+use std::collections::HashMap;
+
 use serde_json::{Map, Value};
 
+use self::output::resolve::resolve_env_vars;
 use self::types::ResultYml;
 use self::v3::types::Schema as AppYmlV3;
+use self::v4::permissions::PermissionRegistry;
+use self::v4::resources::ResourcePolicy;
 use self::v4::types::AppYml as AppYmlV4;
+use crate::diagnostics::Diagnostic;
+use crate::error::{Error, Location};
+use crate::source_map::SourceMap;
 
 pub enum AppYmlFile {
     V3(AppYmlV3),
     V4(AppYmlV4),
 }
 
-pub fn load_config<R>(app_reader: R) -> Result<AppYmlFile, String>
+pub fn load_config<R>(app_reader: R) -> Result<AppYmlFile, Error>
 where
     R: std::io::Read,
 {
-    let app_yml = serde_yaml::from_reader::<R, serde_yaml::Value>(app_reader)
-        .expect("Failed to parse app.yml");
+    load_config_with_source_map(app_reader).map(|(app_yml, _)| app_yml)
+}
+
+/// Load an app definition and keep track of where every key is located in it
+pub fn load_config_with_source_map<R>(mut app_reader: R) -> Result<(AppYmlFile, SourceMap), Error>
+where
+    R: std::io::Read,
+{
+    let mut app_yml_str = String::new();
+    app_reader
+        .read_to_string(&mut app_yml_str)
+        .map_err(|error| Error::Io(error.to_string()))?;
+    let app_yml = parse_config(&app_yml_str)?;
+    Ok((app_yml, SourceMap::parse(&app_yml_str)))
+}
+
+fn parse_config(app_yml_str: &str) -> Result<AppYmlFile, Error> {
+    let app_yml =
+        serde_yaml::from_str::<serde_yaml::Value>(app_yml_str).map_err(|error| Error::Parse {
+            message: error.to_string(),
+            location: error.location().map(Location::from),
+        })?;
     if !app_yml.is_mapping() {
-        return Err("App.yml is not a map!".to_string());
-    }
-    let version: u64;
-    if app_yml.get("citadel_version").is_none()
-        || !app_yml.get("citadel_version").unwrap().is_number()
-    {
-        if app_yml.get("version").is_some() && app_yml.get("version").unwrap().is_number() {
-            version = app_yml.get("version").unwrap().as_u64().unwrap();
-        } else {
-            return Err("Citadel file format is not set or not a number!".to_string());
-        }
-    } else {
-        version = app_yml.get("citadel_version").unwrap().as_u64().unwrap();
+        return Err(Error::NotAMap);
     }
+    let version = app_yml
+        .get("citadel_version")
+        .and_then(|version| version.as_u64())
+        .or_else(|| app_yml.get("version").and_then(|version| version.as_u64()))
+        .ok_or(Error::MissingVersion)?;
+    // Deserialize from the string again instead of the value so errors contain a location
+    let invalid_definition = |error: serde_yaml::Error| Error::InvalidDefinition {
+        version,
+        message: error.to_string(),
+        location: error.location().map(Location::from),
+    };
     match version {
-        3 => {
-            let app_definition: Result<AppYmlV3, serde_yaml::Error> =
-                serde_yaml::from_value(app_yml);
-            match app_definition {
-                Ok(app_definition) => Ok(AppYmlFile::V3(app_definition)),
-                Err(error) => Err(format!("Error loading app.yml as v3: {}", error)),
-            }
-        }
-        4 => {
-            let app_definition: Result<AppYmlV4, serde_yaml::Error> =
-                serde_yaml::from_value(app_yml);
-            match app_definition {
-                Ok(app_definition) => Ok(AppYmlFile::V4(app_definition)),
-                Err(error) => Err(format!("Error loading app.yml as v4: {}", error)),
-            }
-        }
-        _ => Err("Version not supported".to_string()),
+        3 => serde_yaml::from_str::<AppYmlV3>(app_yml_str)
+            .map(AppYmlFile::V3)
+            .map_err(invalid_definition),
+        4 => serde_yaml::from_str::<AppYmlV4>(app_yml_str)
+            .map(AppYmlFile::V4)
+            .map_err(invalid_definition),
+        _ => Err(Error::UnsupportedVersion(version)),
     }
 }
 
+/// Convert an app definition, stopping at the first error
+/// Returns the result and the warnings found while converting it
 pub fn convert_config<R>(
     app_name: &str,
     app_reader: R,
     port_map: &Option<Map<String, Value>>,
     installed_services: &Option<Vec<String>>,
-) -> Result<ResultYml, String>
+    resource_policy: &Option<ResourcePolicy>,
+    permission_registry: &Option<PermissionRegistry>,
+) -> Result<(ResultYml, Vec<Diagnostic>), Error>
 where
     R: std::io::Read,
 {
-    let app_yml = load_config(app_reader).expect("Failed to parse app.yml");
+    let app_yml = load_config(app_reader)?;
     match app_yml {
-        AppYmlFile::V4(app_definition) => {
-            v4::convert::convert_config(app_name, app_definition, port_map, installed_services)
-        }
+        AppYmlFile::V4(app_definition) => v4::convert::convert_config(
+            app_name,
+            app_definition,
+            port_map,
+            installed_services,
+            resource_policy,
+            permission_registry,
+        ),
         AppYmlFile::V3(app_definition) => {
             if let Some(installed_services) = installed_services {
-                v3::convert::convert_config(app_name, app_definition, port_map, installed_services)
+                v3::convert::convert_config(
+                    app_name,
+                    app_definition,
+                    port_map,
+                    installed_services,
+                    resource_policy,
+                    permission_registry,
+                )
             } else {
-                Err("No installed services defined. If you are trying to validate an app, please make sure it is an app.yml v4 or later.".to_string())
+                Err(Error::MissingInstalledServices)
             }
         }
     }
 }
+
+/// Like convert_config, but returns every problem found instead of only the first one
+/// Diagnostics contain their location in the app definition if it is known
+/// If the node's env is given, env vars in the result are replaced with their values
+pub fn convert_config_with_diagnostics<R>(
+    app_name: &str,
+    app_reader: R,
+    port_map: &Option<Map<String, Value>>,
+    installed_services: &Option<Vec<String>>,
+    resource_policy: &Option<ResourcePolicy>,
+    permission_registry: &Option<PermissionRegistry>,
+    env: &Option<HashMap<String, String>>,
+) -> (Option<ResultYml>, Vec<Diagnostic>)
+where
+    R: std::io::Read,
+{
+    let (app_yml, source_map) = match load_config_with_source_map(app_reader) {
+        Ok(result) => result,
+        Err(error) => return (None, vec![Diagnostic::error("", error)]),
+    };
+    let mut diagnostics = Vec::<Diagnostic>::new();
+    let result = match app_yml {
+        AppYmlFile::V4(app_definition) => v4::convert::convert_with_diagnostics(
+            app_name,
+            app_definition,
+            port_map,
+            installed_services,
+            resource_policy,
+            permission_registry,
+            &mut diagnostics,
+        ),
+        AppYmlFile::V3(app_definition) => {
+            let result = match installed_services {
+                Some(installed_services) => v3::convert::convert_config(
+                    app_name,
+                    app_definition,
+                    port_map,
+                    installed_services,
+                    resource_policy,
+                    permission_registry,
+                ),
+                None => Err(Error::MissingInstalledServices),
+            };
+            match result {
+                Ok((result, warnings)) => {
+                    diagnostics.extend(warnings);
+                    Some(result)
+                }
+                Err(error) => {
+                    diagnostics.push(Diagnostic::error("", error));
+                    None
+                }
+            }
+        }
+    };
+    let result = match (result, env) {
+        (Some(mut result), Some(env)) => {
+            let unresolved = resolve_env_vars(&mut result.spec, env);
+            let is_resolved = unresolved.is_empty();
+            diagnostics.extend(unresolved);
+            is_resolved.then_some(result)
+        }
+        (result, _) => result,
+    };
+    for diagnostic in &mut diagnostics {
+        diagnostic.locate(&source_map);
+    }
+    (result, diagnostics)
+}
+
+/// Validate an app definition and return every problem found in it
+pub fn validate_config<R>(app_name: &str, app_reader: R) -> Vec<Diagnostic>
+where
+    R: std::io::Read,
+{
+    convert_config_with_diagnostics(app_name, app_reader, &None, &None, &None, &None, &None).1
+}
+
+#[cfg(test)]
+mod test {
+    use super::{load_config, validate_config};
+    use crate::error::{Error, Location};
+
+    #[test]
+    fn report_parse_errors_with_location() {
+        let result = load_config("citadel_version: 4\nmetadata: [\n".as_bytes());
+        match result {
+            Err(Error::Parse { location, .. }) => assert_eq!(location.unwrap().line, 3),
+            _ => panic!("Expected a parse error"),
+        }
+    }
+
+    #[test]
+    fn reject_non_maps() {
+        let result = load_config("- citadel_version: 4".as_bytes());
+        assert_eq!(result.err(), Some(Error::NotAMap));
+    }
+
+    #[test]
+    fn reject_missing_version() {
+        let result = load_config("metadata: {}".as_bytes());
+        assert_eq!(result.err(), Some(Error::MissingVersion));
+    }
+
+    #[test]
+    fn reject_unsupported_version() {
+        let result = load_config("citadel_version: 5".as_bytes());
+        assert_eq!(result.err(), Some(Error::UnsupportedVersion(5)));
+    }
+
+    #[test]
+    fn report_invalid_definitions_with_location() {
+        let result =
+            load_config("citadel_version: 4\nmetadata:\n  name: 1\nservices: {}\n".as_bytes());
+        match result {
+            Err(Error::InvalidDefinition {
+                version, location, ..
+            }) => {
+                assert_eq!(version, 4);
+                assert_eq!(location, Some(Location { line: 3, column: 3 }));
+            }
+            _ => panic!("Expected an invalid definition error"),
+        }
+    }
+
+    #[test]
+    fn locate_diagnostics() {
+        let app_yml = "citadel_version: 4
+metadata:
+  name: Example
+  version: 1.0.0
+  category: Example
+  tagline: Example
+  developers: {}
+  description: Example
+  repo: {}
+  support: Example
+services:
+  main:
+    image: example
+    environment:
+      LND: $LND_IP
+";
+        let diagnostics = validate_config("example-app", app_yml.as_bytes());
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].path, "services.main.environment.LND");
+        assert_eq!(
+            diagnostics[0].location,
+            Some(Location {
+                line: 15,
+                column: 7
+            })
+        );
+        assert_eq!(diagnostics[0].length, 3);
+    }
+}
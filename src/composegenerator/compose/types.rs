@@ -1,7 +1,7 @@
 #[cfg(feature = "schema")]
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 #[cfg_attr(feature = "schema", derive(JsonSchema))]
@@ -331,7 +331,7 @@ pub enum StringOrIntOrBool {
 #[serde(untagged)]
 pub enum EnvVars {
     List(Vec<String>),
-    Map(HashMap<String, StringOrIntOrBool>),
+    Map(BTreeMap<String, StringOrIntOrBool>),
 }
 
 #[derive(Clone, Default, Deserialize, Serialize, PartialEq, Debug)]
@@ -542,7 +542,7 @@ pub struct ComposeSpecification {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub secrets: Option<ComposeSpecificationConfigsSecrets>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub services: Option<HashMap<String, Service>>,
+    pub services: Option<BTreeMap<String, Service>>,
     #[doc = " declared for backward compatibility, ignored."]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub version: Option<String>,
@@ -1 +1,3 @@
+pub mod render;
+pub mod resolve;
 pub mod types;
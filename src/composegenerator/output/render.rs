@@ -0,0 +1,125 @@
+use std::collections::BTreeMap;
+
+use super::types::{ComposeSpecification, Network};
+use crate::composegenerator::types::ResultYml;
+
+/// The Docker network apps are connected to by default
+pub const DEFAULT_NETWORK_NAME: &str = "citadel_main_network";
+
+/// Settings of the node that are required to turn an app into a complete compose file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RenderOptions {
+    /// The name of the Docker network the app's default network refers to
+    pub network_name: String,
+    /// Env files every container loads, for example the node's .env
+    pub env_files: Vec<String>,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions {
+            network_name: DEFAULT_NETWORK_NAME.to_string(),
+            env_files: Vec::new(),
+        }
+    }
+}
+
+/// Get the name of the container of a service of an app
+pub fn container_name(app_name: &str, service_name: &str) -> String {
+    format!("{}_{}_1", app_name, service_name)
+}
+
+/// Turn the result of converting an app into a docker-compose.yml that can be started directly
+pub fn render_compose(
+    app_name: &str,
+    result: &ResultYml,
+    options: &RenderOptions,
+) -> ComposeSpecification {
+    let mut spec = result.spec.clone();
+    for (service_name, service) in spec.services.iter_mut().flatten() {
+        service.container_name = Some(container_name(app_name, service_name));
+        for env_file in &options.env_files {
+            if !service.env_file.contains(env_file) {
+                service.env_file.push(env_file.clone());
+            }
+        }
+        service
+            .labels
+            .insert("space.runcitadel.app.id".to_string(), app_name.to_string());
+        service.labels.insert(
+            "space.runcitadel.app.version".to_string(),
+            result.metadata.version.clone(),
+        );
+        service.labels.insert(
+            "space.runcitadel.app.service".to_string(),
+            service_name.clone(),
+        );
+    }
+    // The network is created by Citadel and shared by all apps, so apps must not create it.
+    // Its subnet is configured where it is created: compose does not allow ipam on an external
+    // network, and the IP addresses of apps are allocated from that subnet by the ip-addresses
+    // command
+    spec.networks.get_or_insert_with(BTreeMap::new).insert(
+        "default".to_string(),
+        Network {
+            name: Some(options.network_name.clone()),
+            external: Some(true),
+        },
+    );
+    spec
+}
+
+#[cfg(test)]
+mod test {
+    use super::{render_compose, RenderOptions};
+    use crate::bmap;
+    use crate::composegenerator::output::types::{ComposeSpecification, Network, Service};
+    use crate::composegenerator::tor::TorConfig;
+    use crate::composegenerator::types::{Metadata, ResultYml};
+
+    #[test]
+    fn render_full_compose_file() {
+        let result = ResultYml {
+            port: 3000,
+            new_tor_entries: TorConfig::default(),
+            spec: ComposeSpecification {
+                services: Some(bmap! {
+                    "main" => Service {
+                        image: Some("ghcr.io/runcitadel/example:main".to_string()),
+                        ..Default::default()
+                    }
+                }),
+                ..Default::default()
+            },
+            metadata: Metadata {
+                version: "1.0.0".to_string(),
+                ..Default::default()
+            },
+        };
+        let options = RenderOptions {
+            env_files: vec!["/home/citadel/.env".to_string()],
+            ..Default::default()
+        };
+        let spec = render_compose("example-app", &result, &options);
+        let main = &spec.services.as_ref().unwrap()["main"];
+        assert_eq!(main.container_name, Some("example-app_main_1".to_string()));
+        assert_eq!(main.env_file, vec!["/home/citadel/.env".to_string()]);
+        assert_eq!(
+            main.labels,
+            bmap! {
+                "space.runcitadel.app.id" => "example-app".to_string(),
+                "space.runcitadel.app.service" => "main".to_string(),
+                "space.runcitadel.app.version" => "1.0.0".to_string()
+            }
+        );
+        assert_eq!(
+            spec.networks,
+            Some(bmap! {
+                "default" => Network {
+                    name: Some("citadel_main_network".to_string()),
+                    external: Some(true),
+                }
+            })
+        );
+    }
+}
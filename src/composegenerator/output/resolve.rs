@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+
+use super::types::ComposeSpecification;
+use crate::composegenerator::compose::types::{Command, StringOrIntOrBool};
+use crate::diagnostics::{service_path, Diagnostic};
+use crate::error::Error;
+use crate::utils::substitute_env_vars;
+
+/// Replace every env var in a converted app with its value from the node's env
+///
+/// Env vars that are not set are reported as errors at the key in the app.yml they come from.
+/// Fields that are generated from other keys, like volumes, are reported at the key they are generated from.
+pub fn resolve_env_vars(
+    spec: &mut ComposeSpecification,
+    env: &HashMap<String, String>,
+) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::<Diagnostic>::new();
+    for (service_name, service) in spec.services.iter_mut().flatten() {
+        let mut resolve = |value: &mut String, key: &str| {
+            let (resolved, missing) = substitute_env_vars(value, env);
+            *value = resolved;
+            for env_var in missing {
+                diagnostics.push(Diagnostic::error(
+                    service_path(service_name, key),
+                    Error::UnresolvedEnvVar {
+                        env_var,
+                        service: service_name.clone(),
+                    },
+                ));
+            }
+        };
+        let commands = [
+            ("entrypoint", service.entrypoint.as_mut()),
+            ("command", service.command.as_mut()),
+            (
+                "healthcheck.test",
+                service
+                    .healthcheck
+                    .as_mut()
+                    .map(|healthcheck| &mut healthcheck.test),
+            ),
+        ];
+        for (key, command) in commands {
+            match command {
+                Some(Command::SimpleCommand(command)) => resolve(command, key),
+                Some(Command::ArrayCommand(command)) => {
+                    for (index, argument) in command.iter_mut().enumerate() {
+                        resolve(argument, &format!("{}.{}", key, index));
+                    }
+                }
+                None => {}
+            }
+        }
+        for (key, value) in service.environment.iter_mut().flatten() {
+            if let StringOrIntOrBool::String(value) = value {
+                resolve(value, &format!("environment.{}", key));
+            }
+        }
+        for (index, extra_host) in service.extra_hosts.iter_mut().flatten().enumerate() {
+            resolve(extra_host, &format!("extra_hosts.{}", index));
+        }
+        for (key, value) in [
+            ("image", &mut service.image),
+            ("user", &mut service.user),
+            ("working_dir", &mut service.working_dir),
+            ("network_mode", &mut service.network_mode),
+            ("", &mut service.hostname),
+        ] {
+            if let Some(value) = value {
+                resolve(value, key);
+            }
+        }
+        for network in service
+            .networks
+            .iter_mut()
+            .flat_map(|networks| networks.values_mut())
+        {
+            if let Some(ipv4_address) = &mut network.ipv4_address {
+                resolve(ipv4_address, "");
+            }
+        }
+        for port in &mut service.ports {
+            resolve(port, "");
+        }
+        for volume in &mut service.volumes {
+            resolve(volume, "mounts");
+        }
+    }
+    diagnostics
+}
+
+#[cfg(test)]
+mod test {
+    use super::resolve_env_vars;
+    use crate::composegenerator::compose::types::{Command, StringOrIntOrBool};
+    use crate::composegenerator::output::types::{ComposeSpecification, NetworkEntry, Service};
+    use crate::error::Error;
+    use crate::{bmap, map};
+
+    #[test]
+    fn resolve_and_report_env_vars() {
+        let mut spec = ComposeSpecification {
+            services: Some(bmap! {
+                "main" => Service {
+                    command: Some(Command::ArrayCommand(vec![
+                        "--bitcoin".to_string(),
+                        "$BITCOIN_IP".to_string(),
+                        "$MISSING".to_string(),
+                    ])),
+                    environment: Some(bmap! {
+                        "DATA" => StringOrIntOrBool::String("${APP_DATA_DIR}/data".to_string())
+                    }),
+                    networks: Some(bmap! {
+                        "default" => NetworkEntry {
+                            ipv4_address: Some("$APP_EXAMPLE_APP_MAIN_IP".to_string())
+                        }
+                    }),
+                    volumes: vec!["${BITCOIN_DATA_DIR}:/bitcoin".to_string()],
+                    ..Default::default()
+                }
+            }),
+            ..Default::default()
+        };
+        let env = map! {
+            "BITCOIN_IP" => "10.21.21.8".to_string(),
+            "APP_DATA_DIR" => "/app-data/example-app".to_string(),
+            "APP_EXAMPLE_APP_MAIN_IP" => "10.21.22.2".to_string(),
+            "BITCOIN_DATA_DIR" => "/bitcoin".to_string()
+        };
+        let diagnostics = resolve_env_vars(&mut spec, &env);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].path, "services.main.command.2");
+        assert_eq!(
+            diagnostics[0].error,
+            Error::UnresolvedEnvVar {
+                env_var: "MISSING".to_string(),
+                service: "main".to_string(),
+            }
+        );
+        let main = &spec.services.as_ref().unwrap()["main"];
+        assert_eq!(
+            main.command,
+            Some(Command::ArrayCommand(vec![
+                "--bitcoin".to_string(),
+                "10.21.21.8".to_string(),
+                "$MISSING".to_string(),
+            ]))
+        );
+        assert_eq!(
+            main.environment.as_ref().unwrap()["DATA"],
+            StringOrIntOrBool::String("/app-data/example-app/data".to_string())
+        );
+        assert_eq!(
+            main.networks.as_ref().unwrap()["default"].ipv4_address,
+            Some("10.21.22.2".to_string())
+        );
+        assert_eq!(main.volumes, vec!["/bitcoin:/bitcoin".to_string()]);
+    }
+
+    #[test]
+    fn escape_dollar_signs_in_values() {
+        let mut spec = ComposeSpecification {
+            services: Some(bmap! {
+                "main" => Service {
+                    environment: Some(bmap! {
+                        "RPC_AUTH" => StringOrIntOrBool::String("$BITCOIN_RPC_AUTH".to_string()),
+                        "PASSWORD" => StringOrIntOrBool::String("${MISSING:-a$$b}".to_string())
+                    }),
+                    ..Default::default()
+                }
+            }),
+            ..Default::default()
+        };
+        let env = map! {
+            "BITCOIN_RPC_AUTH" => "user:salt$hash".to_string()
+        };
+        assert!(resolve_env_vars(&mut spec, &env).is_empty());
+        let environment = spec.services.as_ref().unwrap()["main"]
+            .environment
+            .as_ref()
+            .unwrap();
+        assert_eq!(
+            environment["RPC_AUTH"],
+            StringOrIntOrBool::String("user:salt$$hash".to_string())
+        );
+        assert_eq!(
+            environment["PASSWORD"],
+            StringOrIntOrBool::String("a$$b".to_string())
+        );
+    }
+}
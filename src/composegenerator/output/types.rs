@@ -2,6 +2,7 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
+use std::fmt;
 use super::super::compose::types::{StringOrIntOrBool, Command};
 
 #[derive(Clone, Default, Deserialize, Serialize, PartialEq, Eq, Debug)]
@@ -11,6 +12,84 @@ pub struct NetworkEntry {
     pub ipv4_address: Option<String>,
 }
 
+/// A number of CPUs a container can use, like 0.5 or 2
+#[derive(Clone, Copy, Deserialize, Serialize, PartialEq, PartialOrd, Debug)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(try_from = "f64", into = "f64")]
+pub struct Cpus(f64);
+
+impl Cpus {
+    pub fn new(cpus: f64) -> Option<Self> {
+        (cpus.is_finite() && cpus > 0.0).then_some(Cpus(cpus))
+    }
+
+    pub fn get(&self) -> f64 {
+        self.0
+    }
+}
+
+// Cpus can never be NaN, so comparing them is always reflexive
+impl Eq for Cpus {}
+
+impl TryFrom<f64> for Cpus {
+    type Error = String;
+
+    fn try_from(cpus: f64) -> Result<Self, Self::Error> {
+        Cpus::new(cpus).ok_or_else(|| format!("{} is not a valid number of CPUs", cpus))
+    }
+}
+
+impl From<Cpus> for f64 {
+    fn from(cpus: Cpus) -> Self {
+        cpus.0
+    }
+}
+
+impl fmt::Display for Cpus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Clone, Deserialize, Serialize, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+pub struct Healthcheck {
+    /// The command to run, a string is run with the container's default shell
+    /// A list has to start with CMD or CMD-SHELL
+    pub test: Command,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interval: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_period: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retries: Option<u32>,
+}
+
+#[derive(Clone, Deserialize, Serialize, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum DependencyCondition {
+    ServiceStarted,
+    ServiceHealthy,
+}
+
+#[derive(Clone, Deserialize, Serialize, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+pub struct Dependency {
+    pub condition: DependencyCondition,
+}
+
+#[derive(Clone, Deserialize, Serialize, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(untagged)]
+pub enum DependsOn {
+    List(Vec<String>),
+    /// Used if the start of a service waits for another service to be healthy
+    Conditions(BTreeMap<String, Dependency>),
+}
+
 #[derive(Clone, Default, Deserialize, Serialize, PartialEq, Eq, Debug)]
 #[cfg_attr(feature = "schema", derive(JsonSchema))]
 #[serde(rename = "service")]
@@ -18,34 +97,58 @@ pub struct Service {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub cap_add: Option<Vec<String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub cap_drop: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub command: Option<Command>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub depends_on: Option<Vec<String>>,
+    pub container_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpus: Option<Cpus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub depends_on: Option<DependsOn>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub entrypoint: Option<Command>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub env_file: Vec<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub environment: Option<BTreeMap<String, StringOrIntOrBool>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub extra_hosts: Option<Vec<String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub healthcheck: Option<Healthcheck>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub hostname: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub image: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub init: Option<bool>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty", default)]
+    pub labels: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mem_limit: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mem_reservation: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub network_mode: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub networks: Option<BTreeMap<String, NetworkEntry>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pids_limit: Option<u32>,
     #[serde(skip_serializing_if = "Vec::is_empty", default)]
     pub ports: Vec<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub read_only: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub restart: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub security_opt: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub stop_grace_period: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub stop_signal: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub tmpfs: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub user: Option<String>,
     #[serde(skip_serializing_if = "Vec::is_empty", default)]
     pub volumes: Vec<String>,
@@ -53,10 +156,22 @@ pub struct Service {
     pub working_dir: Option<String>,
 }
 
+#[derive(Clone, Default, Deserialize, Serialize, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(rename = "network")]
+pub struct Network {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub external: Option<bool>,
+}
+
 #[derive(Clone, Default, Deserialize, Serialize, PartialEq, Eq, Debug)]
 #[cfg_attr(feature = "schema", derive(JsonSchema))]
 #[serde(rename = "Compose Specification")]
 pub struct ComposeSpecification {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub services: Option<BTreeMap<String, Service>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub networks: Option<BTreeMap<String, Network>>,
 }
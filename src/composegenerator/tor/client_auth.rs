@@ -0,0 +1,115 @@
+//! Keys for the client authorization of v3 onion services
+//!
+//! Tor uses x25519 keys for client authorization.
+
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::composegenerator::v4::utils::derive_key;
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// Clamp a private key like every x25519 implementation does before using it
+fn clamp(scalar: &[u8; 32]) -> [u8; 32] {
+    let mut clamped = *scalar;
+    clamped[0] &= 248;
+    clamped[31] = (clamped[31] & 127) | 64;
+    clamped
+}
+
+/// Get the x25519 public key for a private key
+pub fn x25519_public_key(private_key: &[u8; 32]) -> [u8; 32] {
+    PublicKey::from(&StaticSecret::from(*private_key)).to_bytes()
+}
+
+/// Encode data as base32 without padding, the format Tor uses for keys
+pub fn base32_encode(data: &[u8]) -> String {
+    let mut result = String::with_capacity((data.len() * 8).div_ceil(5));
+    let mut buffer = 0u16;
+    let mut bits = 0;
+    for byte in data {
+        buffer = (buffer << 8) | *byte as u16;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            result.push(BASE32_ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        result.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+    result
+}
+
+/// The keys a client needs to connect to an onion service that uses client authorization
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClientAuthKeys {
+    /// The base32 encoded private key, this is given to the client
+    pub private_key: String,
+    /// The base32 encoded public key, this is saved in the authorized_clients directory
+    pub public_key: String,
+}
+
+/// Derive the keys of an authorized client from the node's seed
+pub fn derive_client_auth_keys(seed: &str, identifier: &str) -> ClientAuthKeys {
+    let private_key = clamp(&derive_key(seed, identifier));
+    ClientAuthKeys {
+        private_key: base32_encode(&private_key),
+        public_key: base32_encode(&x25519_public_key(&private_key)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand_core::OsRng;
+    use x25519_dalek::{PublicKey, StaticSecret};
+
+    use super::{base32_encode, derive_client_auth_keys, x25519_public_key};
+
+    #[test]
+    fn x25519_test_vector() {
+        // From RFC 7748, section 6.1
+        let private_key: [u8; 32] =
+            hex::decode("77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a")
+                .unwrap()
+                .try_into()
+                .unwrap();
+        assert_eq!(
+            hex::encode(x25519_public_key(&private_key)),
+            "8520f0098930a754748b7ddcb43ef75a0dbf3a0d26381af4eba4a98eaa9b4e6a"
+        );
+    }
+
+    #[test]
+    fn agree_on_shared_secret() {
+        let private_key = StaticSecret::random_from_rng(OsRng).to_bytes();
+        let other = StaticSecret::random_from_rng(OsRng);
+        let public_key = PublicKey::from(x25519_public_key(&private_key));
+        assert_eq!(
+            StaticSecret::from(private_key)
+                .diffie_hellman(&PublicKey::from(&other))
+                .to_bytes(),
+            other.diffie_hellman(&public_key).to_bytes()
+        );
+    }
+
+    #[test]
+    fn encode_base32() {
+        assert_eq!(base32_encode(b"foobar"), "MZXW6YTBOI");
+        assert_eq!(base32_encode(&[0u8; 32]).len(), 52);
+    }
+
+    #[test]
+    fn derive_keys() {
+        let keys = derive_client_auth_keys("seed", "app-example-app-admin-client-phone");
+        assert_eq!(keys.private_key.len(), 52);
+        assert_eq!(keys.public_key.len(), 52);
+        assert_eq!(
+            keys,
+            derive_client_auth_keys("seed", "app-example-app-admin-client-phone")
+        );
+        assert_ne!(
+            keys,
+            derive_client_auth_keys("seed", "app-example-app-admin-client-laptop")
+        );
+    }
+}
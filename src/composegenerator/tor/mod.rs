@@ -0,0 +1,507 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use self::client_auth::{derive_client_auth_keys, ClientAuthKeys};
+use crate::composegenerator::v4::ip_addresses::IpAllocation;
+use crate::error::Error;
+
+pub mod client_auth;
+
+/// Tor ignores comments, so authorized clients are kept in the torrc as comments like this
+const AUTHORIZED_CLIENT_PREFIX: &str = "# AuthorizedClient ";
+
+/// Get the name of the placeholder for the IP address of a container, without the < and >
+pub fn ip_placeholder(app_name: &str, service_name: &str) -> String {
+    format!(
+        "app-{}-{}-ip",
+        app_name.to_lowercase().replace('_', "-"),
+        service_name.to_lowercase().replace('_', "-")
+    )
+}
+
+/// The host a hidden service port points to
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TorHost {
+    /// A placeholder like <app-example-app-main-ip> that is replaced with the IP address of a container
+    Placeholder(String),
+    /// An IP address or hostname
+    Address(String),
+}
+
+impl TorHost {
+    /// Get the placeholder for the IP address of a container
+    pub fn container(app_name: &str, service_name: &str) -> Self {
+        TorHost::Placeholder(ip_placeholder(app_name, service_name))
+    }
+}
+
+impl fmt::Display for TorHost {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TorHost::Placeholder(placeholder) => write!(f, "<{}>", placeholder),
+            TorHost::Address(address) => write!(f, "{}", address),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HiddenServicePort {
+    /// The port of the onion service
+    pub virtual_port: u16,
+    /// The host connections are forwarded to, Tor uses 127.0.0.1 if this is not set
+    pub host: Option<TorHost>,
+    pub target_port: u16,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HiddenService {
+    pub dir: String,
+    pub ports: Vec<HiddenServicePort>,
+    /// HiddenServiceVersion, only 3 is supported by current versions of Tor
+    pub version: Option<u8>,
+    pub allow_unknown_ports: Option<bool>,
+    pub max_streams: Option<u32>,
+    pub max_streams_close_circuit: Option<bool>,
+    pub num_introduction_points: Option<u8>,
+    /// HiddenServiceEnableIntroDoSDefense, only supported by v3 onion services
+    pub enable_intro_dos_defense: Option<bool>,
+    /// The names of the clients that are allowed to connect, if this is empty, client authorization is disabled
+    /// Their keys are saved in the authorized_clients directory of the hidden service
+    pub authorized_clients: BTreeSet<String>,
+}
+
+impl HiddenService {
+    pub fn new(dir: impl Into<String>) -> Self {
+        HiddenService {
+            dir: dir.into(),
+            ..Default::default()
+        }
+    }
+
+    /// The name of the hidden service directory, without /var/lib/tor/
+    pub fn name(&self) -> &str {
+        self.dir.rsplit('/').next().unwrap_or(&self.dir)
+    }
+
+    /// Derive the keys of an authorized client from the node's seed
+    pub fn client_auth_keys(&self, seed: &str, client: &str) -> ClientAuthKeys {
+        derive_client_auth_keys(seed, &format!("{}-client-{}", self.name(), client))
+    }
+
+    /// Get the files in the hidden service dir that authorize the clients of this hidden service
+    pub fn authorized_client_files(&self, seed: &str) -> Vec<(String, String)> {
+        self.authorized_clients
+            .iter()
+            .map(|client| {
+                (
+                    format!("{}/authorized_clients/{}.auth", self.dir, client),
+                    format!(
+                        "descriptor:x25519:{}\n",
+                        self.client_auth_keys(seed, client).public_key
+                    ),
+                )
+            })
+            .collect()
+    }
+}
+
+/// The part of a torrc that configures hidden services
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub struct TorConfig {
+    pub hidden_services: Vec<HiddenService>,
+    /// Lines that do not configure a hidden service, kept as they are
+    pub other_lines: Vec<String>,
+}
+
+fn format_bool(value: bool) -> &'static str {
+    if value {
+        "1"
+    } else {
+        "0"
+    }
+}
+
+impl fmt::Display for TorConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.other_lines {
+            writeln!(f, "{}", line)?;
+        }
+        for hidden_service in &self.hidden_services {
+            writeln!(f, "HiddenServiceDir {}", hidden_service.dir)?;
+            for client in &hidden_service.authorized_clients {
+                writeln!(f, "{}{}", AUTHORIZED_CLIENT_PREFIX, client)?;
+            }
+            if let Some(version) = hidden_service.version {
+                writeln!(f, "HiddenServiceVersion {}", version)?;
+            }
+            if let Some(allow_unknown_ports) = hidden_service.allow_unknown_ports {
+                writeln!(
+                    f,
+                    "HiddenServiceAllowUnknownPorts {}",
+                    format_bool(allow_unknown_ports)
+                )?;
+            }
+            if let Some(max_streams) = hidden_service.max_streams {
+                writeln!(f, "HiddenServiceMaxStreams {}", max_streams)?;
+            }
+            if let Some(close_circuit) = hidden_service.max_streams_close_circuit {
+                writeln!(
+                    f,
+                    "HiddenServiceMaxStreamsCloseCircuit {}",
+                    format_bool(close_circuit)
+                )?;
+            }
+            if let Some(introduction_points) = hidden_service.num_introduction_points {
+                writeln!(
+                    f,
+                    "HiddenServiceNumIntroductionPoints {}",
+                    introduction_points
+                )?;
+            }
+            if let Some(dos_defense) = hidden_service.enable_intro_dos_defense {
+                writeln!(
+                    f,
+                    "HiddenServiceEnableIntroDoSDefense {}",
+                    format_bool(dos_defense)
+                )?;
+            }
+            for port in &hidden_service.ports {
+                match &port.host {
+                    Some(host) => writeln!(
+                        f,
+                        "HiddenServicePort {} {}:{}",
+                        port.virtual_port, host, port.target_port
+                    )?,
+                    None => writeln!(
+                        f,
+                        "HiddenServicePort {} {}",
+                        port.virtual_port, port.target_port
+                    )?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl From<TorConfig> for String {
+    fn from(config: TorConfig) -> Self {
+        config.to_string()
+    }
+}
+
+impl TryFrom<String> for TorConfig {
+    type Error = Error;
+
+    fn try_from(config: String) -> Result<Self, Self::Error> {
+        TorConfig::parse(&config)
+    }
+}
+
+impl TorConfig {
+    /// Parse the hidden service configuration in a torrc
+    pub fn parse(config: &str) -> Result<TorConfig, Error> {
+        let mut result = TorConfig::default();
+        for (index, raw_line) in config.lines().enumerate() {
+            let invalid = |message: &str| Error::InvalidTorConfig {
+                line: index + 1,
+                message: message.to_string(),
+            };
+            let line = raw_line.trim();
+            if let Some(client) = line.strip_prefix(AUTHORIZED_CLIENT_PREFIX) {
+                result
+                    .hidden_services
+                    .last_mut()
+                    .ok_or_else(|| invalid("Authorized client before HiddenServiceDir"))?
+                    .authorized_clients
+                    .insert(client.trim().to_string());
+                continue;
+            }
+            let (keyword, value) = line
+                .split_once(char::is_whitespace)
+                .map_or((line, ""), |(keyword, value)| (keyword, value.trim()));
+            if !keyword.starts_with("HiddenService") {
+                if !line.is_empty() {
+                    result.other_lines.push(raw_line.to_string());
+                }
+                continue;
+            }
+            if keyword == "HiddenServiceDir" {
+                if value.is_empty() {
+                    return Err(invalid("HiddenServiceDir requires a directory"));
+                }
+                result.hidden_services.push(HiddenService::new(value));
+                continue;
+            }
+            let hidden_service = result
+                .hidden_services
+                .last_mut()
+                .ok_or_else(|| invalid(&format!("{} before HiddenServiceDir", keyword)))?;
+            let parse_number = |value: &str| {
+                value
+                    .parse::<u32>()
+                    .map_err(|_| invalid(&format!("Invalid value for {}: {}", keyword, value)))
+            };
+            let parse_small_number = |value: &str| {
+                u8::try_from(parse_number(value)?)
+                    .map_err(|_| invalid(&format!("Invalid value for {}: {}", keyword, value)))
+            };
+            let parse_bool = |value: &str| match value {
+                "0" => Ok(false),
+                "1" => Ok(true),
+                _ => Err(invalid(&format!(
+                    "Invalid value for {}: {}",
+                    keyword, value
+                ))),
+            };
+            match keyword {
+                "HiddenServicePort" => hidden_service.ports.push(parse_port(value, &invalid)?),
+                "HiddenServiceVersion" => hidden_service.version = Some(parse_small_number(value)?),
+                "HiddenServiceAllowUnknownPorts" => {
+                    hidden_service.allow_unknown_ports = Some(parse_bool(value)?)
+                }
+                "HiddenServiceMaxStreams" => {
+                    hidden_service.max_streams = Some(parse_number(value)?)
+                }
+                "HiddenServiceMaxStreamsCloseCircuit" => {
+                    hidden_service.max_streams_close_circuit = Some(parse_bool(value)?)
+                }
+                "HiddenServiceNumIntroductionPoints" => {
+                    hidden_service.num_introduction_points = Some(parse_small_number(value)?)
+                }
+                "HiddenServiceEnableIntroDoSDefense" => {
+                    hidden_service.enable_intro_dos_defense = Some(parse_bool(value)?)
+                }
+                _ => return Err(invalid(&format!("Unsupported option {}", keyword))),
+            }
+        }
+        Ok(result)
+    }
+
+    /// Add the hidden services and other lines of another config to this one
+    pub fn merge(&mut self, other: TorConfig) {
+        self.hidden_services.extend(other.hidden_services);
+        self.other_lines.extend(other.other_lines);
+    }
+
+    /// Replace the IP address placeholders with the addresses of the containers
+    pub fn resolve_placeholders(&mut self, allocation: &IpAllocation) -> Result<(), Error> {
+        let addresses: HashMap<String, String> = allocation
+            .iter()
+            .flat_map(|(app_name, services)| {
+                services.iter().map(move |(service_name, address)| {
+                    (ip_placeholder(app_name, service_name), address.to_string())
+                })
+            })
+            .collect();
+        for port in self
+            .hidden_services
+            .iter_mut()
+            .flat_map(|hidden_service| hidden_service.ports.iter_mut())
+        {
+            if let Some(TorHost::Placeholder(placeholder)) = &port.host {
+                let address = addresses
+                    .get(placeholder)
+                    .ok_or_else(|| Error::UnresolvedTorPlaceholder(placeholder.clone()))?;
+                port.host = Some(TorHost::Address(address.clone()));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_port(value: &str, invalid: &dyn Fn(&str) -> Error) -> Result<HiddenServicePort, Error> {
+    let invalid_port = || invalid(&format!("Invalid HiddenServicePort: {}", value));
+    let mut parts = value.split_whitespace();
+    let virtual_port = parts
+        .next()
+        .and_then(|port| port.parse::<u16>().ok())
+        .ok_or_else(invalid_port)?;
+    let (host, target_port) = match parts.next() {
+        None => (None, virtual_port),
+        Some(target) => match target.rsplit_once(':') {
+            Some(("unix", _)) => return Err(invalid("Unix sockets are not supported")),
+            Some((host, port)) => {
+                let host = match host
+                    .strip_prefix('<')
+                    .and_then(|host| host.strip_suffix('>'))
+                {
+                    Some(placeholder) => TorHost::Placeholder(placeholder.to_string()),
+                    None => TorHost::Address(host.to_string()),
+                };
+                (Some(host), port.parse::<u16>().map_err(|_| invalid_port())?)
+            }
+            None => (None, target.parse::<u16>().map_err(|_| invalid_port())?),
+        },
+    };
+    if parts.next().is_some() {
+        return Err(invalid_port());
+    }
+    Ok(HiddenServicePort {
+        virtual_port,
+        host,
+        target_port,
+    })
+}
+
+/// Merge the Tor configs of multiple apps into one, failing if two hidden services use the same directory
+pub fn merge_tor_configs(configs: &BTreeMap<String, TorConfig>) -> Result<TorConfig, Error> {
+    let mut dirs = HashMap::<&str, &str>::new();
+    let mut result = TorConfig::default();
+    for (app_name, config) in configs {
+        for hidden_service in &config.hidden_services {
+            if let Some(other_app) = dirs.insert(&hidden_service.dir, app_name) {
+                return Err(Error::DuplicateHiddenServiceDir {
+                    dir: hidden_service.dir.clone(),
+                    apps: vec![other_app.to_string(), app_name.clone()],
+                });
+            }
+        }
+        result.merge(config.clone());
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+    use std::net::Ipv4Addr;
+
+    use super::{merge_tor_configs, HiddenService, HiddenServicePort, TorConfig, TorHost};
+    use crate::error::Error;
+
+    const TORRC: &str = "HiddenServiceDir /var/lib/tor/app-example-app
+HiddenServiceVersion 3
+HiddenServicePort 80 <app-example-app-main-ip>:3000
+HiddenServicePort 8333 10.21.21.8:8333
+HiddenServiceDir /var/lib/tor/app-example-app-rpc
+HiddenServicePort 9000 9001
+";
+
+    fn example_config() -> TorConfig {
+        TorConfig {
+            hidden_services: vec![
+                HiddenService {
+                    version: Some(3),
+                    ports: vec![
+                        HiddenServicePort {
+                            virtual_port: 80,
+                            host: Some(TorHost::container("example-app", "main")),
+                            target_port: 3000,
+                        },
+                        HiddenServicePort {
+                            virtual_port: 8333,
+                            host: Some(TorHost::Address("10.21.21.8".to_string())),
+                            target_port: 8333,
+                        },
+                    ],
+                    ..HiddenService::new("/var/lib/tor/app-example-app")
+                },
+                HiddenService {
+                    ports: vec![HiddenServicePort {
+                        virtual_port: 9000,
+                        host: None,
+                        target_port: 9001,
+                    }],
+                    ..HiddenService::new("/var/lib/tor/app-example-app-rpc")
+                },
+            ],
+            other_lines: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn parse_and_render() {
+        let config = TorConfig::parse(TORRC).unwrap();
+        assert_eq!(config, example_config());
+        assert_eq!(config.to_string(), TORRC);
+    }
+
+    #[test]
+    fn reject_invalid_configs() {
+        assert_eq!(
+            TorConfig::parse("HiddenServicePort 80 127.0.0.1:80"),
+            Err(Error::InvalidTorConfig {
+                line: 1,
+                message: "HiddenServicePort before HiddenServiceDir".to_string()
+            })
+        );
+        assert!(TorConfig::parse("HiddenServiceDir /a\nHiddenServicePort 80 host:http").is_err());
+        assert_eq!(
+            TorConfig::parse("HiddenServiceDir /a\nHiddenServiceVersion 259"),
+            Err(Error::InvalidTorConfig {
+                line: 2,
+                message: "Invalid value for HiddenServiceVersion: 259".to_string()
+            })
+        );
+        assert!(
+            TorConfig::parse("HiddenServiceDir /a\nHiddenServiceNumIntroductionPoints 300")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn detect_duplicate_dirs() {
+        let configs = BTreeMap::from([
+            ("example-app".to_string(), example_config()),
+            ("other-app".to_string(), example_config()),
+        ]);
+        assert_eq!(
+            merge_tor_configs(&configs),
+            Err(Error::DuplicateHiddenServiceDir {
+                dir: "/var/lib/tor/app-example-app".to_string(),
+                apps: vec!["example-app".to_string(), "other-app".to_string()],
+            })
+        );
+    }
+
+    #[test]
+    fn resolve_placeholders() {
+        let mut config = example_config();
+        let allocation = BTreeMap::from([(
+            "example-app".to_string(),
+            BTreeMap::from([("main".to_string(), Ipv4Addr::new(10, 21, 22, 2))]),
+        )]);
+        config.resolve_placeholders(&allocation).unwrap();
+        assert_eq!(
+            config.hidden_services[0].ports[0].host,
+            Some(TorHost::Address("10.21.22.2".to_string()))
+        );
+        assert_eq!(
+            TorConfig::parse(TORRC)
+                .unwrap()
+                .resolve_placeholders(&BTreeMap::new()),
+            Err(Error::UnresolvedTorPlaceholder(
+                "app-example-app-main-ip".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn authorized_clients() {
+        let torrc = "HiddenServiceDir /var/lib/tor/app-example-app-admin
+# AuthorizedClient laptop
+# AuthorizedClient phone
+HiddenServiceVersion 3
+HiddenServicePort 80 <app-example-app-web-ip>:8080
+";
+        let config = TorConfig::parse(torrc).unwrap();
+        assert_eq!(config.to_string(), torrc);
+        let hidden_service = &config.hidden_services[0];
+        let files = hidden_service.authorized_client_files("seed");
+        assert_eq!(
+            files[1].0,
+            "/var/lib/tor/app-example-app-admin/authorized_clients/phone.auth"
+        );
+        assert_eq!(
+            files[1].1,
+            format!(
+                "descriptor:x25519:{}\n",
+                hidden_service.client_auth_keys("seed", "phone").public_key
+            )
+        );
+    }
+}
@@ -1,9 +1,10 @@
 #[cfg(feature = "schema")]
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
 use crate::composegenerator::output::types::ComposeSpecification;
+use crate::composegenerator::tor::TorConfig;
 
 // General types also relevant for the output
 // Can be re-used by schemas
@@ -32,14 +33,14 @@ pub struct Metadata {
     /// A short tagline for the app
     pub tagline: String,
     // Developer name -> their website
-    pub developers: HashMap<String, String>,
+    pub developers: BTreeMap<String, String>,
     /// A description of the app
     pub description: String,
     #[serde(default)]
     /// Permissions the app requires
     pub permissions: Vec<Permissions>,
     /// App repository name -> repo URL
-    pub repo: HashMap<String, String>,
+    pub repo: BTreeMap<String, String>,
     /// A support link for the app
     pub support: String,
     /// A list of promo images for the apps
@@ -47,11 +48,17 @@ pub struct Metadata {
     /// The path the "Open" link on the dashboard should lead to
     #[serde(skip_serializing_if = "Option::is_none")]
     pub path: Option<String>,
+    /// The app's default username
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_username: Option<String>,
     /// The app's default password. Can also be $APP_SEED for a random password
     pub default_password: Option<String>,
     #[serde(default = "bool::default")]
     /// True if the app only works over Tor
     pub tor_only: bool,
+    /// What changed in this version of the app
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub release_notes: Option<String>,
     /// A list of containers to update automatically (still validated by the Citadel team)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub update_containers: Option<Vec<String>>,
@@ -72,7 +79,9 @@ pub struct Metadata {
 #[cfg_attr(feature = "schema", derive(JsonSchema))]
 pub struct ResultYml {
     pub port: u16,
-    pub new_tor_entries: String,
+    /// Serialized as the torrc lines for the app
+    #[cfg_attr(feature = "schema", schemars(with = "String"))]
+    pub new_tor_entries: TorConfig,
     pub spec: ComposeSpecification,
     pub metadata: Metadata,
 }
@@ -1,46 +1,93 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
-use crate::composegenerator::compose::types::{Command, EnvVars, StringOrIntOrBool};
+use crate::bmap;
+use crate::composegenerator::compose::types::{
+    Command, ComposeSpecification, EnvVars, Healthcheck as ComposeHealthcheck,
+    Service as ComposeService, StringOrIntOrBool,
+};
+use crate::composegenerator::output::types::Healthcheck;
 use crate::composegenerator::types::{Metadata as CitadelMetadata, Permissions};
+use crate::composegenerator::umbrel::mapping::{Mapping, MountTarget};
 use crate::composegenerator::umbrel::types::Metadata;
-use crate::composegenerator::v4::types::{AppYml, Container, Mounts};
-use crate::map;
+use crate::composegenerator::v4::types::{AppYml, Container, Mounts, PortsDefinition};
+use crate::diagnostics::{service_path, Diagnostic};
+use crate::error::Error;
+use crate::utils::{find_env_vars, rename_env_vars};
 
-pub fn convert_metadata(metadata: Metadata) -> CitadelMetadata {
+/// docker-compose.yml fields that are converted to an app.yml
+const CONVERTED_FIELDS: [&str; 18] = [
+    "cap_add",
+    "command",
+    "depends_on",
+    "entrypoint",
+    "environment",
+    "extra_hosts",
+    "healthcheck",
+    "image",
+    "init",
+    "network_mode",
+    "networks",
+    "ports",
+    "restart",
+    "stop_grace_period",
+    "stop_signal",
+    "user",
+    "volumes",
+    "working_dir",
+];
+
+/// docker-compose.yml fields that give a container access to the host, these are never imported
+const HOST_ACCESS_FIELDS: [&str; 12] = [
+    "cgroup_parent",
+    "device_cgroup_rules",
+    "devices",
+    "ipc",
+    "isolation",
+    "pid",
+    "privileged",
+    "runtime",
+    "security_opt",
+    "sysctls",
+    "userns_mode",
+    "volumes_from",
+];
+
+/// Capabilities an app can get with the network permission
+const NETWORK_CAPABILITIES: [&str; 2] = ["NET_ADMIN", "NET_RAW"];
+
+/// The newest umbrel-app.yml manifest version the importer knows
+const SUPPORTED_MANIFEST_VERSION: f64 = 1.1;
+
+pub fn convert_metadata(metadata: Metadata, mapping: &Mapping) -> CitadelMetadata {
     let deps: Vec<Permissions> = metadata
         .dependencies
-        .into_iter()
-        .map(|dep| -> Permissions {
-            match dep.as_str() {
-                "lightning" => Permissions::OneDependency("lnd".to_string()),
-                "bitcoin" => Permissions::OneDependency("bitcoind".to_string()),
-                "electrs" => Permissions::OneDependency("electrum".to_string()),
-                _ => Permissions::OneDependency(dep),
-            }
-        })
+        .iter()
+        .map(|dep| Permissions::OneDependency(mapping.citadel_dependency(dep)))
         .collect();
     CitadelMetadata {
         id: None,
         name: metadata.name,
         version: metadata.version,
-        repo: map! {
+        repo: bmap! {
             "Public" => metadata.repo
         },
         support: metadata.support,
         category: metadata.category,
         tagline: metadata.tagline,
         permissions: deps,
-        developers: map! {
+        developers: bmap! {
             metadata.developer => metadata.website
         },
         gallery: metadata.gallery,
         path: metadata.path,
+        default_username: metadata.default_username,
         default_password: if metadata.deterministic_password {
             Some("$APP_SEED".to_string())
         } else {
             metadata.default_password
         },
         tor_only: metadata.tor_only,
+        release_notes: metadata.release_notes,
         update_containers: None,
         description: metadata.description,
         implements: None,
@@ -51,34 +98,211 @@ pub fn convert_metadata(metadata: Metadata) -> CitadelMetadata {
     }
 }
 
-fn replace_env_vars(mut string: String) -> String {
-    if string.contains("APP_BITCOIN_NETWORK") {
-        string = string.replace("APP_BITCOIN_NETWORK", "BITCOIN_NETWORK");
+/// Report the parts of an umbrel-app.yml that can not be expressed in an app.yml
+/// hooks are the names of the scripts in the app's hooks directory
+pub fn check_metadata(metadata: &Metadata, hooks: &[String]) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    if metadata
+        .manifest_version
+        .as_f64()
+        .is_none_or(|version| version > SUPPORTED_MANIFEST_VERSION)
+    {
+        diagnostics.push(Diagnostic::warning(
+            "manifestVersion",
+            Error::UnsupportedUmbrelManifestVersion(metadata.manifest_version.to_string()),
+        ));
     }
-    if string.contains("APP_LIGHTNING_NODE_GRPC_PORT") {
-        string = string.replace("APP_LIGHTNING_NODE_GRPC_PORT", "LND_GRPC_PORT");
+    let dropped = |field: &str, reason: &str| Error::UmbrelFieldDropped {
+        field: field.to_string(),
+        reason: reason.to_string(),
+    };
+    for (field, is_set) in [
+        ("submitter", metadata.submitter.is_some()),
+        ("submission", metadata.submission.is_some()),
+    ] {
+        if is_set {
+            diagnostics.push(Diagnostic::warning(
+                field,
+                dropped(field, "Citadel apps do not record who submitted them"),
+            ));
+        }
+    }
+    if metadata.widgets.is_some() {
+        diagnostics.push(Diagnostic::warning(
+            "widgets",
+            dropped("widgets", "Citadel has no dashboard widgets"),
+        ));
+    }
+    if metadata.optimized_for_umbrel_home == Some(true) {
+        diagnostics.push(Diagnostic::warning(
+            "optimizedForUmbrelHome",
+            dropped(
+                "optimizedForUmbrelHome",
+                "it only applies to Umbrel hardware",
+            ),
+        ));
+    }
+    for hook in hooks {
+        diagnostics.push(Diagnostic::error(
+            "",
+            dropped(
+                &format!("hook {}", hook),
+                "hooks run on the host, apps can only run code in their containers",
+            ),
+        ));
+    }
+    diagnostics
+}
+
+/// Translate the Umbrel env vars in a value to Citadel env vars
+/// apps are the ID of the app and the Umbrel apps it depends on
+fn replace_env_vars(
+    value: &str,
+    path: &str,
+    service_name: &str,
+    apps: &[String],
+    mapping: &Mapping,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> String {
+    let mut replacements = HashMap::<String, String>::new();
+    for env_var in find_env_vars(value) {
+        match mapping.citadel_env_var(env_var, apps) {
+            Some(citadel_env_var) if citadel_env_var != env_var => {
+                replacements.insert(env_var.to_string(), citadel_env_var);
+            }
+            Some(_) => {}
+            None => diagnostics.push(Diagnostic::warning(
+                path,
+                Error::UnmappedEnvVar {
+                    env_var: env_var.to_string(),
+                    service: service_name.to_string(),
+                },
+            )),
+        }
     }
-    if string.contains("APP_LIGHTNING_NODE_REST_PORT") {
-        string = string.replace("APP_LIGHTNING_NODE_REST_PORT", "LND_REST_PORT");
+    rename_env_vars(value, &replacements)
+}
+
+fn replace_env_vars_in_cmd(
+    command: Command,
+    path: &str,
+    service_name: &str,
+    apps: &[String],
+    mapping: &Mapping,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Command {
+    let original = command.clone();
+    let result = match command {
+        Command::SimpleCommand(command) => Command::SimpleCommand(replace_env_vars(
+            &command,
+            path,
+            service_name,
+            apps,
+            mapping,
+            diagnostics,
+        )),
+        Command::ArrayCommand(values) => Command::ArrayCommand(
+            values
+                .iter()
+                .map(|argument| {
+                    replace_env_vars(argument, path, service_name, apps, mapping, diagnostics)
+                })
+                .collect(),
+        ),
+    };
+    if result != original {
+        diagnostics.push(Diagnostic::warning(
+            path,
+            Error::ComposeFieldTransformed {
+                field: path.rsplit('.').next().unwrap_or(path).to_string(),
+                service: service_name.to_string(),
+                message: "env vars were renamed to their Citadel equivalents".to_string(),
+            },
+        ));
     }
-    if string.contains("APP_LIGHTNING_NODE_IP") {
-        string = string.replace("APP_LIGHTNING_NODE_IP", "LND_IP");
+    result
+}
+
+/// Convert a healthcheck from a docker-compose.yml, healthchecks that are disabled or have no test are dropped
+fn convert_healthcheck(healthcheck: ComposeHealthcheck) -> Option<Healthcheck> {
+    if healthcheck.disable == Some(true) {
+        return None;
     }
-    if string.contains("APP_ELECTRS_NODE_IP") {
-        string = string.replace("APP_ELECTRS_NODE_IP", "ELECTRUM_IP");
+    Some(Healthcheck {
+        test: serde_json::from_value(healthcheck.test?).ok()?,
+        interval: healthcheck.interval,
+        timeout: healthcheck.timeout,
+        start_period: healthcheck.start_period,
+        retries: healthcheck.retries.map(|retries| retries as u32),
+    })
+}
+
+/// Parse a published port like "8080:80", "127.0.0.1:8080:80/udp" into its protocol, host port and container port
+fn parse_published_port(port: &str) -> Option<(&str, u16, u16)> {
+    let (ports, protocol) = port.split_once('/').unwrap_or((port, "tcp"));
+    let split = ports.split(':').collect::<Vec<&str>>();
+    if split.len() != 2 && split.len() != 3 {
+        return None;
     }
-    if string.contains("APP_ELECTRS_NODE_PORT") {
-        string = string.replace("APP_ELECTRS_NODE_PORT", "ELECTRUM_PORT");
+    let host_port = split[split.len() - 2].parse().ok()?;
+    let container_port = split[split.len() - 1].parse().ok()?;
+    Some((protocol, host_port, container_port))
+}
+
+/// Report every field of a service that is not converted to an app.yml
+fn check_dropped_fields(
+    service_name: &str,
+    service: &ComposeService,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let fields = match serde_json::to_value(service) {
+        Ok(serde_json::Value::Object(fields)) => fields,
+        _ => return,
+    };
+    for (field, value) in fields {
+        if CONVERTED_FIELDS.contains(&field.as_str()) || value == serde_json::Value::Bool(false) {
+            continue;
+        }
+        let path = service_path(service_name, &field);
+        if HOST_ACCESS_FIELDS.contains(&field.as_str()) {
+            diagnostics.push(Diagnostic::error(
+                path,
+                Error::ComposeFieldDropped {
+                    field,
+                    service: service_name.to_string(),
+                    reason: "it gives the container access to the host".to_string(),
+                },
+            ));
+        } else {
+            diagnostics.push(Diagnostic::warning(
+                path,
+                Error::ComposeFieldDropped {
+                    field,
+                    service: service_name.to_string(),
+                    reason: "it can not be expressed in an app.yml".to_string(),
+                },
+            ));
+        }
     }
-    string
 }
 
-pub fn convert_compose(
-    compose: crate::composegenerator::compose::types::ComposeSpecification,
+pub fn convert_compose(compose: ComposeSpecification, metadata: Metadata) -> AppYml {
+    convert_compose_with_diagnostics(compose, metadata, &Mapping::default(), &mut Vec::new())
+}
+
+/// Convert an Umbrel app and report every field of the docker-compose.yml that was dropped or changed
+/// Errors are reported for settings the app likely needs, but that can not be imported
+pub fn convert_compose_with_diagnostics(
+    compose: ComposeSpecification,
     metadata: Metadata,
+    mapping: &Mapping,
+    diagnostics: &mut Vec<Diagnostic>,
 ) -> AppYml {
+    let apps: Vec<String> = std::iter::once(metadata.id.clone())
+        .chain(metadata.dependencies.iter().cloned())
+        .collect();
     let services = compose.services.unwrap();
-    let mut result_services: HashMap<String, Container> = HashMap::new();
+    let mut result_services: BTreeMap<String, Container> = BTreeMap::new();
     let has_main = services.contains_key("main");
     let mut deps = Vec::<String>::new();
     for service in services {
@@ -88,6 +312,9 @@ pub fn convert_compose(
         if service_name == "app_proxy" {
             continue;
         }
+        // Diagnostics refer to the docker-compose.yml, so they use the original name
+        let compose_name = service_name.clone();
+        check_dropped_fields(&compose_name, &service_def, diagnostics);
         if service_name == "web" && !has_main {
             service_name = "main".to_string();
         }
@@ -95,19 +322,33 @@ pub fn convert_compose(
             bitcoin: None,
             lnd: None,
             c_lightning: None,
-            data: Some(HashMap::new()),
+            data: Some(BTreeMap::new()),
         });
-        for volume in service_def.volumes {
+        for (index, volume) in service_def.volumes.iter().enumerate() {
+            let volume_path_in_compose = service_path(&compose_name, &format!("volumes.{}", index));
+            let dropped_volume = |reason: &str| Error::ComposeFieldDropped {
+                field: format!("volume {}", volume),
+                service: compose_name.clone(),
+                reason: reason.to_string(),
+            };
             // Convert mounts using env vars to real mounts
             // For example, if a volume is "${APP_DATA_DIR}/thing:/data",
             // we add set "/thing" of the mounts.data hashmap to "/data"
             let split = volume.split(':').collect::<Vec<&str>>();
             if split.len() != 2 && split.len() != 3 {
+                diagnostics.push(Diagnostic::warning(
+                    volume_path_in_compose,
+                    dropped_volume("it is not in the format source:target"),
+                ));
                 continue;
             }
             let volume_name = split[0];
             let volume_path = split[1];
-            if volume_name.contains("${APP_DATA_DIR}") || volume_name.contains("$APP_DATA_DIR") {
+            let env_var = find_env_vars(volume_name)
+                .into_iter()
+                .next()
+                .filter(|_| volume_name.starts_with('$'));
+            if env_var == Some("APP_DATA_DIR") {
                 let volume_name_without_prefix = volume_name
                     .replace("${APP_DATA_DIR}", "")
                     .replace("$APP_DATA_DIR", "");
@@ -116,100 +357,215 @@ pub fn convert_compose(
                     volume_name_without_prefix.to_string(),
                     volume_path.to_string(),
                 );
-            } else if volume_name.contains("APP_LIGHTNING_NODE_DATA_DIR") {
-                mounts.as_mut().unwrap().lnd = Some(volume_path.to_string());
-            } else if volume_name.contains("APP_BITCOIN_DATA_DIR") {
-                mounts.as_mut().unwrap().bitcoin = Some(volume_path.to_string());
-            } else if volume_name.contains("APP_CORE_LIGHTNING_REST_CERT_DIR") {
-                mounts.as_mut().unwrap().c_lightning = Some(
-                    "Please set this yourself, I could not automatically check this.".to_string(),
-                );
+            } else if let Some(target) = env_var.and_then(|env_var| mapping.mounts.get(env_var)) {
+                let mounts = mounts.as_mut().unwrap();
+                let mount = match target {
+                    MountTarget::Bitcoin => &mut mounts.bitcoin,
+                    MountTarget::Lnd => &mut mounts.lnd,
+                    MountTarget::CLightning => &mut mounts.c_lightning,
+                };
+                *mount = Some(volume_path.to_string());
+                let env_var = env_var.unwrap();
+                let subdirectory = volume_name
+                    .trim_start_matches(&format!("${{{}}}", env_var))
+                    .trim_start_matches(&format!("${}", env_var));
+                if !subdirectory.is_empty() && subdirectory != "/" {
+                    diagnostics.push(Diagnostic::warning(
+                        volume_path_in_compose,
+                        Error::ComposeFieldTransformed {
+                            field: format!("volume {}", volume),
+                            service: compose_name.clone(),
+                            message: format!(
+                                "the whole directory is mounted instead of {}",
+                                subdirectory
+                            ),
+                        },
+                    ));
+                }
+            } else if let Some(env_var) = env_var {
+                diagnostics.push(Diagnostic::error(
+                    volume_path_in_compose,
+                    dropped_volume(&format!("there is no Citadel mount for {}", env_var)),
+                ));
+            } else if volume_name.starts_with(['/', '.', '~', '$']) {
+                diagnostics.push(Diagnostic::error(
+                    volume_path_in_compose,
+                    dropped_volume(
+                        "only the app's data directory and the node's data can be mounted",
+                    ),
+                ));
+            } else {
+                diagnostics.push(Diagnostic::warning(
+                    volume_path_in_compose,
+                    dropped_volume("named volumes are not supported, use a data directory instead"),
+                ));
             }
         }
-        // Loop through environment (a Option<hashmap>) and in all values, make these replacements
-        // APP_BITCOIN_NETWORK -> BITCOIN_NETWORK
-        // APP_PASSWORD -> APP_SEED
-        // APP_LIGHTNING_NODE_GRPC_PORT -> LND_GRPC_PORT
-        // APP_LIGHTNING_NODE_REST_PORT -> LND_REST_PORT
-        // APP_LIGHTNING_NODE_IP -> LND_IP
-        let mut env: Option<HashMap<String, StringOrIntOrBool>> = Some(HashMap::new());
+        let mut env: Option<BTreeMap<String, StringOrIntOrBool>> = Some(BTreeMap::new());
         let original_env = match service_def.environment {
             Some(env) => match env {
                 EnvVars::List(list) => {
-                    let mut map = HashMap::<String, StringOrIntOrBool>::new();
-                    for val in list {
-                        let mut split = val.split('=');
-                        map.insert(
-                            split.next().expect("Env var invalid").to_string(),
-                            StringOrIntOrBool::String(split.next().expect("Env var invalid").to_string()),
-                        );
+                    let mut map = BTreeMap::<String, StringOrIntOrBool>::new();
+                    for (index, val) in list.into_iter().enumerate() {
+                        match val.split_once('=') {
+                            Some((key, value)) => {
+                                map.insert(
+                                    key.to_string(),
+                                    StringOrIntOrBool::String(value.to_string()),
+                                );
+                            }
+                            None => diagnostics.push(Diagnostic::warning(
+                                service_path(&compose_name, &format!("environment.{}", index)),
+                                Error::ComposeFieldDropped {
+                                    field: format!("environment variable {}", val),
+                                    service: compose_name.clone(),
+                                    reason: "it has no value and apps can not read the host's environment"
+                                        .to_string(),
+                                },
+                            )),
+                        }
                     }
                     map
                 }
                 EnvVars::Map(map) => map,
             },
-            None => HashMap::<String, StringOrIntOrBool>::new(),
+            None => BTreeMap::<String, StringOrIntOrBool>::new(),
         };
         for (key, value) in original_env {
+            let original_value = match &value {
+                StringOrIntOrBool::String(_) => Some(value.clone()),
+                _ => None,
+            };
             let new_value = match value {
-                StringOrIntOrBool::String(str) => {
-                    let mut new_value = replace_env_vars(str);
-                    // If the APP_PASSWORD is also used, there could be a conflict otherwise
-                    // For apps which don't use APP_PASSWORD, this can be reverted
-                    if new_value.contains("APP_SEED") {
-                        new_value = new_value.replace("APP_SEED", "APP_SEED_2");
-                    }
-                    if new_value.contains("APP_PASSWORD") {
-                        new_value = new_value.replace("APP_PASSWORD", "APP_SEED");
-                    }
-                    StringOrIntOrBool::String(new_value)
-                },
+                StringOrIntOrBool::String(value) => StringOrIntOrBool::String(replace_env_vars(
+                    &value,
+                    &service_path(&compose_name, &format!("environment.{}", key)),
+                    &compose_name,
+                    &apps,
+                    mapping,
+                    diagnostics,
+                )),
                 _ => value,
             };
+            if Some(&new_value) != original_value.as_ref() {
+                diagnostics.push(Diagnostic::warning(
+                    service_path(&compose_name, &format!("environment.{}", key)),
+                    Error::ComposeFieldTransformed {
+                        field: format!("environment variable {}", key),
+                        service: compose_name.clone(),
+                        message: "env vars were renamed to their Citadel equivalents".to_string(),
+                    },
+                ));
+            }
             env.as_mut()
                 .unwrap()
                 .insert(key, new_value);
         }
-        let mut new_cmd: Option<Command> = None;
-        if let Some(command) = service_def.command {
-            match command {
-                Command::SimpleCommand(mut command) => {
-                    command = replace_env_vars(command);
-                    if command.contains("APP_PASSWORD") {
-                        // If the APP_SEED is also used, use APP_SEED_2 instead so the seed and the password are different
-                        if command.contains("APP_SEED") {
-                            command = command.replace("APP_SEED", "APP_SEED_2");
-                        }
-                        command = command.replace("APP_PASSWORD", "APP_SEED");
-                    }
-                    new_cmd = Some(Command::SimpleCommand(command));
-                }
-                Command::ArrayCommand(values) => {
-                    let mut result = Vec::<String>::new();
-                    for mut argument in values {
-                        argument = replace_env_vars(argument);
-                        // If the APP_PASSWORD is also used, there could be a conflict otherwise
-                        // For apps which don't use APP_PASSWORD, this can be reverted
-                        if argument.contains("APP_SEED") {
-                            argument = argument.replace("APP_SEED", "APP_SEED_2");
-                        }
-                        if argument.contains("APP_PASSWORD") {
-                            argument = argument.replace("APP_PASSWORD", "APP_SEED");
-                        }
-                        result.push(argument);
-                    }
-                    new_cmd = Some(Command::ArrayCommand(result));
+        let entrypoint = service_def.entrypoint.map(|entrypoint| {
+            replace_env_vars_in_cmd(
+                entrypoint,
+                &service_path(&compose_name, "entrypoint"),
+                &compose_name,
+                &apps,
+                mapping,
+                diagnostics,
+            )
+        });
+        let command = service_def.command.map(|command| {
+            replace_env_vars_in_cmd(
+                command,
+                &service_path(&compose_name, "command"),
+                &compose_name,
+                &apps,
+                mapping,
+                diagnostics,
+            )
+        });
+        let cap_add = service_def.cap_add.map(|caps| {
+            let mut allowed = Vec::<String>::new();
+            for (index, cap) in caps.into_iter().enumerate() {
+                let name = cap.to_uppercase();
+                let name = name.strip_prefix("CAP_").unwrap_or(&name);
+                if NETWORK_CAPABILITIES.contains(&name) {
+                    // Citadel spells capabilities like "cap-net-admin"
+                    allowed.push(format!("cap-{}", name.to_lowercase().replace('_', "-")));
+                    deps.push("network".to_string());
+                } else {
+                    diagnostics.push(Diagnostic::error(
+                        service_path(&compose_name, &format!("cap_add.{}", index)),
+                        Error::ComposeFieldDropped {
+                            field: format!("capability {}", cap),
+                            service: compose_name.clone(),
+                            reason: "apps can only add NET_ADMIN and NET_RAW".to_string(),
+                        },
+                    ));
                 }
-            };
-        }
-        if let Some(caps) = &service_def.cap_add {
-            if caps.contains(&"CAP_NET_ADMIN".to_string()) || caps.contains(&"CAP_NET_RAW".to_string()) {
-                deps.push("network".to_string());
             }
+            allowed
+        });
+        let healthcheck_is_enabled = service_def
+            .healthcheck
+            .as_ref()
+            .is_some_and(|healthcheck| healthcheck.disable != Some(true));
+        let mut healthcheck = service_def.healthcheck.and_then(convert_healthcheck);
+        if let Some(healthcheck) = healthcheck.as_mut() {
+            healthcheck.test = replace_env_vars_in_cmd(
+                healthcheck.test.clone(),
+                &service_path(&compose_name, "healthcheck.test"),
+                &compose_name,
+                &apps,
+                mapping,
+                diagnostics,
+            );
+        }
+        if healthcheck_is_enabled && healthcheck.is_none() {
+            diagnostics.push(Diagnostic::warning(
+                service_path(&compose_name, "healthcheck"),
+                Error::ComposeFieldDropped {
+                    field: "healthcheck".to_string(),
+                    service: compose_name.clone(),
+                    reason: "its test is missing or invalid".to_string(),
+                },
+            ));
+        }
+        if service_def.networks.is_none() {
+            diagnostics.push(Diagnostic::warning(
+                service_path(&compose_name, ""),
+                Error::ComposeFieldTransformed {
+                    field: "networks".to_string(),
+                    service: compose_name.clone(),
+                    message:
+                        "networking is disabled, because the service is not connected to a network"
+                            .to_string(),
+                },
+            ));
         }
         if service_def.network_mode.is_some() {
             deps.push("network".to_string());
         }
+        let mut required_ports: Option<PortsDefinition> = None;
+        for (index, port) in service_def.ports.iter().enumerate() {
+            let Some((protocol, host_port, container_port)) = parse_published_port(port) else {
+                diagnostics.push(Diagnostic::warning(
+                    service_path(&compose_name, &format!("ports.{}", index)),
+                    Error::ComposeFieldDropped {
+                        field: format!("port {}", port),
+                        service: compose_name.clone(),
+                        reason: "only single ports published on a fixed host port can be imported"
+                            .to_string(),
+                    },
+                ));
+                continue;
+            };
+            let ports = required_ports.get_or_insert_with(PortsDefinition::default);
+            let ports = match protocol {
+                "udp" => &mut ports.udp,
+                _ => &mut ports.tcp,
+            };
+            ports
+                .get_or_insert_with(BTreeMap::new)
+                .insert(host_port, container_port);
+        }
         let new_service = Container {
             image: service_def.image.unwrap(),
             user: service_def.user,
@@ -220,9 +576,11 @@ pub fn convert_compose(
             restart: service_def.restart,
             init: service_def.init,
             extra_hosts: service_def.extra_hosts,
-            entrypoint: service_def.entrypoint,
-            working_dir: None,
-            command: new_cmd,
+            entrypoint,
+            working_dir: service_def.working_dir,
+            healthcheck,
+            resources: None,
+            command,
             environment: env,
             port: if service_name == "main" || service_name == "web" {
                 Some(metadata.port)
@@ -230,7 +588,7 @@ pub fn convert_compose(
                 None
             },
             port_priority: None,
-            required_ports: None,
+            required_ports,
             mounts,
             enable_networking: if service_def.networks.is_some() {
                 None
@@ -238,13 +596,373 @@ pub fn convert_compose(
                 Some(false)
             },
             hidden_services: None,
-            cap_add: service_def.cap_add,
+            cap_add,
+            security: None,
         };
         result_services.insert(service_name, new_service);
     }
+    let mut metadata = convert_metadata(metadata, mapping);
+    for dep in deps {
+        let dep = Permissions::OneDependency(dep);
+        if !metadata.permissions.contains(&dep) {
+            metadata.permissions.push(dep);
+        }
+    }
     AppYml {
         citadel_version: 4,
-        metadata: convert_metadata(metadata),
+        metadata,
         services: result_services,
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use super::{check_metadata, convert_compose_with_diagnostics, convert_metadata};
+    use crate::bmap;
+    use crate::composegenerator::compose::types::StringOrIntOrBool;
+    use crate::composegenerator::types::Permissions;
+    use crate::composegenerator::umbrel::mapping::Mapping;
+    use crate::composegenerator::umbrel::types::Metadata;
+    use crate::composegenerator::v4::types::PortsDefinition;
+    use crate::diagnostics::Diagnostic;
+    use crate::error::Error;
+
+    #[test]
+    fn report_dropped_fields() {
+        let metadata: Metadata = serde_yaml::from_str(
+            "manifestVersion: 1
+id: example
+name: Example
+version: 1.0.0
+category: Example category
+tagline: Example
+developer: Example developer
+website: https://example.com
+repo: https://github.com/example/example
+support: https://example.com/support
+port: 3000
+description: Example
+",
+        )
+        .unwrap();
+        let compose = serde_yaml::from_str(
+            "services:
+  web:
+    image: example:1.0.0
+    privileged: true
+    cap_add:
+      - SYS_ADMIN
+    volumes:
+      - ${APP_DATA_DIR}/data:/data
+      - /var/run/docker.sock:/var/run/docker.sock
+    networks:
+      default:
+        ipv4_address: $APP_EXAMPLE_WEB_IP
+",
+        )
+        .unwrap();
+        let mut diagnostics = Vec::new();
+        let result = convert_compose_with_diagnostics(
+            compose,
+            metadata,
+            &Mapping::default(),
+            &mut diagnostics,
+        );
+        let main = &result.services["main"];
+        assert_eq!(main.cap_add, Some(vec![]));
+        assert_eq!(
+            main.mounts.as_ref().unwrap().data.as_ref().unwrap()["data"],
+            "/data"
+        );
+        assert_eq!(
+            diagnostics,
+            vec![
+                Diagnostic::error(
+                    "services.web.privileged",
+                    Error::ComposeFieldDropped {
+                        field: "privileged".to_string(),
+                        service: "web".to_string(),
+                        reason: "it gives the container access to the host".to_string(),
+                    }
+                ),
+                Diagnostic::error(
+                    "services.web.volumes.1",
+                    Error::ComposeFieldDropped {
+                        field: "volume /var/run/docker.sock:/var/run/docker.sock".to_string(),
+                        service: "web".to_string(),
+                        reason: "only the app's data directory and the node's data can be mounted"
+                            .to_string(),
+                    }
+                ),
+                Diagnostic::error(
+                    "services.web.cap_add.0",
+                    Error::ComposeFieldDropped {
+                        field: "capability SYS_ADMIN".to_string(),
+                        service: "web".to_string(),
+                        reason: "apps can only add NET_ADMIN and NET_RAW".to_string(),
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn convert_caps_ports_and_env_list() {
+        let metadata: Metadata = serde_yaml::from_str(
+            "manifestVersion: 1
+id: example
+name: Example
+version: 1.0.0
+category: Example category
+tagline: Example
+developer: Example developer
+website: https://example.com
+repo: https://github.com/example/example
+support: https://example.com/support
+port: 3000
+description: Example
+",
+        )
+        .unwrap();
+        let compose = serde_yaml::from_str(
+            "services:
+  web:
+    image: example:1.0.0
+    cap_add:
+      - NET_ADMIN
+      - CAP_NET_RAW
+    ports:
+      - 9735:9735
+      - 127.0.0.1:51820:51820/udp
+      - 8080
+    environment:
+      - TOKEN=abc=def
+      - FROM_HOST
+    networks:
+      default:
+        ipv4_address: $APP_EXAMPLE_WEB_IP
+",
+        )
+        .unwrap();
+        let mut diagnostics = Vec::new();
+        let result = convert_compose_with_diagnostics(
+            compose,
+            metadata,
+            &Mapping::default(),
+            &mut diagnostics,
+        );
+        let main = &result.services["main"];
+        assert_eq!(
+            main.cap_add,
+            Some(vec!["cap-net-admin".to_string(), "cap-net-raw".to_string()])
+        );
+        assert_eq!(
+            main.required_ports,
+            Some(PortsDefinition {
+                tcp: Some(BTreeMap::from([(9735, 9735)])),
+                udp: Some(BTreeMap::from([(51820, 51820)])),
+            })
+        );
+        assert_eq!(
+            main.environment,
+            Some(bmap! {
+                "TOKEN" => StringOrIntOrBool::String("abc=def".to_string())
+            })
+        );
+        assert_eq!(
+            result.metadata.permissions,
+            vec![Permissions::OneDependency("network".to_string())]
+        );
+        assert_eq!(
+            diagnostics,
+            vec![
+                Diagnostic::warning(
+                    "services.web.environment.1",
+                    Error::ComposeFieldDropped {
+                        field: "environment variable FROM_HOST".to_string(),
+                        service: "web".to_string(),
+                        reason: "it has no value and apps can not read the host's environment"
+                            .to_string(),
+                    }
+                ),
+                Diagnostic::warning(
+                    "services.web.ports.2",
+                    Error::ComposeFieldDropped {
+                        field: "port 8080".to_string(),
+                        service: "web".to_string(),
+                        reason: "only single ports published on a fixed host port can be imported"
+                            .to_string(),
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn map_env_vars() {
+        let metadata: Metadata = serde_yaml::from_str(
+            "manifestVersion: 1
+id: example
+name: Example
+version: 1.0.0
+category: Example category
+tagline: Example
+developer: Example developer
+website: https://example.com
+dependencies:
+  - core-lightning
+  - other-app
+repo: https://github.com/example/example
+support: https://example.com/support
+port: 3000
+description: Example
+",
+        )
+        .unwrap();
+        let compose = serde_yaml::from_str(
+            "services:
+  web:
+    image: example:1.0.0
+    environment:
+      LIGHTNING_HOST: $APP_CORE_LIGHTNING_DAEMON_IP
+      OTHER_APP: http://${APP_OTHER_APP_WEB_IP}:3000
+      TOR_PROXY: $TOR_PROXY_IP
+      UNKNOWN: $APP_UNKNOWN_IP
+    volumes:
+      - ${APP_CORE_LIGHTNING_DATA_DIR}:/c-lightning
+    networks:
+      default:
+        ipv4_address: $APP_EXAMPLE_WEB_IP
+",
+        )
+        .unwrap();
+        let mut diagnostics = Vec::new();
+        let result = convert_compose_with_diagnostics(
+            compose,
+            metadata,
+            &Mapping::default(),
+            &mut diagnostics,
+        );
+        let main = &result.services["main"];
+        assert_eq!(
+            main.environment,
+            Some(bmap! {
+                "LIGHTNING_HOST" => StringOrIntOrBool::String("$C_LIGHTNING_IP".to_string()),
+                "OTHER_APP" => StringOrIntOrBool::String("http://${APP_OTHER_APP_WEB_IP}:3000".to_string()),
+                "TOR_PROXY" => StringOrIntOrBool::String("$TOR_PROXY_IP".to_string()),
+                "UNKNOWN" => StringOrIntOrBool::String("$APP_UNKNOWN_IP".to_string())
+            })
+        );
+        assert_eq!(
+            main.mounts.as_ref().unwrap().c_lightning,
+            Some("/c-lightning".to_string())
+        );
+        assert!(diagnostics.contains(&Diagnostic::warning(
+            "services.web.environment.UNKNOWN",
+            Error::UnmappedEnvVar {
+                env_var: "APP_UNKNOWN_IP".to_string(),
+                service: "web".to_string(),
+            }
+        )));
+        assert_eq!(
+            result.metadata.permissions,
+            vec![
+                Permissions::OneDependency("c-lightning".to_string()),
+                Permissions::OneDependency("other-app".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn manifest_version_1_1() {
+        let metadata: Metadata = serde_yaml::from_str(
+            "manifestVersion: 1.1
+id: example
+name: Example
+version: 1.0.0
+category: Example category
+tagline: Example
+developer: Example developer
+website: https://example.com
+submitter: Example submitter
+submission: https://github.com/getumbrel/umbrel-apps/pull/1
+repo: https://github.com/example/example
+support: https://example.com/support
+port: 3000
+defaultUsername: admin
+releaseNotes: Fixed a bug
+widgets:
+  - id: stats
+optimizedForUmbrelHome: false
+description: Example
+",
+        )
+        .unwrap();
+        assert_eq!(
+            check_metadata(&metadata, &["pre-start".to_string()]),
+            vec![
+                Diagnostic::warning(
+                    "submitter",
+                    Error::UmbrelFieldDropped {
+                        field: "submitter".to_string(),
+                        reason: "Citadel apps do not record who submitted them".to_string(),
+                    }
+                ),
+                Diagnostic::warning(
+                    "submission",
+                    Error::UmbrelFieldDropped {
+                        field: "submission".to_string(),
+                        reason: "Citadel apps do not record who submitted them".to_string(),
+                    }
+                ),
+                Diagnostic::warning(
+                    "widgets",
+                    Error::UmbrelFieldDropped {
+                        field: "widgets".to_string(),
+                        reason: "Citadel has no dashboard widgets".to_string(),
+                    }
+                ),
+                Diagnostic::error(
+                    "",
+                    Error::UmbrelFieldDropped {
+                        field: "hook pre-start".to_string(),
+                        reason: "hooks run on the host, apps can only run code in their containers"
+                            .to_string(),
+                    }
+                ),
+            ]
+        );
+        let result = convert_metadata(metadata, &Mapping::default());
+        assert_eq!(result.default_username, Some("admin".to_string()));
+        assert_eq!(result.release_notes, Some("Fixed a bug".to_string()));
+
+        let metadata = Metadata {
+            manifest_version: serde_json::Number::from(2),
+            ..serde_yaml::from_str::<Metadata>(
+                "manifestVersion: 1
+id: example
+name: Example
+version: 1.0.0
+category: Example category
+tagline: Example
+developer: Example developer
+website: https://example.com
+repo: https://github.com/example/example
+support: https://example.com/support
+port: 3000
+description: Example
+",
+            )
+            .unwrap()
+        };
+        assert_eq!(
+            check_metadata(&metadata, &[]),
+            vec![Diagnostic::warning(
+                "manifestVersion",
+                Error::UnsupportedUmbrelManifestVersion("2".to_string())
+            )]
+        );
+    }
+}
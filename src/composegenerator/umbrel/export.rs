@@ -0,0 +1,567 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde_json::json;
+
+use crate::composegenerator::compose::types::{
+    Command, ComposeSpecification, EnvVars, Healthcheck as ComposeHealthcheck,
+    Service as ComposeService, StringOrIntOrBool,
+};
+use crate::composegenerator::output::types::Healthcheck;
+use crate::composegenerator::types::{Metadata as CitadelMetadata, Permissions};
+use crate::composegenerator::umbrel::mapping::{Mapping, MountTarget};
+use crate::composegenerator::umbrel::types::Metadata;
+use crate::composegenerator::v4::types::{AppYml, Container, Mounts};
+use crate::composegenerator::v4::utils::{get_main_container, ip_env_var};
+use crate::diagnostics::{service_path, Diagnostic};
+use crate::error::Error;
+use crate::utils::{find_env_vars, rename_env_vars};
+
+/// Permissions that are not apps, so they can not be Umbrel dependencies
+const NON_APP_PERMISSIONS: [&str; 2] = ["network", "privileges"];
+
+/// An Umbrel app, consisting of its umbrel-app.yml and docker-compose.yml
+#[derive(Clone, Debug, PartialEq)]
+pub struct UmbrelApp {
+    pub metadata: Metadata,
+    pub compose: ComposeSpecification,
+}
+
+/// Translate the Citadel env vars in a value to Umbrel env vars
+/// apps are the ID of the app and the Umbrel apps it depends on, their own env vars are kept
+fn convert_env_vars(
+    value: &str,
+    path: &str,
+    service_name: &str,
+    apps: &[String],
+    mapping: &Mapping,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> String {
+    let mut renames = HashMap::<String, String>::new();
+    for env_var in find_env_vars(value) {
+        match mapping.umbrel_env_var(env_var) {
+            Some(umbrel_env_var) if umbrel_env_var != env_var => {
+                renames.insert(env_var.to_string(), umbrel_env_var);
+            }
+            Some(_) => {}
+            None if mapping.citadel_env_var(env_var, apps).as_deref() == Some(env_var) => {}
+            None => diagnostics.push(Diagnostic::warning(
+                path,
+                Error::NoUmbrelEquivalent {
+                    env_var: env_var.to_string(),
+                    service: service_name.to_string(),
+                },
+            )),
+        }
+    }
+    rename_env_vars(value, &renames)
+}
+
+fn convert_command(
+    command: Command,
+    path: &str,
+    service_name: &str,
+    apps: &[String],
+    mapping: &Mapping,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Command {
+    match command {
+        Command::SimpleCommand(command) => Command::SimpleCommand(convert_env_vars(
+            &command,
+            path,
+            service_name,
+            apps,
+            mapping,
+            diagnostics,
+        )),
+        Command::ArrayCommand(values) => Command::ArrayCommand(
+            values
+                .iter()
+                .map(|argument| {
+                    convert_env_vars(argument, path, service_name, apps, mapping, diagnostics)
+                })
+                .collect(),
+        ),
+    }
+}
+
+fn convert_healthcheck(
+    healthcheck: Healthcheck,
+    service_name: &str,
+    apps: &[String],
+    mapping: &Mapping,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> ComposeHealthcheck {
+    let test = convert_command(
+        healthcheck.test,
+        &service_path(service_name, "healthcheck.test"),
+        service_name,
+        apps,
+        mapping,
+        diagnostics,
+    );
+    ComposeHealthcheck {
+        disable: None,
+        test: serde_json::to_value(test).ok(),
+        interval: healthcheck.interval,
+        timeout: healthcheck.timeout,
+        start_period: healthcheck.start_period,
+        retries: healthcheck.retries.map(f64::from),
+    }
+}
+
+/// Convert mounts back to the volumes Umbrel uses for them
+fn convert_mounts(
+    service_name: &str,
+    mounts: Mounts,
+    mapping: &Mapping,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Vec<String> {
+    let mut volumes = Vec::<String>::new();
+    for (source, target) in mounts.data.unwrap_or_default() {
+        volumes.push(format!("${{APP_DATA_DIR}}/{}:{}", source, target));
+    }
+    for (mount_target, mount, target) in [
+        (MountTarget::Bitcoin, "bitcoin", mounts.bitcoin),
+        (MountTarget::Lnd, "lnd", mounts.lnd),
+        (MountTarget::CLightning, "c_lightning", mounts.c_lightning),
+    ] {
+        let target = match target {
+            Some(target) => target,
+            None => continue,
+        };
+        match mapping
+            .mounts
+            .iter()
+            .find(|(_, mapped_target)| **mapped_target == mount_target)
+        {
+            Some((env_var, _)) => volumes.push(format!("${{{}}}:{}", env_var, target)),
+            None => diagnostics.push(Diagnostic::warning(
+                service_path(service_name, &format!("mounts.{}", mount)),
+                Error::ComposeFieldDropped {
+                    field: format!("mounts.{}", mount),
+                    service: service_name.to_string(),
+                    reason: "the mapping has no Umbrel data directory for it".to_string(),
+                },
+            )),
+        }
+    }
+    volumes
+}
+
+pub fn convert_metadata(
+    app_id: &str,
+    metadata: CitadelMetadata,
+    port: u16,
+    mapping: &Mapping,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Metadata {
+    let mut dependencies = Vec::<String>::new();
+    for (index, permission) in metadata.permissions.into_iter().enumerate() {
+        // Umbrel has no alternative dependencies, so the first alternative is used
+        let dependency = match permission {
+            Permissions::OneDependency(dependency) => dependency,
+            Permissions::AlternativeDependency(mut alternatives) => {
+                if alternatives.is_empty() {
+                    continue;
+                }
+                let dependency = alternatives.remove(0);
+                if !alternatives.is_empty() {
+                    diagnostics.push(Diagnostic::warning(
+                        format!("metadata.permissions.{}", index),
+                        Error::CitadelFieldDropped {
+                            field: format!("alternative dependencies {}", alternatives.join(", ")),
+                            reason: format!(
+                                "Umbrel apps can not have alternative dependencies, so only {} is used",
+                                dependency
+                            ),
+                        },
+                    ));
+                }
+                dependency
+            }
+        };
+        if NON_APP_PERMISSIONS.contains(&dependency.as_str()) {
+            continue;
+        }
+        dependencies.push(mapping.umbrel_dependency(&dependency));
+    }
+    let (developer, website) = metadata.developers.into_iter().next().unwrap_or_default();
+    let repo = match metadata.repo.get("Public") {
+        Some(repo) => repo.to_owned(),
+        None => metadata.repo.into_values().next().unwrap_or_default(),
+    };
+    let deterministic_password = metadata.default_password.as_deref() == Some("$APP_SEED");
+    Metadata {
+        manifest_version: serde_json::Number::from(1),
+        id: app_id.to_string(),
+        name: metadata.name,
+        version: metadata.version,
+        category: metadata.category,
+        tagline: metadata.tagline,
+        developer,
+        website,
+        submitter: None,
+        submission: None,
+        dependencies,
+        repo,
+        support: metadata.support,
+        gallery: metadata.gallery,
+        path: metadata.path,
+        default_username: metadata.default_username,
+        default_password: if deterministic_password {
+            None
+        } else {
+            metadata.default_password
+        },
+        tor_only: metadata.tor_only,
+        port,
+        deterministic_password,
+        description: metadata.description,
+        release_notes: metadata.release_notes,
+        widgets: None,
+        optimized_for_umbrel_home: None,
+    }
+}
+
+fn convert_service(
+    app_id: &str,
+    service_name: &str,
+    container: Container,
+    apps: &[String],
+    mapping: &Mapping,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> ComposeService {
+    if container.hidden_services.is_some() {
+        diagnostics.push(Diagnostic::warning(
+            service_path(service_name, "hidden_services"),
+            Error::ComposeFieldDropped {
+                field: "hidden_services".to_string(),
+                service: service_name.to_string(),
+                reason: "Umbrel apps can not define hidden services".to_string(),
+            },
+        ));
+    }
+    let mut ports = Vec::<String>::new();
+    let required_ports = container.required_ports.unwrap_or_default();
+    for (host, target) in required_ports.tcp.unwrap_or_default() {
+        ports.push(format!("{}:{}", host, target));
+    }
+    for (host, target) in required_ports.udp.unwrap_or_default() {
+        ports.push(format!("{}:{}/udp", host, target));
+    }
+    let environment = container.environment.map(|environment| {
+        EnvVars::Map(
+            environment
+                .into_iter()
+                .map(|(key, value)| match value {
+                    StringOrIntOrBool::String(value) => {
+                        let value = convert_env_vars(
+                            &value,
+                            &service_path(service_name, &format!("environment.{}", key)),
+                            service_name,
+                            apps,
+                            mapping,
+                            diagnostics,
+                        );
+                        (key, StringOrIntOrBool::String(value))
+                    }
+                    _ => (key, value),
+                })
+                .collect(),
+        )
+    });
+    let healthcheck = container.healthcheck.map(|healthcheck| {
+        convert_healthcheck(healthcheck, service_name, apps, mapping, diagnostics)
+    });
+    let entrypoint = container.entrypoint.map(|entrypoint| {
+        convert_command(
+            entrypoint,
+            &service_path(service_name, "entrypoint"),
+            service_name,
+            apps,
+            mapping,
+            diagnostics,
+        )
+    });
+    let command = container.command.map(|command| {
+        convert_command(
+            command,
+            &service_path(service_name, "command"),
+            service_name,
+            apps,
+            mapping,
+            diagnostics,
+        )
+    });
+    let networks = if container.enable_networking == Some(false) {
+        None
+    } else {
+        Some(json!({
+            "default": {
+                "ipv4_address": format!("${}", ip_env_var(app_id, service_name)),
+            }
+        }))
+    };
+    let resources = container.resources.unwrap_or_default();
+    let security = container.security.unwrap_or_default();
+    ComposeService {
+        image: Some(container.image),
+        user: container.user,
+        stop_grace_period: container.stop_grace_period,
+        stop_signal: container.stop_signal,
+        depends_on: container.depends_on,
+        restart: container.restart,
+        init: container.init,
+        extra_hosts: container.extra_hosts,
+        working_dir: container.working_dir,
+        healthcheck,
+        entrypoint,
+        command,
+        environment,
+        cap_add: container.cap_add,
+        network_mode: container.network_mode,
+        networks,
+        ports,
+        volumes: container
+            .mounts
+            .map(|mounts| convert_mounts(service_name, mounts, mapping, diagnostics))
+            .unwrap_or_default(),
+        mem_limit: resources.memory_limit.map(serde_json::Value::from),
+        mem_reservation: resources.memory_reservation.map(serde_json::Value::from),
+        cpus: resources
+            .cpus
+            .map(|cpus| serde_json::Value::from(cpus.get())),
+        pids_limit: resources.pids_limit.map(serde_json::Value::from),
+        read_only: security.read_only,
+        tmpfs: (!security.tmpfs.is_empty()).then(|| serde_json::Value::from(security.tmpfs)),
+        ..Default::default()
+    }
+}
+
+/// Convert a Citadel app to an Umbrel app
+/// Settings that Umbrel does not support are reported as diagnostics
+/// Returns None if the app could not be converted because of an error
+pub fn convert_app(
+    app_id: &str,
+    app: AppYml,
+    mapping: &Mapping,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Option<UmbrelApp> {
+    let main_service = match get_main_container(&app) {
+        Ok(main_service) => main_service,
+        Err(error) => {
+            diagnostics.push(Diagnostic::error("services", error));
+            return None;
+        }
+    };
+    let port = app.services[&main_service].port.unwrap_or(3000);
+    let mut services = BTreeMap::<String, ComposeService>::new();
+    // Umbrel's app proxy makes the main port available on the dashboard
+    services.insert(
+        "app_proxy".to_string(),
+        ComposeService {
+            environment: Some(EnvVars::Map(BTreeMap::from([
+                (
+                    "APP_HOST".to_string(),
+                    StringOrIntOrBool::String(format!("{}_{}_1", app_id, main_service)),
+                ),
+                ("APP_PORT".to_string(), StringOrIntOrBool::Int(port as i64)),
+            ]))),
+            ..Default::default()
+        },
+    );
+    let metadata = convert_metadata(app_id, app.metadata, port, mapping, diagnostics);
+    let apps: Vec<String> = std::iter::once(app_id.to_string())
+        .chain(metadata.dependencies.iter().cloned())
+        .collect();
+    for (service_name, container) in app.services {
+        let service = convert_service(
+            app_id,
+            &service_name,
+            container,
+            &apps,
+            mapping,
+            diagnostics,
+        );
+        services.insert(service_name, service);
+    }
+    Some(UmbrelApp {
+        metadata,
+        compose: ComposeSpecification {
+            version: Some("3.7".to_string()),
+            services: Some(services),
+            ..Default::default()
+        },
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::convert_app;
+    use crate::bmap;
+    use crate::composegenerator::compose::types::{EnvVars, StringOrIntOrBool};
+    use crate::composegenerator::types::Permissions;
+    use crate::composegenerator::umbrel::convert::convert_compose;
+    use crate::composegenerator::umbrel::mapping::Mapping;
+    use crate::composegenerator::v4::types::AppYml;
+    use crate::diagnostics::Diagnostic;
+    use crate::error::Error;
+
+    #[test]
+    fn round_trip() {
+        let app: AppYml = serde_yaml::from_str(
+            "citadel_version: 4
+metadata:
+  name: Example app
+  version: 1.0.0
+  category: Example category
+  tagline: Example
+  developers:
+    Citadel team: runcitadel.space
+  description: Example
+  permissions:
+    - lnd
+    - network
+  repo:
+    Public: https://github.com/runcitadel/example
+  support: https://t.me/citadeldevelopers
+  defaultPassword: $APP_SEED
+services:
+  main:
+    image: ghcr.io/runcitadel/example:main
+    port: 3000
+    environment:
+      LND_HOST: $LND_IP
+      PASSWORD: ${APP_SEED}
+    mounts:
+      lnd: /lnd
+      data:
+        data: /data
+",
+        )
+        .unwrap();
+        let mut diagnostics = Vec::new();
+        let result = convert_app(
+            "example-app",
+            app.clone(),
+            &Mapping::default(),
+            &mut diagnostics,
+        )
+        .unwrap();
+        assert!(diagnostics.is_empty());
+        assert_eq!(result.metadata.dependencies, vec!["lightning".to_string()]);
+        assert!(result.metadata.deterministic_password);
+        assert_eq!(result.metadata.port, 3000);
+
+        let services = result.compose.services.clone().unwrap();
+        assert_eq!(
+            services["app_proxy"].environment,
+            Some(EnvVars::Map(bmap! {
+                "APP_HOST" => StringOrIntOrBool::String("example-app_main_1".to_string()),
+                "APP_PORT" => StringOrIntOrBool::Int(3000)
+            }))
+        );
+        assert_eq!(
+            services["main"].environment,
+            Some(EnvVars::Map(bmap! {
+                "LND_HOST" => StringOrIntOrBool::String("$APP_LIGHTNING_NODE_IP".to_string()),
+                "PASSWORD" => StringOrIntOrBool::String("${APP_PASSWORD}".to_string())
+            }))
+        );
+        assert_eq!(
+            services["main"].volumes,
+            vec![
+                "${APP_DATA_DIR}/data:/data".to_string(),
+                "${APP_LIGHTNING_NODE_DATA_DIR}:/lnd".to_string()
+            ]
+        );
+
+        let imported = convert_compose(result.compose, result.metadata);
+        assert_eq!(
+            imported.metadata.permissions,
+            vec![Permissions::OneDependency("lnd".to_string())]
+        );
+        assert_eq!(
+            imported.metadata.default_password,
+            app.metadata.default_password
+        );
+        let main = &imported.services["main"];
+        let original = &app.services["main"];
+        assert_eq!(main.image, original.image);
+        assert_eq!(main.port, original.port);
+        assert_eq!(
+            main.environment,
+            Some(bmap! {
+                "LND_HOST" => StringOrIntOrBool::String("$LND_IP".to_string()),
+                "PASSWORD" => StringOrIntOrBool::String("${APP_SEED}".to_string())
+            })
+        );
+        assert_eq!(main.mounts, original.mounts);
+    }
+
+    #[test]
+    fn report_what_can_not_be_exported() {
+        let app: AppYml = serde_yaml::from_str(
+            "citadel_version: 4
+metadata:
+  name: Example app
+  version: 1.0.0
+  category: Example category
+  tagline: Example
+  developers:
+    Citadel team: runcitadel.space
+  description: Example
+  permissions:
+    - [lnd, c-lightning]
+    - electrum
+  repo:
+    Public: https://github.com/runcitadel/example
+  support: https://t.me/citadeldevelopers
+services:
+  main:
+    image: ghcr.io/runcitadel/example:main
+    port: 3000
+    command: run --seed $APP_SEED_1
+    environment:
+      ELECTRUM: ${APP_ELECTRUM_IP}:50001
+      SELF: $APP_EXAMPLE_APP_MAIN_IP
+",
+        )
+        .unwrap();
+        let mut diagnostics = Vec::new();
+        let result =
+            convert_app("example-app", app, &Mapping::default(), &mut diagnostics).unwrap();
+        assert_eq!(
+            result.metadata.dependencies,
+            vec!["lightning".to_string(), "electrs".to_string()]
+        );
+        assert_eq!(
+            diagnostics,
+            vec![
+                Diagnostic::warning(
+                    "metadata.permissions.0",
+                    Error::CitadelFieldDropped {
+                        field: "alternative dependencies c-lightning".to_string(),
+                        reason:
+                            "Umbrel apps can not have alternative dependencies, so only lnd is used"
+                                .to_string(),
+                    }
+                ),
+                Diagnostic::warning(
+                    "services.main.environment.ELECTRUM",
+                    Error::NoUmbrelEquivalent {
+                        env_var: "APP_ELECTRUM_IP".to_string(),
+                        service: "main".to_string(),
+                    }
+                ),
+                Diagnostic::warning(
+                    "services.main.command",
+                    Error::NoUmbrelEquivalent {
+                        env_var: "APP_SEED_1".to_string(),
+                        service: "main".to_string(),
+                    }
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,165 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+
+/// The mapping that is used if no other mapping is given
+const DEFAULT_MAPPING: &str = include_str!("mapping.yml");
+
+/// A rule to translate an Umbrel env var to a Citadel env var
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct EnvVarRule {
+    /// The Umbrel env var, it can contain {app} and end with *
+    pub umbrel: String,
+    /// The Citadel env var, {app} and * are replaced with what they matched in the Umbrel env var
+    pub citadel: String,
+}
+
+/// The Citadel mounts that data directories of other Umbrel apps can be converted to
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MountTarget {
+    Bitcoin,
+    Lnd,
+    CLightning,
+}
+
+/// How Umbrel apps are translated to Citadel apps
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Mapping {
+    /// Umbrel app -> the Citadel permission for it
+    #[serde(default)]
+    pub dependencies: BTreeMap<String, String>,
+    /// Env var of an Umbrel data directory -> the Citadel mount for it
+    #[serde(default)]
+    pub mounts: BTreeMap<String, MountTarget>,
+    /// The first rule that matches an env var is used
+    #[serde(default)]
+    pub env_vars: Vec<EnvVarRule>,
+}
+
+impl Default for Mapping {
+    fn default() -> Self {
+        load_mapping(DEFAULT_MAPPING.as_bytes()).expect("Built-in Umbrel mapping is invalid")
+    }
+}
+
+/// Get the env var name of an app, for example APP_ID for app-id
+fn env_var_name(app: &str) -> String {
+    app.to_uppercase().replace('-', "_")
+}
+
+/// Apply a rule without {app} to an env var
+fn apply_rule(pattern: &str, replacement: &str, env_var: &str) -> Option<String> {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => {
+            let rest = env_var.strip_prefix(prefix)?;
+            if rest.is_empty() {
+                return None;
+            }
+            Some(replacement.replace('*', rest))
+        }
+        None => (pattern == env_var).then(|| replacement.to_string()),
+    }
+}
+
+impl Mapping {
+    /// Get the Citadel permission for an Umbrel dependency
+    pub fn citadel_dependency(&self, dependency: &str) -> String {
+        self.dependencies
+            .get(dependency)
+            .cloned()
+            .unwrap_or_else(|| dependency.to_string())
+    }
+
+    /// Get the Umbrel dependency for a Citadel permission
+    pub fn umbrel_dependency(&self, permission: &str) -> String {
+        self.dependencies
+            .iter()
+            .find(|(_, citadel)| *citadel == permission)
+            .map(|(umbrel, _)| umbrel.to_owned())
+            .unwrap_or_else(|| permission.to_string())
+    }
+
+    /// Get the Citadel env var for an Umbrel env var, or None if there is no rule for it
+    /// apps are the ID of the app and the Umbrel apps it depends on
+    pub fn citadel_env_var(&self, env_var: &str, apps: &[String]) -> Option<String> {
+        // Built-in apps have their own rules, so {app} does not match them
+        let apps: Vec<String> = apps
+            .iter()
+            .filter(|app| !self.dependencies.contains_key(*app))
+            .map(|app| env_var_name(app))
+            .collect();
+        self.env_vars.iter().find_map(|rule| {
+            if rule.umbrel.contains("{app}") {
+                apps.iter().find_map(|app| {
+                    apply_rule(
+                        &rule.umbrel.replace("{app}", app),
+                        &rule.citadel.replace("{app}", app),
+                        env_var,
+                    )
+                })
+            } else {
+                apply_rule(&rule.umbrel, &rule.citadel, env_var)
+            }
+        })
+    }
+
+    /// Get the Umbrel env var for a Citadel env var, only rules without patterns are used
+    pub fn umbrel_env_var(&self, env_var: &str) -> Option<String> {
+        self.env_vars
+            .iter()
+            .filter(|rule| !rule.umbrel.contains("{app}") && !rule.umbrel.ends_with('*'))
+            .find(|rule| rule.citadel == env_var)
+            .map(|rule| rule.umbrel.to_owned())
+    }
+}
+
+/// Load a mapping for Umbrel apps
+pub fn load_mapping<R>(reader: R) -> Result<Mapping, Error>
+where
+    R: std::io::Read,
+{
+    serde_yaml::from_reader(reader).map_err(|error| Error::InvalidUmbrelMapping(error.to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Mapping, MountTarget};
+
+    #[test]
+    fn default_mapping() {
+        let mapping = Mapping::default();
+        let apps = vec![
+            "example-app".to_string(),
+            "lightning".to_string(),
+            "other-app".to_string(),
+        ];
+        assert_eq!(
+            mapping.citadel_env_var("APP_LIGHTNING_NODE_IP", &apps),
+            Some("LND_IP".to_string())
+        );
+        assert_eq!(
+            mapping.citadel_env_var("APP_OTHER_APP_WEB_IP", &apps),
+            Some("APP_OTHER_APP_WEB_IP".to_string())
+        );
+        assert_eq!(
+            mapping.citadel_env_var("APP_LIGHTNING_NODE_FOO", &apps),
+            None
+        );
+        assert_eq!(mapping.citadel_env_var("APP_ANOTHER_APP_IP", &apps), None);
+        assert_eq!(
+            mapping.umbrel_env_var("APP_SEED"),
+            Some("APP_PASSWORD".to_string())
+        );
+        assert_eq!(mapping.citadel_dependency("lightning"), "lnd");
+        assert_eq!(mapping.umbrel_dependency("c-lightning"), "core-lightning");
+        assert_eq!(
+            mapping.mounts.get("APP_CORE_LIGHTNING_DATA_DIR"),
+            Some(&MountTarget::CLightning)
+        );
+    }
+}
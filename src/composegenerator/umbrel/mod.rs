@@ -1,2 +1,4 @@
 pub mod convert;
+pub mod export;
+pub mod mapping;
 pub mod types;
@@ -6,8 +6,8 @@ use serde::{Deserialize, Serialize};
 #[cfg_attr(feature = "schema", derive(JsonSchema))]
 #[serde(rename_all = "camelCase")]
 pub struct Metadata {
-    /// The version of the metadata file
-    pub manifest_version: i8,
+    /// The version of the metadata file, for example 1 or 1.1
+    pub manifest_version: serde_json::Number,
     /// The app id
     pub id: String,
     /// The name of the app
@@ -22,6 +22,12 @@ pub struct Metadata {
     pub developer: String,
     // Developer wbsite
     pub website: String,
+    /// The person who submitted the app to the app store
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub submitter: Option<String>,
+    /// A link to the submission of the app
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub submission: Option<String>,
     #[serde(default)]
     /// Permissions the app requires
     pub dependencies: Vec<String>,
@@ -34,6 +40,9 @@ pub struct Metadata {
     /// The path the "Open" link on the dashboard should lead to
     #[serde(skip_serializing_if = "Option::is_none")]
     pub path: Option<String>,
+    /// The app's default username
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_username: Option<String>,
     /// The app's default password. Can also be $APP_SEED for a random password
     pub default_password: Option<String>,
     #[serde(default = "bool::default")]
@@ -45,4 +54,13 @@ pub struct Metadata {
     pub deterministic_password: bool,
     /// A description of the app
     pub description: String,
+    /// What changed in this version of the app
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub release_notes: Option<String>,
+    /// Widgets the app shows on the Umbrel dashboard
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub widgets: Option<Vec<serde_json::Value>>,
+    /// True if the app is designed for the Umbrel Home hardware
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub optimized_for_umbrel_home: Option<bool>,
 }
@@ -3,14 +3,17 @@ use serde_json::{Map, Value};
 use super::types::Schema as AppYmlV3;
 use crate::composegenerator::types::{Metadata, ResultYml};
 use crate::composegenerator::v4::{
-    convert::convert_config as convert_config_v4, types as types_v4,
+    convert::convert_config as convert_config_v4, permissions::PermissionRegistry,
+    resources::ResourcePolicy, types as types_v4,
 };
+use crate::diagnostics::Diagnostic;
+use crate::error::Error;
 use crate::utils::flatten;
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
 pub fn v3_to_v4(app: AppYmlV3, installed_services: &Option<&Vec<String>>) -> types_v4::AppYml {
     let repo = match app.metadata.repo {
-        super::types::RepoDefinition::RepoUrl(url) => HashMap::from([("Public".to_string(), url)]),
+        super::types::RepoDefinition::RepoUrl(url) => BTreeMap::from([("Public".to_string(), url)]),
         super::types::RepoDefinition::MultiRepo(map) => map,
     };
     let metadata = Metadata {
@@ -25,8 +28,10 @@ pub fn v3_to_v4(app: AppYmlV3, installed_services: &Option<&Vec<String>>) -> typ
         support: app.metadata.support,
         gallery: app.metadata.gallery,
         path: app.metadata.path,
+        default_username: None,
         default_password: app.metadata.default_password,
         tor_only: app.metadata.tor_only.unwrap_or(false),
+        release_notes: None,
         update_containers: None,
         description: app.metadata.description,
         implements: None,
@@ -35,7 +40,7 @@ pub fn v3_to_v4(app: AppYmlV3, installed_services: &Option<&Vec<String>>) -> typ
         compatible: true,
         missing_dependencies: None,
     };
-    let mut services = HashMap::<String, types_v4::Container>::with_capacity(app.containers.len());
+    let mut services = BTreeMap::<String, types_v4::Container>::new();
     let deps = flatten(app.metadata.dependencies.unwrap_or_default());
     'container_loop: for container in app.containers {
         if let Some(installed_services) = installed_services {
@@ -59,14 +64,14 @@ pub fn v3_to_v4(app: AppYmlV3, installed_services: &Option<&Vec<String>>) -> typ
                 tcp: None,
             };
             if let Some(tcp_ports) = container.required_ports {
-                let mut map = HashMap::<u16, u16>::with_capacity(tcp_ports.capacity());
+                let mut map = BTreeMap::<u16, u16>::new();
                 for value in tcp_ports.iter() {
                     map.insert(*value, *value);
                 }
                 required_ports_def.tcp = Some(map);
             }
             if let Some(udp_ports) = container.required_udp_ports {
-                let mut map = HashMap::<u16, u16>::with_capacity(udp_ports.capacity());
+                let mut map = BTreeMap::<u16, u16>::new();
                 for value in udp_ports.iter() {
                     map.insert(*value, *value);
                 }
@@ -102,9 +107,7 @@ pub fn v3_to_v4(app: AppYmlV3, installed_services: &Option<&Vec<String>>) -> typ
         let data_mounts = container.data.unwrap_or_default();
         for value in &data_mounts {
             if mounts.data.is_none() {
-                mounts.data = Some(HashMap::<String, String>::with_capacity(
-                    data_mounts.capacity(),
-                ))
+                mounts.data = Some(BTreeMap::<String, String>::new())
             }
             let mut split = value.split(':');
             mounts.data.as_mut().unwrap().insert(
@@ -134,6 +137,8 @@ pub fn v3_to_v4(app: AppYmlV3, installed_services: &Option<&Vec<String>>) -> typ
                 entrypoint: container.entrypoint,
                 command: container.command,
                 working_dir: None,
+                healthcheck: None,
+                resources: None,
                 environment: container.environment,
                 port: container.port,
                 port_priority,
@@ -149,26 +154,27 @@ pub fn v3_to_v4(app: AppYmlV3, installed_services: &Option<&Vec<String>>) -> typ
                     }
                     super::types::HiddenServices::LegacyLayeredMap(map) => {
                         let new_values = map.iter().map(|val| {
-                            let hashmap = HashMap::from_iter(val.1.iter().map(|val| (*val, *val)));
+                            let hashmap = BTreeMap::from_iter(val.1.iter().map(|val| (*val, *val)));
                             (val.0.to_owned(), hashmap)
                         });
-                        types_v4::HiddenServices::LayeredMap(HashMap::from_iter(new_values))
+                        types_v4::HiddenServices::LayeredMap(BTreeMap::from_iter(new_values))
                     }
                     super::types::HiddenServices::LegacySinglePort(port) => {
-                        types_v4::HiddenServices::PortMap(HashMap::from([(port, port)]))
+                        types_v4::HiddenServices::PortMap(BTreeMap::from([(port, port)]))
                     }
                     super::types::HiddenServices::LegacyPortArray(ports) => {
-                        let hashmap = HashMap::from_iter(ports.iter().map(|val| (*val, *val)));
+                        let hashmap = BTreeMap::from_iter(ports.iter().map(|val| (*val, *val)));
                         types_v4::HiddenServices::PortMap(hashmap)
                     }
                     super::types::HiddenServices::LegacyMap(map) => {
-                        let new_values = map
-                            .iter()
-                            .map(|(name, port)| (name.to_owned(), HashMap::from([(*port, *port)])));
-                        types_v4::HiddenServices::LayeredMap(HashMap::from_iter(new_values))
+                        let new_values = map.iter().map(|(name, port)| {
+                            (name.to_owned(), BTreeMap::from([(*port, *port)]))
+                        });
+                        types_v4::HiddenServices::LayeredMap(BTreeMap::from_iter(new_values))
                     }
                 }),
                 cap_add: None,
+                security: None,
             },
         );
     }
@@ -184,6 +190,15 @@ pub fn convert_config(
     app: AppYmlV3,
     port_map: &Option<Map<String, Value>>,
     installed_services: &Vec<String>,
-) -> Result<ResultYml, String> {
-    convert_config_v4(app_name, v3_to_v4(app, &Some(installed_services)), port_map, &None)
+    resource_policy: &Option<ResourcePolicy>,
+    permission_registry: &Option<PermissionRegistry>,
+) -> Result<(ResultYml, Vec<Diagnostic>), Error> {
+    convert_config_v4(
+        app_name,
+        v3_to_v4(app, &Some(installed_services)),
+        port_map,
+        &None,
+        resource_policy,
+        permission_registry,
+    )
 }
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
 #[cfg(feature = "schema")]
 use schemars::JsonSchema;
@@ -13,12 +13,12 @@ use crate::composegenerator::{
 #[cfg_attr(feature = "schema", derive(JsonSchema))]
 #[serde(untagged)]
 pub enum HiddenServices {
-    PortMap(HashMap<u16, u16>),
-    LayeredMap(HashMap<String, HashMap<u16, u16>>),
-    LegacyLayeredMap(HashMap<String, Vec<u16>>),
+    PortMap(BTreeMap<u16, u16>),
+    LayeredMap(BTreeMap<String, BTreeMap<u16, u16>>),
+    LegacyLayeredMap(BTreeMap<String, Vec<u16>>),
     LegacySinglePort(u16),
     LegacyPortArray(Vec<u16>),
-    LegacyMap(HashMap<String, u16>),
+    LegacyMap(BTreeMap<String, u16>),
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
@@ -26,7 +26,7 @@ pub enum HiddenServices {
 #[serde(untagged)]
 pub enum RepoDefinition {
     RepoUrl(String),
-    MultiRepo(HashMap<String, String>),
+    MultiRepo(BTreeMap<String, String>),
 }
 
 #[derive(Clone, PartialEq, Eq, Debug, Default, Deserialize, Serialize)]
@@ -60,7 +60,7 @@ pub struct SchemaItemContainers {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub entrypoint: Option<Command>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub environment: Option<HashMap<String, StringOrIntOrBool>>,
+    pub environment: Option<BTreeMap<String, StringOrIntOrBool>>,
     /// This can either be a map of hidden service names (human readable names, not the .onion URL,
     /// and strings, not numbers) to a port if your app needs multiple hidden services on different
     /// ports, a map of port inside to port on the hidden service (if your app has multiple ports
@@ -132,7 +132,7 @@ pub struct SchemaMetadata {
     /// A longer description of the app
     pub description: String,
     /// The awesome people behind the app
-    pub developers: HashMap<String, String>,
+    pub developers: BTreeMap<String, String>,
     /// The services the app depends on.
     /// This can also contain an array like [c-lightning, lnd] if your app requires one of two
     /// dependencies to function.
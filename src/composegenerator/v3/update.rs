@@ -20,12 +20,12 @@ pub async fn get_hash(container: &str, docker: &Docker) -> Result<String, bollar
     let digests = hash.repo_digests.expect("No digest found!");
     let result = digests.first().expect("No digest found!");
 
-    Ok(result.to_owned().split('@').last().unwrap().to_owned())
+    Ok(result.split('@').next_back().unwrap().to_owned())
 }
 
 pub async fn update_container(
     container: &mut SchemaItemContainers,
-    to_version: &String,
+    to_version: &str,
     docker: &Docker,
 ) -> Result<(), bollard::errors::Error> {
     let image = &container.image;
@@ -1,149 +1,105 @@
 use serde_json::{Map, Value};
 
 use super::{
-    permissions, types,
+    permissions::{check_unused_permissions, PermissionRegistry},
+    resources::{apply_resource_policy, ResourcePolicy},
+    types,
     types::PortMapElement,
-    utils::{get_host_port, get_main_container, validate_cmd, validate_port_map_app},
+    utils::{
+        check_cmd, check_env_vars, get_host_port, get_main_container, ip_env_var, parse_duration,
+        validate_port_map_app,
+    },
 };
-use crate::utils::{find_env_vars, flatten};
+use crate::diagnostics::{first_error, service_path, Diagnostic};
+use crate::utils::{flatten, substitute_known_env_vars};
 use crate::{
     bmap,
     composegenerator::{
-        compose::types::StringOrIntOrBool,
-        output::types::{ComposeSpecification, NetworkEntry, Service},
+        compose::types::{Command, StringOrIntOrBool},
+        output::types::{
+            ComposeSpecification, Dependency, DependencyCondition, DependsOn, NetworkEntry, Service,
+        },
         types::Permissions,
     },
 };
 use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
 
+use crate::composegenerator::tor::{HiddenService, HiddenServicePort, TorConfig, TorHost};
 use crate::composegenerator::types::ResultYml;
+use crate::error::Error;
+
+/// Healthchecks that run more often than this put too much load on small nodes
+const MIN_HEALTHCHECK_INTERVAL: Duration = Duration::from_secs(5);
+const MIN_HEALTHCHECK_TIMEOUT: Duration = Duration::from_secs(1);
+const MAX_HEALTHCHECK_DURATION: Duration = Duration::from_secs(3600);
 
 fn get_main_port(
-    containers: &HashMap<String, types::Container>,
+    containers: &BTreeMap<String, types::Container>,
     main_container: &str,
     port_map: &Option<HashMap<String, Vec<PortMapElement>>>,
-) -> Result<u16, String> {
-    let mut result: u16 = 0;
-    for service_name in containers.keys() {
-        let original_definition = containers.get(service_name).unwrap();
-        if service_name != main_container && original_definition.port.is_some() {
-            return Err(
-                "port: is not supported for containers other than the main container".to_string(),
-            );
-        }
-
-        if let Some(internal_port) = original_definition.port {
-            if service_name != main_container {
-                return Err(
-                    "port: is not supported for containers other than the main container"
-                        .to_string(),
-                );
-            }
-            let public_port: Option<&PortMapElement>;
-            let fake_port = PortMapElement {
-                internal_port,
-                public_port: internal_port,
-                dynamic: false,
-            };
-            if let Some(real_port_map) = port_map {
-                if real_port_map.get(service_name).is_none() {
-                    return Err(format!(
-                        "Container {} not found or invalid in port map",
-                        service_name
-                    ));
-                }
-                let ports = real_port_map.get(service_name).unwrap();
-                public_port = get_host_port(ports, internal_port);
-            } else {
-                public_port = Some(&fake_port);
-            }
-            if public_port.is_some() {
-                result = internal_port;
-                break;
-            } else {
-                return Err("Main container port not found in port map".to_string());
-            }
-        } else if service_name == main_container {
-            let empty_vec = Vec::<PortMapElement>::with_capacity(0);
-            if let Some(real_port) = port_map
-                .clone()
-                .unwrap_or_default()
-                .get(service_name)
-                .unwrap_or(&empty_vec)
-                .iter()
-                .find(|elem| elem.dynamic)
-            {
-                result = real_port.internal_port;
-            } else if port_map.is_none() {
-                result = 3000;
-            } else {
-                return Err("A port is required for the main container".to_string());
+) -> Result<u16, Error> {
+    let original_definition = containers.get(main_container).unwrap();
+    if let Some(internal_port) = original_definition.port {
+        if let Some(real_port_map) = port_map {
+            let ports =
+                real_port_map
+                    .get(main_container)
+                    .ok_or_else(|| Error::ContainerNotInPortMap {
+                        service: main_container.to_string(),
+                    })?;
+            if get_host_port(ports, internal_port).is_none() {
+                return Err(Error::MainPortNotInPortMap {
+                    service: main_container.to_string(),
+                });
             }
         }
+        Ok(internal_port)
+    } else if let Some(real_port_map) = port_map {
+        real_port_map
+            .get(main_container)
+            .and_then(|ports| ports.iter().find(|elem| elem.dynamic))
+            .map(|elem| elem.internal_port)
+            .ok_or_else(|| Error::MainPortRequired {
+                service: main_container.to_string(),
+            })
+    } else {
+        Ok(3000)
     }
-
-    Ok(result)
 }
 
 fn configure_ports(
-    containers: &HashMap<String, types::Container>,
+    containers: &BTreeMap<String, types::Container>,
     main_container: &str,
     output: &mut ComposeSpecification,
     port_map: &Option<HashMap<String, Vec<PortMapElement>>>,
-) -> Result<(), String> {
+    diagnostics: &mut Vec<Diagnostic>,
+) {
     let services = output.services.as_mut().unwrap();
     for (service_name, service) in services {
         let original_definition = containers.get(service_name).unwrap();
-        if service_name != main_container && original_definition.port.is_some() {
-            return Err(
-                "port: is not supported for containers other than the main container".to_string(),
-            );
-        }
-
         if let Some(internal_port) = original_definition.port {
             if service_name != main_container {
-                return Err(
-                    "port: is not supported for containers other than the main container"
-                        .to_string(),
-                );
-            }
-            let public_port: Option<&PortMapElement>;
-            let fake_port = PortMapElement {
-                internal_port,
-                public_port: internal_port,
-                dynamic: false,
-            };
-            if let Some(real_port_map) = port_map {
-                if real_port_map.get(service_name).is_none() {
-                    return Err(format!(
-                        "Container {} not found or invalid in port map",
-                        service_name
-                    ));
-                }
-                let ports = real_port_map.get(service_name).unwrap();
-                public_port = get_host_port(ports, internal_port);
-            } else {
-                public_port = Some(&fake_port);
-            }
-            if let Some(port_map_elem) = public_port {
-                service
-                    .ports
-                    .push(format!("{}:{}", port_map_elem.public_port, internal_port));
+                diagnostics.push(Diagnostic::error(
+                    service_path(service_name, "port"),
+                    Error::PortOnNonMainContainer {
+                        service: service_name.to_string(),
+                    },
+                ));
             } else {
-                return Err("Main container port not found in port map".to_string());
-            }
-        } else if service_name == main_container {
-            let empty_vec = Vec::<PortMapElement>::with_capacity(0);
-            if port_map.is_some()
-                && !port_map
-                    .clone()
-                    .unwrap()
-                    .get(service_name)
-                    .unwrap_or(&empty_vec)
-                    .iter()
-                    .any(|elem| elem.dynamic)
-            {
-                return Err("A port is required for the main container".to_string());
+                // If the main port is missing in the port map, get_main_port already reported it
+                let public_port = match port_map {
+                    Some(real_port_map) => real_port_map
+                        .get(service_name)
+                        .and_then(|ports| get_host_port(ports, internal_port))
+                        .map(|elem| elem.public_port),
+                    None => Some(internal_port),
+                };
+                if let Some(public_port) = public_port {
+                    service
+                        .ports
+                        .push(format!("{}:{}", public_port, internal_port));
+                }
             }
         }
         if let Some(required_ports) = &original_definition.required_ports {
@@ -159,16 +115,15 @@ fn configure_ports(
             }
         }
     }
-
-    Ok(())
 }
 
 fn define_ip_addresses(
     app_name: &str,
-    containers: &HashMap<String, types::Container>,
+    containers: &BTreeMap<String, types::Container>,
     main_container: &str,
     output: &mut ComposeSpecification,
-) -> Result<(), String> {
+    diagnostics: &mut Vec<Diagnostic>,
+) {
     let services = output.services.as_mut().unwrap();
     for (service_name, service) in services {
         if containers
@@ -179,63 +134,117 @@ fn define_ip_addresses(
         {
             service.networks = Some(bmap! {
                 "default" => NetworkEntry {
-                    ipv4_address: Some(format!("$APP_{}_{}_IP", app_name.to_string().to_uppercase().replace('-', "_"), service_name.to_uppercase().replace('-', "_")))
+                    ipv4_address: Some(format!("${}", ip_env_var(app_name, service_name)))
                 }
             })
         } else if service_name == main_container {
-            return Err("Network can not be disabled for the main container".to_string());
+            diagnostics.push(Diagnostic::error(
+                service_path(service_name, "enable_networking"),
+                Error::NetworkDisabledForMainContainer {
+                    service: service_name.to_string(),
+                },
+            ));
         }
     }
-
-    Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 fn validate_service(
     app_name: &str,
+    service_name: &str,
     permissions: &mut Vec<String>,
+    registry: &PermissionRegistry,
     service: &types::Container,
     replace_env_vars: &HashMap<String, String>,
     result: &mut Service,
-) -> Result<(), String> {
+    diagnostics: &mut Vec<Diagnostic>,
+) {
     if let Some(entrypoint) = &service.entrypoint {
-        let validation_result = validate_cmd(app_name, entrypoint, permissions);
-        if validation_result.is_err() {
-            return Err(validation_result.err().unwrap());
-        }
+        diagnostics.extend(check_cmd(
+            app_name,
+            service_name,
+            &service_path(service_name, "entrypoint"),
+            entrypoint,
+            permissions,
+            registry,
+        ));
         result.entrypoint = Some(entrypoint.to_owned());
     }
     if let Some(command) = &service.command {
-        let validation_result = validate_cmd(app_name, command, permissions);
-        if validation_result.is_err() {
-            return Err(validation_result.err().unwrap());
-        }
+        diagnostics.extend(check_cmd(
+            app_name,
+            service_name,
+            &service_path(service_name, "command"),
+            command,
+            permissions,
+            registry,
+        ));
         result.command = Some(command.to_owned());
     }
+    if let Some(healthcheck) = &service.healthcheck {
+        diagnostics.extend(check_cmd(
+            app_name,
+            service_name,
+            &service_path(service_name, "healthcheck.test"),
+            &healthcheck.test,
+            permissions,
+            registry,
+        ));
+        if let Command::ArrayCommand(test) = &healthcheck.test {
+            if !matches!(
+                test.first().map(String::as_str),
+                Some("CMD") | Some("CMD-SHELL")
+            ) {
+                diagnostics.push(Diagnostic::error(
+                    service_path(service_name, "healthcheck.test"),
+                    Error::InvalidHealthcheck {
+                        service: service_name.to_string(),
+                        message: "test has to start with CMD or CMD-SHELL".to_string(),
+                    },
+                ));
+            }
+        }
+        for (key, value, min) in [
+            ("interval", &healthcheck.interval, MIN_HEALTHCHECK_INTERVAL),
+            ("timeout", &healthcheck.timeout, MIN_HEALTHCHECK_TIMEOUT),
+            ("start_period", &healthcheck.start_period, Duration::ZERO),
+        ] {
+            let message = match value.as_deref().map(parse_duration) {
+                Some(None) => format!("{} is not a valid duration", key),
+                Some(Some(duration)) if duration < min || duration > MAX_HEALTHCHECK_DURATION => {
+                    format!(
+                        "{} has to be between {:?} and {:?}",
+                        key, min, MAX_HEALTHCHECK_DURATION
+                    )
+                }
+                _ => continue,
+            };
+            diagnostics.push(Diagnostic::error(
+                service_path(service_name, &format!("healthcheck.{}", key)),
+                Error::InvalidHealthcheck {
+                    service: service_name.to_string(),
+                    message,
+                },
+            ));
+        }
+        result.healthcheck = Some(healthcheck.to_owned());
+    }
     if let Some(env) = &service.environment {
         result.environment = Some(BTreeMap::<String, StringOrIntOrBool>::new());
         let result_env = result.environment.as_mut().unwrap();
         for value in env {
             let val = match value.1 {
                 StringOrIntOrBool::String(val) => {
-                    let env_vars = find_env_vars(val);
-                    for env_var in &env_vars {
-                        if !permissions::is_allowed_by_permissions(app_name, env_var, permissions) {
-                            return Err(format!("Env var {} not allowed by permissions", env_var));
-                        }
-                    }
-                    let mut val = val.to_owned();
-                    if !env_vars.is_empty() {
-                        let to_replace = replace_env_vars
-                            .iter()
-                            .filter(|(key, _)| env_vars.contains(&key.as_str()));
-                        for (env_var, replacement) in to_replace {
-                            let syntax_1 = "$".to_string() + env_var;
-                            let syntax_2 = format!("${{{}}}", env_var);
-                            val = val.replace(&syntax_1, replacement);
-                            val = val.replace(&syntax_2, replacement);
-                        }
-                    }
-                    StringOrIntOrBool::String(val)
+                    diagnostics.extend(check_env_vars(
+                        app_name,
+                        service_name,
+                        &service_path(service_name, &format!("environment.{}", value.0)),
+                        val,
+                        permissions,
+                        registry,
+                    ));
+                    // Env vars without a replacement are kept for docker-compose to resolve
+                    StringOrIntOrBool::String(substitute_known_env_vars(val, replace_env_vars))
                 }
                 StringOrIntOrBool::Int(int) => StringOrIntOrBool::Int(*int),
                 StringOrIntOrBool::Bool(bool) => StringOrIntOrBool::Bool(*bool),
@@ -247,7 +256,12 @@ fn validate_service(
     if service.network_mode.is_some() {
         if !permissions.contains(&"network".to_string()) {
             // To preserve compatibility, this is only a warning, but we add the permission to the output
-            eprintln!("App defines network-mode, but does not request the network permission");
+            diagnostics.push(Diagnostic::warning(
+                service_path(service_name, "network_mode"),
+                Error::NetworkModeWithoutPermission {
+                    service: service_name.to_string(),
+                },
+            ));
             permissions.push("network".to_string());
         }
         result.network_mode = service.network_mode.to_owned();
@@ -255,26 +269,114 @@ fn validate_service(
     if let Some(caps) = &service.cap_add {
         let mut cap_add = Vec::<String>::new();
         for cap in caps {
-            match cap.to_lowercase().as_str() {
-                "cap-net-raw" | "cap-net-admin" => {
-                    if !permissions.contains(&"network".to_string()) {
-                        return Err("App defines a network capability, but does not request the network permission".to_string());
+            match registry.capability_permission(cap) {
+                Some(permission) => {
+                    if !permissions.iter().any(|p| p == permission) {
+                        diagnostics.push(Diagnostic::error(
+                            service_path(service_name, "cap_add"),
+                            Error::CapabilityNotAllowed {
+                                capability: cap.to_owned(),
+                                service: service_name.to_string(),
+                                permission: permission.to_string(),
+                            },
+                        ));
                     }
                     cap_add.push(cap.to_owned());
                 }
-                _ => return Err(format!("App defines unknown capability: {}", cap)),
+                None => diagnostics.push(Diagnostic::error(
+                    service_path(service_name, "cap_add"),
+                    Error::UnknownCapability {
+                        capability: cap.to_owned(),
+                        service: service_name.to_string(),
+                    },
+                )),
             }
         }
         result.cap_add = Some(cap_add);
     }
-    Ok(())
+    if let Some(types::HiddenServices::Detailed(definitions)) = &service.hidden_services {
+        for (name, definition) in definitions {
+            for (index, client) in definition.authorized_clients.iter().enumerate() {
+                let is_valid = !client.is_empty()
+                    && client
+                        .chars()
+                        .all(|char| char.is_ascii_alphanumeric() || char == '-' || char == '_');
+                if !is_valid {
+                    diagnostics.push(Diagnostic::error(
+                        service_path(
+                            service_name,
+                            &format!("hidden_services.{}.authorized_clients.{}", name, index),
+                        ),
+                        Error::InvalidAuthorizedClient {
+                            client: client.to_owned(),
+                            service: service_name.to_string(),
+                        },
+                    ));
+                }
+            }
+        }
+    }
+}
+
+/// Apply the hardened defaults to a service, apps can only opt out of them with the privileges permission
+fn configure_security(
+    service_name: &str,
+    permissions: &[String],
+    service: &types::Container,
+    result: &mut Service,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let security = service.security.clone().unwrap_or_default();
+    for (option, value) in [
+        ("no_new_privileges", security.no_new_privileges),
+        ("drop_capabilities", security.drop_capabilities),
+    ] {
+        if value == Some(false) && !permissions.contains(&"privileges".to_string()) {
+            diagnostics.push(Diagnostic::error(
+                service_path(service_name, &format!("security.{}", option)),
+                Error::SecurityOptOutWithoutPermission {
+                    option: option.to_string(),
+                    service: service_name.to_string(),
+                },
+            ));
+        }
+    }
+    if security.no_new_privileges != Some(false) {
+        result.security_opt = Some(vec!["no-new-privileges:true".to_string()]);
+    }
+    // The capabilities in cap_add are added back after dropping all others
+    if security.drop_capabilities != Some(false) {
+        result.cap_drop = Some(vec!["ALL".to_string()]);
+    }
+    if security.read_only == Some(true) {
+        result.read_only = Some(true);
+    }
+    for (index, tmpfs) in security.tmpfs.iter().enumerate() {
+        let path = tmpfs
+            .split_once(':')
+            .map_or(tmpfs.as_str(), |(path, _)| path);
+        if !path.starts_with('/') || path.split('/').any(|component| component == "..") {
+            diagnostics.push(Diagnostic::error(
+                service_path(service_name, &format!("security.tmpfs.{}", index)),
+                Error::InvalidTmpfs {
+                    path: tmpfs.to_owned(),
+                    service: service_name.to_string(),
+                },
+            ));
+        }
+    }
+    if !security.tmpfs.is_empty() {
+        result.tmpfs = Some(security.tmpfs);
+    }
 }
 
 fn convert_volumes(
-    containers: &HashMap<String, types::Container>,
+    containers: &BTreeMap<String, types::Container>,
     permissions: &[String],
+    registry: &PermissionRegistry,
     output: &mut ComposeSpecification,
-) -> Result<(), String> {
+    diagnostics: &mut Vec<Diagnostic>,
+) {
     let services = output.services.as_mut().unwrap();
     for (service_name, service) in services {
         let original_definition = containers.get(service_name).unwrap();
@@ -282,9 +384,14 @@ fn convert_volumes(
             if let Some(data_mounts) = &mounts.data {
                 for (host_path, container_path) in data_mounts {
                     if host_path.contains("..") {
-                        return Err(
-                            "A data dir to mount is not allowed to contain '..'".to_string()
-                        );
+                        diagnostics.push(Diagnostic::error(
+                            service_path(service_name, &format!("mounts.data.{}", host_path)),
+                            Error::InvalidMount {
+                                path: host_path.to_owned(),
+                                service: service_name.to_string(),
+                            },
+                        ));
+                        continue;
                     }
                     let mount_host_dir: String = if !host_path.starts_with('/') {
                         "/".to_owned() + host_path
@@ -298,100 +405,149 @@ fn convert_volumes(
                 }
             }
 
-            if let Some(bitcoin_mount) = &mounts.bitcoin {
-                if !permissions.contains(&"bitcoind".to_string()) {
-                    return Err(
-                        "bitcoin mount defined by container without Bitcoin permissions"
-                            .to_string(),
-                    );
-                }
-                service
-                    .volumes
-                    .push(format!("${{BITCOIN_DATA_DIR}}:{}", bitcoin_mount));
-            }
-
-            if let Some(lnd_mount) = &mounts.lnd {
-                if !permissions.contains(&"lnd".to_string()) {
-                    return Err(
-                        "lnd mount defined by container without LND permissions".to_string()
-                    );
-                }
-                service
-                    .volumes
-                    .push(format!("${{LND_DATA_DIR}}:{}", lnd_mount));
-            }
-
-            if let Some(c_lightning_mount) = &mounts.c_lightning {
-                if !permissions.contains(&"c-lightning".to_string()) {
-                    return Err(
-                        "c-lightning mount defined by container without Core Lightning permissions"
-                            .to_string(),
-                    );
+            for (mount, container_path, host_dir) in [
+                ("bitcoin", &mounts.bitcoin, "BITCOIN_DATA_DIR"),
+                ("lnd", &mounts.lnd, "LND_DATA_DIR"),
+                ("c_lightning", &mounts.c_lightning, "C_LIGHTNING_DATA_DIR"),
+            ] {
+                let container_path = match container_path {
+                    Some(container_path) => container_path,
+                    None => continue,
+                };
+                let permission = registry.mount_permission(mount);
+                if !permission.is_some_and(|permission| permissions.iter().any(|p| p == permission))
+                {
+                    diagnostics.push(Diagnostic::error(
+                        service_path(service_name, &format!("mounts.{}", mount)),
+                        Error::MountNotAllowed {
+                            mount: mount.replace('_', "-"),
+                            service: service_name.to_string(),
+                            permission: permission.unwrap_or("unknown").to_string(),
+                        },
+                    ));
                 }
                 service
                     .volumes
-                    .push(format!("${{C_LIGHTNING_DATA_DIR}}:{}", c_lightning_mount));
+                    .push(format!("${{{}}}:{}", host_dir, container_path));
             }
         }
     }
+}
 
-    Ok(())
+/// Let services wait until the services they depend on are healthy, if those define a healthcheck
+fn get_depends_on(
+    depends_on: &Option<Vec<String>>,
+    containers: &BTreeMap<String, types::Container>,
+) -> Option<DependsOn> {
+    let depends_on = depends_on.as_ref()?;
+    let has_healthcheck = |dependency: &String| {
+        containers
+            .get(dependency)
+            .is_some_and(|container| container.healthcheck.is_some())
+    };
+    if !depends_on.iter().any(has_healthcheck) {
+        return Some(DependsOn::List(depends_on.clone()));
+    }
+    Some(DependsOn::Conditions(
+        depends_on
+            .iter()
+            .map(|dependency| {
+                let condition = if has_healthcheck(dependency) {
+                    DependencyCondition::ServiceHealthy
+                } else {
+                    DependencyCondition::ServiceStarted
+                };
+                (dependency.clone(), Dependency { condition })
+            })
+            .collect(),
+    ))
 }
 
 fn get_hidden_services(
     app_name: &str,
-    containers: HashMap<String, types::Container>,
+    containers: BTreeMap<String, types::Container>,
     main_container: &str,
     main_port: u16,
-) -> String {
-    let mut result = String::new();
+) -> TorConfig {
+    let mut result = TorConfig::default();
+    let app_name_slug = app_name.to_lowercase().replace('_', "-");
+    let ports = |service_name: &str, ports: &BTreeMap<u16, u16>| {
+        ports
+            .iter()
+            .map(|(virtual_port, target_port)| HiddenServicePort {
+                virtual_port: *virtual_port,
+                host: Some(TorHost::container(app_name, service_name)),
+                target_port: *target_port,
+            })
+            .collect::<Vec<HiddenServicePort>>()
+    };
     for service_name in containers.keys() {
         let original_definition = containers.get(service_name).unwrap();
         if original_definition.network_mode == Some("host".to_string()) {
             continue;
         }
-        let app_name_slug = app_name.to_lowercase().replace('_', "-");
         let service_name_slug = service_name.to_lowercase().replace('_', "-");
         if service_name == main_container {
-            let hidden_service_string = format!(
-                "HiddenServiceDir /var/lib/tor/app-{}\nHiddenServicePort 80 <app-{}-{}-ip>:{}\n",
-                app_name_slug, app_name_slug, service_name_slug, main_port
-            );
-            result += hidden_service_string.as_str();
+            let mut hidden_service =
+                HiddenService::new(format!("/var/lib/tor/app-{}", app_name_slug));
+            hidden_service.ports.push(HiddenServicePort {
+                virtual_port: 80,
+                host: Some(TorHost::container(app_name, service_name)),
+                target_port: main_port,
+            });
+            result.hidden_services.push(hidden_service);
         }
         if let Some(hidden_services) = &original_definition.hidden_services {
             match hidden_services {
                 types::HiddenServices::PortMap(simple_map) => {
                     if service_name != main_container {
-                        let hidden_service_string = format!(
-                            "HiddenServiceDir /var/lib/tor/app-{}-{}\n",
+                        result.hidden_services.push(HiddenService::new(format!(
+                            "/var/lib/tor/app-{}-{}",
                             app_name_slug, service_name_slug
-                        );
-                        result += hidden_service_string.as_str();
-                    }
-                    for port in simple_map {
-                        let port_string = format!(
-                            "HiddenServicePort {} <app-{}-{}-ip>:{}\n",
-                            port.0, app_name_slug, service_name_slug, port.1
-                        );
-                        result += port_string.as_str();
+                        )));
                     }
+                    // The main container's ports are added to the app's main hidden service
+                    result
+                        .hidden_services
+                        .last_mut()
+                        .unwrap()
+                        .ports
+                        .extend(ports(service_name, simple_map));
                 }
                 types::HiddenServices::LayeredMap(layered_map) => {
                     for element in layered_map {
-                        let hidden_service_string = format!(
-                            "HiddenServiceDir /var/lib/tor/app-{}-{}\n",
+                        let mut hidden_service = HiddenService::new(format!(
+                            "/var/lib/tor/app-{}-{}",
                             app_name_slug,
                             element.0.to_lowercase().replace('_', "-")
-                        );
-                        result += hidden_service_string.as_str();
-                        for port in element.1 {
-                            let port_string = format!(
-                                "HiddenServicePort {} <app-{}-{}-ip>:{}\n",
-                                port.0, app_name_slug, service_name_slug, port.1
-                            );
-                            result += port_string.as_str();
+                        ));
+                        hidden_service.ports = ports(service_name, element.1);
+                        result.hidden_services.push(hidden_service);
+                    }
+                }
+                types::HiddenServices::Detailed(definitions) => {
+                    for (name, definition) in definitions {
+                        let mut hidden_service = HiddenService::new(format!(
+                            "/var/lib/tor/app-{}-{}",
+                            app_name_slug,
+                            name.to_lowercase().replace('_', "-")
+                        ));
+                        hidden_service.ports = ports(service_name, &definition.ports);
+                        hidden_service.max_streams = definition.max_streams;
+                        hidden_service.max_streams_close_circuit =
+                            definition.max_streams_close_circuit;
+                        hidden_service.num_introduction_points = definition.num_introduction_points;
+                        hidden_service.enable_intro_dos_defense =
+                            definition.enable_intro_dos_defense;
+                        hidden_service.authorized_clients =
+                            definition.authorized_clients.iter().cloned().collect();
+                        // Client authorization and the DoS defense are only supported by v3 onion services
+                        if !hidden_service.authorized_clients.is_empty()
+                            || hidden_service.enable_intro_dos_defense.is_some()
+                        {
+                            hidden_service.version = Some(3);
                         }
+                        result.hidden_services.push(hidden_service);
                     }
                 }
             }
@@ -423,45 +579,74 @@ fn get_missing_dependencies(
     missing
 }
 
-pub fn convert_config(
+/// Convert an app and collect every problem found on the way
+/// Returns None if the app could not be converted because of an error
+pub fn convert_with_diagnostics(
     app_name: &str,
     app: types::AppYml,
     port_map: &Option<Map<String, Value>>,
     installed_services: &Option<Vec<String>>,
-) -> Result<ResultYml, String> {
+    resource_policy: &Option<ResourcePolicy>,
+    permission_registry: &Option<PermissionRegistry>,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Option<ResultYml> {
+    let registry = permission_registry
+        .as_ref()
+        .unwrap_or_else(|| PermissionRegistry::builtin());
     let mut spec: ComposeSpecification = ComposeSpecification {
         services: Some(BTreeMap::new()),
+        ..Default::default()
     };
     let spec_services = spec.services.get_or_insert(BTreeMap::new());
     let mut permissions = flatten(app.metadata.permissions.clone());
 
-    let main_service = get_main_container(&app)?;
     let mut converted_port_map: Option<HashMap<String, Vec<PortMapElement>>> = None;
     if let Some(real_port_map) = port_map {
-        let conversion_result = validate_port_map_app(real_port_map);
-        match conversion_result {
+        match validate_port_map_app(real_port_map) {
+            Ok(conversion_result) => converted_port_map = Some(conversion_result),
             Err(conversion_error) => {
-                return Err(conversion_error.to_string());
-            }
-            Ok(conversion_result) => {
-                converted_port_map = Some(conversion_result);
+                diagnostics.push(Diagnostic::error(
+                    "",
+                    Error::InvalidPortMap(conversion_error.to_string()),
+                ));
+                return None;
             }
         }
     }
-    let main_port = get_main_port(&app.services, &main_service, &converted_port_map)?;
 
-    // Required for dynamic ports
-    let env_var = format!(
-        "APP_{}_{}_PORT",
-        app_name.replace('-', "_").to_uppercase(),
-        main_service.to_uppercase()
-    );
+    let main_service = match get_main_container(&app) {
+        Ok(main_service) => Some(main_service),
+        Err(error) => {
+            diagnostics.push(Diagnostic::error("services", error));
+            None
+        }
+    };
+    let main_port = match &main_service {
+        Some(main_service) => {
+            match get_main_port(&app.services, main_service, &converted_port_map) {
+                Ok(main_port) => Some(main_port),
+                Err(error) => {
+                    diagnostics.push(Diagnostic::error(service_path(main_service, "port"), error));
+                    None
+                }
+            }
+        }
+        None => None,
+    };
 
-    let replace_env_vars = HashMap::<String, String>::from([
-        (env_var, main_port.to_string()),
+    let mut replace_env_vars = HashMap::<String, String>::from([
         ("ELECTRUM_IP".to_string(), "${APP_ELECTRUM_IP}".to_string()),
         ("ELECTRUM_PORT".to_string(), "50001".to_string()),
     ]);
+    if let (Some(main_service), Some(main_port)) = (&main_service, main_port) {
+        // Required for dynamic ports
+        let env_var = format!(
+            "APP_{}_{}_PORT",
+            app_name.replace('-', "_").to_uppercase(),
+            main_service.to_uppercase()
+        );
+        replace_env_vars.insert(env_var, main_port.to_string());
+    }
 
     // Copy all properties that are the same in docker-compose.yml and need no or only a simple validation
     for (service_name, service) in &app.services {
@@ -472,7 +657,7 @@ pub fn convert_config(
             stop_signal: service.stop_signal.clone(),
             user: service.user.clone(),
             init: service.init,
-            depends_on: service.depends_on.clone(),
+            depends_on: get_depends_on(&service.depends_on, &app.services),
             extra_hosts: service.extra_hosts.clone(),
             working_dir: service.working_dir.clone(),
             ports: Vec::new(),
@@ -482,23 +667,67 @@ pub fn convert_config(
         spec_services.insert(service_name.to_string(), base_result);
         validate_service(
             app_name,
+            service_name,
             &mut permissions,
+            registry,
             service,
             &replace_env_vars,
             spec_services.get_mut(service_name).unwrap(),
-        )?;
+            diagnostics,
+        );
+        configure_security(
+            service_name,
+            &permissions,
+            service,
+            spec_services.get_mut(service_name).unwrap(),
+            diagnostics,
+        );
+        // Without a policy, the requested limits are only validated
+        apply_resource_policy(
+            service_name,
+            &service.resources,
+            resource_policy
+                .as_ref()
+                .unwrap_or(&ResourcePolicy::default()),
+            spec_services.get_mut(service_name).unwrap(),
+            diagnostics,
+        );
     }
     // We can now finalize the process by parsing some of the remaining values
-    configure_ports(&app.services, &main_service, &mut spec, &converted_port_map)?;
+    if let Some(main_service) = &main_service {
+        configure_ports(
+            &app.services,
+            main_service,
+            &mut spec,
+            &converted_port_map,
+            diagnostics,
+        );
+        define_ip_addresses(
+            app_name,
+            &app.services,
+            main_service,
+            &mut spec,
+            diagnostics,
+        );
+    }
 
-    let ip_address_result = define_ip_addresses(app_name, &app.services, &main_service, &mut spec);
+    diagnostics.extend(check_unused_permissions(app_name, &app, registry));
+    convert_volumes(
+        &app.services,
+        &permissions,
+        registry,
+        &mut spec,
+        diagnostics,
+    );
 
-    if ip_address_result.is_err() {
-        return Err(ip_address_result.err().unwrap());
+    let (main_service, main_port) = match (main_service, main_port) {
+        (Some(main_service), Some(main_port)) => (main_service, main_port),
+        _ => return None,
+    };
+    if diagnostics.iter().any(Diagnostic::is_error) {
+        return None;
     }
 
-    convert_volumes(&app.services, &permissions, &mut spec)?;
-
     let mut main_port_host: Option<u16> = None;
     if let Some(converted_map) = converted_port_map {
         main_port_host = Some(
@@ -527,23 +756,64 @@ pub fn convert_config(
     };
 
     // And we're done
-    Ok(result)
+    Some(result)
+}
+
+/// Convert an app, stopping at the first error
+/// Returns the result and the warnings found while converting it
+pub fn convert_config(
+    app_name: &str,
+    app: types::AppYml,
+    port_map: &Option<Map<String, Value>>,
+    installed_services: &Option<Vec<String>>,
+    resource_policy: &Option<ResourcePolicy>,
+    permission_registry: &Option<PermissionRegistry>,
+) -> Result<(ResultYml, Vec<Diagnostic>), Error> {
+    let mut diagnostics = Vec::<Diagnostic>::new();
+    let result = convert_with_diagnostics(
+        app_name,
+        app,
+        port_map,
+        installed_services,
+        resource_policy,
+        permission_registry,
+        &mut diagnostics,
+    );
+    match result {
+        Some(result) if !diagnostics.iter().any(Diagnostic::is_error) => Ok((result, diagnostics)),
+        _ => Err(first_error(diagnostics).unwrap_or(Error::ConversionFailed)),
+    }
+}
+
+/// Validate an app without stopping at the first problem
+/// Returns all errors and warnings, the app is valid if none of them is an error
+pub fn validate_config(app_name: &str, app: types::AppYml) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::<Diagnostic>::new();
+    convert_with_diagnostics(app_name, app, &None, &None, &None, &None, &mut diagnostics);
+    diagnostics
 }
 
 #[cfg(test)]
 mod test {
-    use super::convert_config;
+    use super::{convert_config, get_hidden_services, validate_config};
     use crate::{
         bmap,
         composegenerator::{
-            output::types::{ComposeSpecification, NetworkEntry, Service},
+            compose::types::{Command, StringOrIntOrBool},
+            output::types::{
+                ComposeSpecification, Dependency, DependencyCondition, DependsOn, Healthcheck,
+                NetworkEntry, Service,
+            },
+            tor::TorConfig,
             types::{Metadata, Permissions, ResultYml},
-            v4::types::{AppYml, Container},
+            v4::types::{AppYml, Container, HiddenServices, Mounts},
         },
-        map,
+        diagnostics::Diagnostic,
+        error::Error,
     };
 
     use pretty_assertions::assert_eq;
+    use std::collections::BTreeMap;
 
     #[test]
     fn test_simple_app() {
@@ -556,18 +826,18 @@ mod test {
                 version: "1.0.0".to_string(),
                 category: "Example category".to_string(),
                 tagline: "The only example app for Citadel you will ever need".to_string(),
-                developers: map! {
+                developers: bmap! {
                     "Citadel team".to_string() => "runcitadel.space".to_string()
                 },
                 permissions: vec![Permissions::OneDependency("lnd".to_string())],
-                repo: map! {
+                repo: bmap! {
                     "Example repo".to_string() => "https://github.com/runcitadel/app-cli".to_string()
                 },
                 support: "https://t.me/citadeldevelopers".to_string(),
                 description: "This is an example app that provides multiple features that you need on your node. These features include:\n\n- Example\n- Example\n- Example".to_string(),
                 ..Default::default()
             },
-            services: map! {
+            services: bmap! {
                 "main" => Container {
                     image: "ghcr.io/runcitadel/example:main".to_string(),
                     user: Some("1000:1000".to_string()),
@@ -582,23 +852,25 @@ mod test {
                 }
             }
         };
-        let result = convert_config("example-app", example_app, &None, &None);
+        let result = convert_config("example-app", example_app, &None, &None, &None, &None);
         assert!(result.is_ok());
         let expected_result = ResultYml {
             port: 3000,
-            new_tor_entries: "HiddenServiceDir /var/lib/tor/app-example-app\nHiddenServicePort 80 <app-example-app-main-ip>:3000\n".to_string(),
+            new_tor_entries: TorConfig::parse("HiddenServiceDir /var/lib/tor/app-example-app\nHiddenServicePort 80 <app-example-app-main-ip>:3000\n").unwrap(),
             spec: ComposeSpecification {
                 services: Some(bmap! {
                     "main" => Service {
                         image: Some("ghcr.io/runcitadel/example:main".to_string()),
                         user: Some("1000:1000".to_string()),
-                        depends_on: Some(vec!["database".to_string()]),
+                        depends_on: Some(DependsOn::List(vec!["database".to_string()])),
                         ports: vec!["3000:3000".to_string()],
                         networks: Some(bmap! {
                             "default" => NetworkEntry {
                                 ipv4_address: Some("$APP_EXAMPLE_APP_MAIN_IP".to_string())
                             }
                         }),
+                        cap_drop: Some(vec!["ALL".to_string()]),
+                        security_opt: Some(vec!["no-new-privileges:true".to_string()]),
                         ..Default::default()
                     },
                     "database" => Service {
@@ -609,11 +881,12 @@ mod test {
                                 ipv4_address: Some("$APP_EXAMPLE_APP_DATABASE_IP".to_string())
                             }
                         }),
+                        cap_drop: Some(vec!["ALL".to_string()]),
+                        security_opt: Some(vec!["no-new-privileges:true".to_string()]),
                         ..Default::default()
                     }
                 }),
                 ..Default::default()
-
             },
             metadata: Metadata {
                 id: Some("example-app".to_string()),
@@ -621,11 +894,11 @@ mod test {
                 version: "1.0.0".to_string(),
                 category: "Example category".to_string(),
                 tagline: "The only example app for Citadel you will ever need".to_string(),
-                developers: map! {
+                developers: bmap! {
                     "Citadel team".to_string() => "runcitadel.space".to_string()
                 },
                 permissions: vec![Permissions::OneDependency("lnd".to_string())],
-                repo: map! {
+                repo: bmap! {
                     "Example repo".to_string() => "https://github.com/runcitadel/app-cli".to_string()
                 },
                 support: "https://t.me/citadeldevelopers".to_string(),
@@ -635,6 +908,385 @@ mod test {
                 ..Default::default()
             },
         };
-        assert_eq!(expected_result, result.unwrap());
+        assert_eq!(expected_result, result.unwrap().0);
+    }
+
+    #[test]
+    fn sorted_tor_entries() {
+        let services = bmap! {
+            "web" => Container {
+                hidden_services: Some(HiddenServices::LayeredMap(bmap! {
+                    "rpc" => BTreeMap::from([(9001, 9001), (9000, 9000)]),
+                    "api" => BTreeMap::from([(8080, 80)])
+                })),
+                ..Default::default()
+            },
+            "main" => Container {
+                hidden_services: Some(HiddenServices::PortMap(BTreeMap::from([(8333, 8333), (8080, 8080)]))),
+                ..Default::default()
+            }
+        };
+        assert_eq!(
+            get_hidden_services("example-app", services, "main", 3000).to_string(),
+            "HiddenServiceDir /var/lib/tor/app-example-app
+HiddenServicePort 80 <app-example-app-main-ip>:3000
+HiddenServicePort 8080 <app-example-app-main-ip>:8080
+HiddenServicePort 8333 <app-example-app-main-ip>:8333
+HiddenServiceDir /var/lib/tor/app-example-app-api
+HiddenServicePort 8080 <app-example-app-web-ip>:80
+HiddenServiceDir /var/lib/tor/app-example-app-rpc
+HiddenServicePort 9000 <app-example-app-web-ip>:9000
+HiddenServicePort 9001 <app-example-app-web-ip>:9001
+"
+        );
+    }
+
+    #[test]
+    fn client_authorization() {
+        let mut app: AppYml = serde_yaml::from_str(
+            "citadel_version: 4
+metadata:
+  name: Example app
+  version: 1.0.0
+  category: Example category
+  tagline: Example
+  developers: {}
+  description: Example
+  repo: {}
+  support: https://t.me/citadeldevelopers
+services:
+  main:
+    image: ghcr.io/runcitadel/example:main
+    port: 3000
+    hidden_services:
+      admin:
+        ports:
+          80: 8080
+        authorized_clients:
+          - phone
+        num_introduction_points: 5
+",
+        )
+        .unwrap();
+        let (result, _) =
+            convert_config("example-app", app.clone(), &None, &None, &None, &None).unwrap();
+        assert_eq!(
+            result.new_tor_entries.to_string(),
+            "HiddenServiceDir /var/lib/tor/app-example-app
+HiddenServicePort 80 <app-example-app-main-ip>:3000
+HiddenServiceDir /var/lib/tor/app-example-app-admin
+# AuthorizedClient phone
+HiddenServiceVersion 3
+HiddenServiceNumIntroductionPoints 5
+HiddenServicePort 80 <app-example-app-main-ip>:8080
+"
+        );
+
+        if let Some(HiddenServices::Detailed(definitions)) =
+            &mut app.services.get_mut("main").unwrap().hidden_services
+        {
+            definitions.get_mut("admin").unwrap().authorized_clients = vec!["../phone".to_string()];
+        }
+        assert_eq!(
+            validate_config("example-app", app),
+            vec![Diagnostic::error(
+                "services.main.hidden_services.admin.authorized_clients.0",
+                Error::InvalidAuthorizedClient {
+                    client: "../phone".to_string(),
+                    service: "main".to_string(),
+                },
+            )]
+        );
+    }
+
+    #[test]
+    fn security_defaults() {
+        let mut app: AppYml = serde_yaml::from_str(
+            "citadel_version: 4
+metadata:
+  name: Example app
+  version: 1.0.0
+  category: Example category
+  tagline: Example
+  developers: {}
+  description: Example
+  repo: {}
+  support: https://t.me/citadeldevelopers
+services:
+  main:
+    image: ghcr.io/runcitadel/example:main
+    port: 3000
+    security:
+      read_only: true
+      tmpfs:
+        - /tmp
+        - run:size=64m
+      drop_capabilities: false
+",
+        )
+        .unwrap();
+        assert_eq!(
+            validate_config("example-app", app.clone()),
+            vec![
+                Diagnostic::error(
+                    "services.main.security.drop_capabilities",
+                    Error::SecurityOptOutWithoutPermission {
+                        option: "drop_capabilities".to_string(),
+                        service: "main".to_string(),
+                    },
+                ),
+                Diagnostic::error(
+                    "services.main.security.tmpfs.1",
+                    Error::InvalidTmpfs {
+                        path: "run:size=64m".to_string(),
+                        service: "main".to_string(),
+                    },
+                ),
+            ]
+        );
+
+        app.metadata.permissions = vec![Permissions::OneDependency("privileges".to_string())];
+        app.services
+            .get_mut("main")
+            .unwrap()
+            .security
+            .as_mut()
+            .unwrap()
+            .tmpfs = vec!["/tmp".to_string(), "/run:size=64m".to_string()];
+        let (result, _) = convert_config("example-app", app, &None, &None, &None, &None).unwrap();
+        let main = &result.spec.services.unwrap()["main"];
+        assert_eq!(main.cap_drop, None);
+        assert_eq!(
+            main.security_opt,
+            Some(vec!["no-new-privileges:true".to_string()])
+        );
+        assert_eq!(main.read_only, Some(true));
+        assert_eq!(
+            main.tmpfs,
+            Some(vec!["/tmp".to_string(), "/run:size=64m".to_string()])
+        );
+    }
+
+    #[test]
+    fn healthchecks() {
+        let healthcheck = Healthcheck {
+            test: Command::ArrayCommand(vec![
+                "CMD".to_string(),
+                "pg_isready".to_string(),
+                "--host=$APP_EXAMPLE_APP_DATABASE_IP".to_string(),
+            ]),
+            interval: Some("30s".to_string()),
+            timeout: Some("5s".to_string()),
+            start_period: None,
+            retries: Some(3),
+        };
+        let mut example_app = AppYml {
+            citadel_version: 4,
+            services: bmap! {
+                "main" => Container {
+                    image: "ghcr.io/runcitadel/example:main".to_string(),
+                    depends_on: Some(vec!["database".to_string(), "cache".to_string()]),
+                    port: Some(3000),
+                    ..Default::default()
+                },
+                "database" => Container {
+                    image: "ghcr.io/runcitadel/example-db:main".to_string(),
+                    healthcheck: Some(healthcheck.clone()),
+                    ..Default::default()
+                },
+                "cache" => Container {
+                    image: "ghcr.io/runcitadel/example-cache:main".to_string(),
+                    ..Default::default()
+                }
+            },
+            ..Default::default()
+        };
+        let result = convert_config(
+            "example-app",
+            example_app.clone(),
+            &None,
+            &None,
+            &None,
+            &None,
+        )
+        .unwrap()
+        .0;
+        let services = result.spec.services.unwrap();
+        assert_eq!(
+            services["main"].depends_on,
+            Some(DependsOn::Conditions(bmap! {
+                "cache" => Dependency { condition: DependencyCondition::ServiceStarted },
+                "database" => Dependency { condition: DependencyCondition::ServiceHealthy }
+            }))
+        );
+        assert_eq!(services["database"].healthcheck, Some(healthcheck.clone()));
+
+        example_app
+            .services
+            .get_mut("database")
+            .unwrap()
+            .healthcheck = Some(Healthcheck {
+            test: Command::ArrayCommand(vec![
+                "pg_isready".to_string(),
+                "$BITCOIN_RPC_PASS".to_string(),
+            ]),
+            interval: Some("1s".to_string()),
+            ..healthcheck
+        });
+        assert_eq!(
+            validate_config("example-app", example_app),
+            vec![
+                Diagnostic::error(
+                    "services.database.healthcheck.test",
+                    Error::EnvVarNotAllowed {
+                        env_var: "BITCOIN_RPC_PASS".to_string(),
+                        service: "database".to_string(),
+                    },
+                ),
+                Diagnostic::error(
+                    "services.database.healthcheck.test",
+                    Error::InvalidHealthcheck {
+                        service: "database".to_string(),
+                        message: "test has to start with CMD or CMD-SHELL".to_string(),
+                    },
+                ),
+                Diagnostic::error(
+                    "services.database.healthcheck.interval",
+                    Error::InvalidHealthcheck {
+                        service: "database".to_string(),
+                        message: "interval has to be between 5s and 3600s".to_string(),
+                    },
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn return_warnings() {
+        let example_app = AppYml {
+            citadel_version: 4,
+            metadata: Metadata {
+                name: "Example app".to_string(),
+                permissions: vec![Permissions::OneDependency("electrum".to_string())],
+                ..Default::default()
+            },
+            services: bmap! {
+                "main" => Container {
+                    image: "ghcr.io/runcitadel/example:main".to_string(),
+                    port: Some(3000),
+                    command: Some(Command::SimpleCommand("run $ELECTRUM_IP".to_string())),
+                    environment: Some(bmap! {
+                        "ELECTRUM" => StringOrIntOrBool::String("$ELECTRUM_IP:$ELECTRUM_PORT".to_string())
+                    }),
+                    network_mode: Some("host".to_string()),
+                    ..Default::default()
+                }
+            },
+        };
+        let deprecated = |path: &str, env_var: &str| {
+            Diagnostic::warning(
+                path,
+                Error::DeprecatedEnvVar {
+                    env_var: env_var.to_string(),
+                    service: "main".to_string(),
+                    message: "Environment variables starting with ELECTRUM_ are deprecated. Please use APP_ELECTRUM_* instead".to_string(),
+                },
+            )
+        };
+        let (_, warnings) =
+            convert_config("example-app", example_app, &None, &None, &None, &None).unwrap();
+        assert_eq!(
+            warnings,
+            vec![
+                deprecated("services.main.command", "ELECTRUM_IP"),
+                deprecated("services.main.environment.ELECTRUM", "ELECTRUM_IP"),
+                deprecated("services.main.environment.ELECTRUM", "ELECTRUM_PORT"),
+                Diagnostic::warning(
+                    "services.main.network_mode",
+                    Error::NetworkModeWithoutPermission {
+                        service: "main".to_string(),
+                    },
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn collect_all_problems() {
+        let example_app = AppYml {
+            citadel_version: 4,
+            metadata: Metadata {
+                name: "Example app".to_string(),
+                ..Default::default()
+            },
+            services: bmap! {
+                "main" => Container {
+                    image: "ghcr.io/runcitadel/example:main".to_string(),
+                    command: Some(Command::SimpleCommand("run --rpc $BITCOIN_RPC_PASS".to_string())),
+                    environment: Some(bmap! {
+                        "LND" => StringOrIntOrBool::String("$LND_IP".to_string())
+                    }),
+                    network_mode: Some("host".to_string()),
+                    ..Default::default()
+                },
+                "database" => Container {
+                    image: "ghcr.io/runcitadel/example-db:main".to_string(),
+                    port: Some(5432),
+                    mounts: Some(Mounts {
+                        data: Some(bmap! {
+                            "../escape" => "/data".to_string()
+                        }),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }
+            },
+        };
+        let expected = vec![
+            Diagnostic::error(
+                "services.main.command",
+                Error::EnvVarNotAllowed {
+                    env_var: "BITCOIN_RPC_PASS".to_string(),
+                    service: "main".to_string(),
+                },
+            ),
+            Diagnostic::error(
+                "services.main.environment.LND",
+                Error::EnvVarNotAllowed {
+                    env_var: "LND_IP".to_string(),
+                    service: "main".to_string(),
+                },
+            ),
+            Diagnostic::warning(
+                "services.main.network_mode",
+                Error::NetworkModeWithoutPermission {
+                    service: "main".to_string(),
+                },
+            ),
+            Diagnostic::error(
+                "services.database.port",
+                Error::PortOnNonMainContainer {
+                    service: "database".to_string(),
+                },
+            ),
+            Diagnostic::error(
+                "services.database.mounts.data.../escape",
+                Error::InvalidMount {
+                    path: "../escape".to_string(),
+                    service: "database".to_string(),
+                },
+            ),
+        ];
+        assert_eq!(
+            validate_config("example-app", example_app.clone()),
+            expected
+        );
+        assert_eq!(
+            convert_config("example-app", example_app, &None, &None, &None, &None).err(),
+            Some(Error::EnvVarNotAllowed {
+                env_var: "BITCOIN_RPC_PASS".to_string(),
+                service: "main".to_string(),
+            })
+        );
     }
 }
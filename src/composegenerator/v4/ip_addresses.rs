@@ -0,0 +1,295 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+
+use super::types::AppYml;
+use super::utils::ip_env_var;
+use crate::error::Error;
+
+/// The IP addresses of every container of an app
+pub type AppIpAddresses = BTreeMap<String, Ipv4Addr>;
+/// The IP addresses of every app, this is the format the allocation is saved in
+pub type IpAllocation = BTreeMap<String, AppIpAddresses>;
+
+/// The subnet app containers get their IP addresses from by default
+pub const DEFAULT_SUBNET: &str = "10.21.0.0/16";
+/// The part of the default subnet that is used by Citadel's own containers
+pub const DEFAULT_RESERVED_RANGE: &str = "10.21.21.0/24";
+
+/// An IPv4 subnet like 10.21.0.0/16
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Subnet {
+    pub address: Ipv4Addr,
+    pub prefix_length: u8,
+}
+
+impl Subnet {
+    fn mask(&self) -> u32 {
+        u32::MAX
+            .checked_shl(32 - self.prefix_length as u32)
+            .unwrap_or(0)
+    }
+
+    fn network(&self) -> u32 {
+        u32::from(self.address) & self.mask()
+    }
+
+    fn broadcast(&self) -> u32 {
+        self.network() | !self.mask()
+    }
+
+    pub fn contains(&self, address: Ipv4Addr) -> bool {
+        u32::from(address) & self.mask() == self.network()
+    }
+
+    /// The addresses that can be assigned to containers
+    /// The network address, the gateway (the first address) and the broadcast address are excluded
+    pub fn hosts(&self) -> impl Iterator<Item = Ipv4Addr> {
+        let first = self.network().saturating_add(2);
+        let last = self.broadcast().saturating_sub(1);
+        (first..=last).map(Ipv4Addr::from)
+    }
+
+    /// Check if an address can be assigned to a container
+    pub fn is_host(&self, address: Ipv4Addr) -> bool {
+        let address = u32::from(address);
+        address >= self.network().saturating_add(2) && address < self.broadcast()
+    }
+
+    /// The gateway Docker uses for a network with this subnet
+    pub fn gateway(&self) -> Ipv4Addr {
+        Ipv4Addr::from(self.network() + 1)
+    }
+}
+
+impl FromStr for Subnet {
+    type Err = Error;
+
+    fn from_str(subnet: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidSubnet(subnet.to_string());
+        let (address, prefix_length) = subnet.split_once('/').ok_or_else(invalid)?;
+        let address = address.parse::<Ipv4Addr>().map_err(|_| invalid())?;
+        let prefix_length = prefix_length.parse::<u8>().map_err(|_| invalid())?;
+        if prefix_length > 32 {
+            return Err(invalid());
+        }
+        Ok(Subnet {
+            address,
+            prefix_length,
+        })
+    }
+}
+
+impl fmt::Display for Subnet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix_length)
+    }
+}
+
+/// Assign an IP address to every container of a set of installed apps
+///
+/// Addresses from the previous allocation are kept if they are still in the subnet and not
+/// reserved. If the previous allocation assigned an address twice, only the first container keeps it.
+/// Containers with networking disabled do not get an address.
+///
+/// Apps in the previous allocation that are not passed keep their addresses, so only some apps
+/// can be allocated again.
+pub fn allocate_ip_addresses(
+    apps: &BTreeMap<String, AppYml>,
+    previous: &IpAllocation,
+    subnet: &Subnet,
+    reserved: &[Subnet],
+) -> Result<IpAllocation, Error> {
+    let is_usable = |address: Ipv4Addr| {
+        subnet.is_host(address) && !reserved.iter().any(|range| range.contains(address))
+    };
+    // Apps that are not allocated again keep their previous addresses
+    let mut allocation: IpAllocation = previous
+        .iter()
+        .filter(|(app_name, _)| !apps.contains_key(*app_name))
+        .map(|(app_name, addresses)| (app_name.clone(), addresses.clone()))
+        .collect();
+    let mut used: HashSet<Ipv4Addr> = allocation
+        .values()
+        .flat_map(|addresses| addresses.values().copied())
+        .collect();
+    let mut missing = Vec::<(&str, &str)>::new();
+    for (app_name, app) in apps {
+        let mut services: Vec<&String> = app
+            .services
+            .iter()
+            .filter(|(_, service)| service.enable_networking.unwrap_or(true))
+            .map(|(service_name, _)| service_name)
+            .collect();
+        services.sort();
+        for service_name in services {
+            let previous_address = previous
+                .get(app_name)
+                .and_then(|addresses| addresses.get(service_name))
+                .copied()
+                .filter(|address| is_usable(*address) && !used.contains(address));
+            match previous_address {
+                Some(address) => {
+                    used.insert(address);
+                    allocation
+                        .entry(app_name.clone())
+                        .or_default()
+                        .insert(service_name.clone(), address);
+                }
+                None => missing.push((app_name, service_name)),
+            }
+        }
+    }
+    let mut free_addresses = subnet
+        .hosts()
+        .filter(|address| is_usable(*address) && !used.contains(address));
+    for (app_name, service_name) in missing {
+        let address = free_addresses
+            .next()
+            .ok_or_else(|| Error::NoFreeIpAddress {
+                service: service_name.to_string(),
+                subnet: subnet.to_string(),
+            })?;
+        allocation
+            .entry(app_name.to_string())
+            .or_default()
+            .insert(service_name.to_string(), address);
+    }
+    Ok(allocation)
+}
+
+/// Load a saved IP address allocation
+pub fn load_ip_allocation<R>(reader: R) -> Result<IpAllocation, Error>
+where
+    R: std::io::Read,
+{
+    serde_json::from_reader(reader).map_err(|error| Error::InvalidIpAllocation(error.to_string()))
+}
+
+/// Get the contents of an env file that defines the IP address env var of every container
+pub fn to_env_file(allocation: &IpAllocation) -> String {
+    let mut result = String::new();
+    for (app_name, addresses) in allocation {
+        for (service_name, address) in addresses {
+            result += &format!("{}={}\n", ip_env_var(app_name, service_name), address);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+    use std::net::Ipv4Addr;
+
+    use super::{allocate_ip_addresses, to_env_file, IpAllocation, Subnet};
+    use crate::composegenerator::v4::types::{AppYml, Container};
+    use crate::error::Error;
+
+    fn app(services: &[&str]) -> AppYml {
+        AppYml {
+            citadel_version: 4,
+            services: services
+                .iter()
+                .map(|service| (service.to_string(), Container::default()))
+                .collect::<BTreeMap<_, _>>(),
+            ..Default::default()
+        }
+    }
+
+    fn subnet(subnet: &str) -> Subnet {
+        subnet.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_subnets() {
+        assert_eq!(
+            subnet("10.21.0.0/16"),
+            Subnet {
+                address: Ipv4Addr::new(10, 21, 0, 0),
+                prefix_length: 16
+            }
+        );
+        assert!(subnet("10.21.0.0/16").contains(Ipv4Addr::new(10, 21, 22, 3)));
+        assert!(!subnet("10.21.0.0/16").contains(Ipv4Addr::new(10, 22, 0, 3)));
+        assert_eq!(
+            "10.21.0.0/33".parse::<Subnet>(),
+            Err(Error::InvalidSubnet("10.21.0.0/33".to_string()))
+        );
+    }
+
+    #[test]
+    fn allocate_addresses() {
+        let apps = BTreeMap::from([
+            ("example-app".to_string(), app(&["main", "database"])),
+            ("other".to_string(), app(&["web"])),
+        ]);
+        let allocation = allocate_ip_addresses(
+            &apps,
+            &IpAllocation::new(),
+            &subnet("10.21.0.0/16"),
+            &[subnet("10.21.0.0/24")],
+        )
+        .unwrap();
+        assert_eq!(
+            to_env_file(&allocation),
+            "APP_EXAMPLE_APP_DATABASE_IP=10.21.1.0\nAPP_EXAMPLE_APP_MAIN_IP=10.21.1.1\nAPP_OTHER_WEB_IP=10.21.1.2\n"
+        );
+    }
+
+    #[test]
+    fn keep_previous_addresses_and_fix_collisions() {
+        let apps = BTreeMap::from([
+            ("a".to_string(), app(&["main"])),
+            ("b".to_string(), app(&["main"])),
+            ("c".to_string(), app(&["main"])),
+        ]);
+        let previous = IpAllocation::from([
+            (
+                "a".to_string(),
+                BTreeMap::from([("main".to_string(), Ipv4Addr::new(10, 21, 0, 10))]),
+            ),
+            (
+                "b".to_string(),
+                BTreeMap::from([("main".to_string(), Ipv4Addr::new(10, 21, 0, 10))]),
+            ),
+            (
+                "c".to_string(),
+                BTreeMap::from([("main".to_string(), Ipv4Addr::new(192, 168, 0, 2))]),
+            ),
+        ]);
+        let allocation =
+            allocate_ip_addresses(&apps, &previous, &subnet("10.21.0.0/24"), &[]).unwrap();
+        assert_eq!(allocation["a"]["main"], Ipv4Addr::new(10, 21, 0, 10));
+        assert_eq!(allocation["b"]["main"], Ipv4Addr::new(10, 21, 0, 2));
+        assert_eq!(allocation["c"]["main"], Ipv4Addr::new(10, 21, 0, 3));
+    }
+
+    #[test]
+    fn keep_apps_that_are_not_allocated_again() {
+        let apps = BTreeMap::from([("a".to_string(), app(&["main"]))]);
+        let previous = IpAllocation::from([(
+            "b".to_string(),
+            BTreeMap::from([("main".to_string(), Ipv4Addr::new(10, 21, 0, 2))]),
+        )]);
+        let allocation =
+            allocate_ip_addresses(&apps, &previous, &subnet("10.21.0.0/24"), &[]).unwrap();
+        assert_eq!(allocation["a"]["main"], Ipv4Addr::new(10, 21, 0, 3));
+        assert_eq!(allocation["b"]["main"], Ipv4Addr::new(10, 21, 0, 2));
+    }
+
+    #[test]
+    fn detect_exhaustion() {
+        let apps = BTreeMap::from([("a".to_string(), app(&["main", "web"]))]);
+        let result =
+            allocate_ip_addresses(&apps, &IpAllocation::new(), &subnet("10.21.0.0/30"), &[]);
+        assert_eq!(
+            result,
+            Err(Error::NoFreeIpAddress {
+                service: "web".to_string(),
+                subnet: "10.21.0.0/30".to_string(),
+            })
+        );
+    }
+}
@@ -1,5 +1,9 @@
 pub mod convert;
+pub mod ip_addresses;
 pub mod permissions;
+pub mod port_conflicts;
+pub mod portmap;
+pub mod resources;
 pub mod types;
 #[cfg(feature = "docker")]
 pub mod update;
@@ -1,76 +1,459 @@
-pub const BITCOIN_ENV_VARS: [&str; 10] = [
-    "BITCOIN_IP",
-    "BITCOIN_P2P_PORT",
-    "BITCOIN_RPC_PORT",
-    "BITCOIN_RPC_USER",
-    "BITCOIN_RPC_PASS",
-    "BITCOIN_RPC_AUTH",
-    "BITCOIN_ZMQ_RAWBLOCK_PORT",
-    "BITCOIN_ZMQ_RAWTX_PORT",
-    "BITCOIN_ZMQ_HASHBLOCK_PORT",
-    "BITCOIN_ZMQ_SEQUENCE_PORT",
-];
-
-pub const LND_ENV_VARS: [&str; 3] = ["LND_IP", "LND_GRPC_PORT", "LND_REST_PORT"];
-
-pub const ELECTRUM_ENV_VARS: [&str; 2] = ["ELECTRUM_IP", "ELECTRUM_PORT"];
-
-pub const C_LIGHTNING_ENV_VARS: [&str; 1] = ["C_LIGHTNING_IP"];
-
-pub const ALWAYS_ALLOWED_ENV_VARS: [&str; 11] = [
-    "TOR_PROXY_IP",
-    "TOR_PROXY_PORT",
-    "APP_DOMAIN",
-    "APP_HIDDEN_SERVICE",
-    "BITCOIN_NETWORK",
-    "APP_SEED",
-    "APP_SEED_1",
-    "APP_SEED_2",
-    "APP_SEED_3",
-    "APP_SEED_4",
-    "APP_SEED_5",
-];
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Display;
 
-pub fn is_allowed_by_permissions(app_id: &str, env_var: &str, permissions: &[String]) -> bool {
-    if ALWAYS_ALLOWED_ENV_VARS.contains(&env_var) {
-        return true;
-    } else if env_var.starts_with("BITCOIN") {
-        return permissions.contains(&"bitcoind".to_string())
-            && BITCOIN_ENV_VARS.contains(&env_var);
-    } else if env_var.starts_with("LND") {
-        return permissions.contains(&"lnd".to_string()) && LND_ENV_VARS.contains(&env_var);
-    } else if env_var.starts_with("ELECTRUM") {
-        println!("Warning: Environment variables starting with ELECTRUM_ are deprecated. Please use APP_ELECTRUM_* instead");
-        return permissions.contains(&"electrum".to_string())
-            && ELECTRUM_ENV_VARS.contains(&env_var);
-    } else if env_var.starts_with("C_LIGHTNING") {
-        return permissions.contains(&"c-lightning".to_string())
-            && C_LIGHTNING_ENV_VARS.contains(&env_var);
-    } else if env_var.starts_with("APP_HIDDEN_SERVICE_") || env_var.starts_with("APP_SEED") {
-        return true;
-    } else if env_var.starts_with("APP_") {
-        let mut split_result: Vec<&str> = env_var.split('_').collect();
-        // Remove the APP_
-        split_result.remove(0);
-        // Remove the _IP / _PORT / _SHAREDSECRET
-        split_result.pop();
-        while app_id != split_result.join("-").to_lowercase()
-            && !permissions.contains(&split_result.join("-").to_lowercase())
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+
+use super::types::{AppYml, Container};
+use crate::composegenerator::compose::types::{Command, StringOrIntOrBool};
+use crate::composegenerator::types::Permissions;
+use crate::diagnostics::{service_path, Diagnostic};
+use crate::error::Error;
+use crate::utils::{find_env_vars, flatten};
+
+/// The registry that is used if no other registry is given
+const BUILTIN_REGISTRY: &str = include_str!("permissions.yml");
+
+lazy_static! {
+    static ref BUILTIN: PermissionRegistry = load_permission_registry(BUILTIN_REGISTRY.as_bytes())
+        .expect("Built-in permission registry is invalid");
+}
+
+/// Why an app can or can not use something
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "grant", content = "permissions", rename_all = "snake_case")]
+pub enum Grant {
+    /// Every app can use it
+    Always,
+    /// It belongs to the app itself
+    OwnApp,
+    /// A permission the app requests grants it
+    Permission(String),
+    /// One of these permissions would grant it, but the app requests none of them
+    MissingPermission(Vec<String>),
+    /// No permission grants it
+    Denied,
+}
+
+impl Grant {
+    pub fn is_allowed(&self) -> bool {
+        matches!(self, Grant::Always | Grant::OwnApp | Grant::Permission(_))
+    }
+}
+
+/// Something an app uses that may require a permission
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "type", content = "name", rename_all = "snake_case")]
+pub enum Resource {
+    EnvVar(String),
+    Mount(String),
+    Capability(String),
+    NetworkMode,
+    /// Disabling one of the security defaults
+    SecurityOptOut(String),
+}
+
+impl Display for Resource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Resource::EnvVar(env_var) => write!(f, "Env var {}", env_var),
+            Resource::Mount(mount) => write!(f, "Mount {}", mount),
+            Resource::Capability(capability) => write!(f, "Capability {}", capability),
+            Resource::NetworkMode => write!(f, "network_mode"),
+            Resource::SecurityOptOut(option) => write!(f, "Disabling {}", option),
+        }
+    }
+}
+
+/// Where an app uses something and why it is allowed or not
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct PermissionUse {
+    /// The key path of the value, for example services.main.environment.FOO
+    pub path: String,
+    pub resource: Resource,
+    pub grant: Grant,
+}
+
+impl Display for PermissionUse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {} ", self.path, self.resource)?;
+        match &self.grant {
+            Grant::Always => write!(f, "is available to every app"),
+            Grant::OwnApp => write!(f, "belongs to the app itself"),
+            Grant::Permission(permission) => {
+                write!(f, "is granted by the {} permission", permission)
+            }
+            Grant::MissingPermission(permissions) => {
+                write!(f, "needs the {} permission", permissions.join(" or the "))
+            }
+            Grant::Denied => write!(f, "is not granted by any permission"),
+        }
+    }
+}
+
+/// Which permissions an app needs for what it uses
+#[derive(Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PermissionReport {
+    pub uses: Vec<PermissionUse>,
+    /// Requested permissions that nothing the app uses needs
+    pub unused: Vec<Permissions>,
+}
+
+/// What an app gets access to with a permission
+#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Permission {
+    /// Env vars starting with this can only be used with this permission
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub env_var_prefix: Option<String>,
+    /// The env vars with the prefix the permission grants access to
+    #[serde(default)]
+    pub env_vars: Vec<String>,
+    /// The mounts the permission grants access to
+    #[serde(default)]
+    pub mounts: Vec<String>,
+    /// The capabilities the permission allows adding
+    #[serde(default)]
+    pub capabilities: Vec<String>,
+    /// If set, using the env vars of this permission prints this warning
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deprecated: Option<String>,
+}
+
+/// Which env vars, mounts and capabilities each permission grants
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct PermissionRegistry {
+    /// Env vars every app can use
+    #[serde(default)]
+    pub always_allowed_env_vars: Vec<String>,
+    /// Env vars starting with one of these can be used by every app
+    #[serde(default)]
+    pub always_allowed_env_var_prefixes: Vec<String>,
+    /// Permission name -> what it grants
+    #[serde(default)]
+    pub permissions: BTreeMap<String, Permission>,
+}
+
+impl Default for PermissionRegistry {
+    fn default() -> Self {
+        Self::builtin().clone()
+    }
+}
+
+impl PermissionRegistry {
+    /// The registry that matches the services built into Citadel
+    pub fn builtin() -> &'static PermissionRegistry {
+        &BUILTIN
+    }
+
+    /// Check if an app can use an env var with the permissions it has
+    pub fn is_env_var_allowed(&self, app_id: &str, env_var: &str, permissions: &[String]) -> bool {
+        self.env_var_grant(app_id, env_var, permissions)
+            .is_allowed()
+    }
+
+    /// Get the deprecation message for an env var, if the permission it belongs to is deprecated
+    pub fn env_var_deprecation(&self, env_var: &str) -> Option<&str> {
+        self.permissions
+            .values()
+            .find(|permission| {
+                permission
+                    .env_var_prefix
+                    .as_ref()
+                    .is_some_and(|prefix| env_var.starts_with(prefix.as_str()))
+            })
+            .and_then(|permission| permission.deprecated.as_deref())
+    }
+
+    /// Explain why an app can or can not use an env var with the permissions it has
+    pub fn env_var_grant(&self, app_id: &str, env_var: &str, permissions: &[String]) -> Grant {
+        if self
+            .always_allowed_env_vars
+            .iter()
+            .any(|allowed| allowed == env_var)
         {
-            // Remove stuff until we hit the end of the value
+            return Grant::Always;
+        }
+        for (name, permission) in &self.permissions {
+            let has_prefix = permission
+                .env_var_prefix
+                .as_ref()
+                .is_some_and(|prefix| env_var.starts_with(prefix.as_str()));
+            if !has_prefix {
+                continue;
+            }
+            return if !permission.env_vars.iter().any(|allowed| allowed == env_var) {
+                Grant::Denied
+            } else if permissions.contains(name) {
+                Grant::Permission(name.to_owned())
+            } else {
+                Grant::MissingPermission(vec![name.to_owned()])
+            };
+        }
+        if self
+            .always_allowed_env_var_prefixes
+            .iter()
+            .any(|prefix| env_var.starts_with(prefix.as_str()))
+        {
+            return Grant::Always;
+        }
+        if env_var.starts_with("APP_") {
+            let mut split_result: Vec<&str> = env_var.split('_').collect();
+            // Remove the APP_
+            split_result.remove(0);
+            // Remove the _IP / _PORT / _SHAREDSECRET
             split_result.pop();
-            if split_result.is_empty() {
-                return false;
+            let mut candidates = Vec::<String>::new();
+            // Remove stuff until we hit the end of the value
+            while !split_result.is_empty() {
+                let name = split_result.join("-").to_lowercase();
+                if app_id == name {
+                    return Grant::OwnApp;
+                } else if permissions.contains(&name) {
+                    return Grant::Permission(name);
+                }
+                candidates.push(name);
+                split_result.pop();
+            }
+            if !candidates.is_empty() {
+                return Grant::MissingPermission(candidates);
             }
         }
-        return true;
+        Grant::Denied
+    }
+
+    /// Explain why a service can or can not use a mount
+    pub fn mount_grant(&self, mount: &str, permissions: &[String]) -> Grant {
+        permission_grant(self.mount_permission(mount), permissions)
     }
-    false
+
+    /// Explain why a service can or can not add a capability
+    pub fn capability_grant(&self, capability: &str, permissions: &[String]) -> Grant {
+        permission_grant(self.capability_permission(capability), permissions)
+    }
+
+    /// Get the permission that grants access to a mount
+    pub fn mount_permission(&self, mount: &str) -> Option<&str> {
+        self.permissions
+            .iter()
+            .find(|(_, permission)| permission.mounts.iter().any(|allowed| allowed == mount))
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Get the permission that allows adding a capability
+    pub fn capability_permission(&self, capability: &str) -> Option<&str> {
+        self.permissions
+            .iter()
+            .find(|(_, permission)| {
+                permission
+                    .capabilities
+                    .iter()
+                    .any(|allowed| allowed.eq_ignore_ascii_case(capability))
+            })
+            .map(|(name, _)| name.as_str())
+    }
+}
+
+fn permission_grant(permission: Option<&str>, permissions: &[String]) -> Grant {
+    match permission {
+        Some(permission) if permissions.iter().any(|p| p == permission) => {
+            Grant::Permission(permission.to_string())
+        }
+        Some(permission) => Grant::MissingPermission(vec![permission.to_string()]),
+        None => Grant::Denied,
+    }
+}
+
+/// Load a permission registry
+pub fn load_permission_registry<R>(reader: R) -> Result<PermissionRegistry, Error>
+where
+    R: std::io::Read,
+{
+    serde_yaml::from_reader(reader)
+        .map_err(|error| Error::InvalidPermissionRegistry(error.to_string()))
+}
+
+/// Find what a service uses that may require a permission
+fn find_uses(
+    app_id: &str,
+    service_name: &str,
+    service: &Container,
+    permissions: &[String],
+    registry: &PermissionRegistry,
+    uses: &mut Vec<PermissionUse>,
+) {
+    let mut add_env_vars = |key: &str, values: &[String]| {
+        let path = service_path(service_name, key);
+        let env_vars: BTreeSet<&str> = values
+            .iter()
+            .flat_map(|value| find_env_vars(value))
+            .collect();
+        for env_var in env_vars {
+            uses.push(PermissionUse {
+                path: path.clone(),
+                resource: Resource::EnvVar(env_var.to_string()),
+                grant: registry.env_var_grant(app_id, env_var, permissions),
+            });
+        }
+    };
+    let command_values = |command: &Command| match command {
+        Command::SimpleCommand(command) => vec![command.to_owned()],
+        Command::ArrayCommand(values) => values.to_owned(),
+    };
+    if let Some(entrypoint) = &service.entrypoint {
+        add_env_vars("entrypoint", &command_values(entrypoint));
+    }
+    if let Some(command) = &service.command {
+        add_env_vars("command", &command_values(command));
+    }
+    if let Some(healthcheck) = &service.healthcheck {
+        add_env_vars("healthcheck.test", &command_values(&healthcheck.test));
+    }
+    for (key, value) in service.environment.iter().flatten() {
+        if let StringOrIntOrBool::String(value) = value {
+            add_env_vars(&format!("environment.{}", key), &[value.to_owned()]);
+        }
+    }
+    if let Some(mounts) = &service.mounts {
+        for (mount, is_used) in [
+            ("bitcoin", mounts.bitcoin.is_some()),
+            ("lnd", mounts.lnd.is_some()),
+            ("c_lightning", mounts.c_lightning.is_some()),
+        ] {
+            if is_used {
+                uses.push(PermissionUse {
+                    path: service_path(service_name, &format!("mounts.{}", mount)),
+                    resource: Resource::Mount(mount.to_string()),
+                    grant: registry.mount_grant(mount, permissions),
+                });
+            }
+        }
+    }
+    for (index, capability) in service.cap_add.iter().flatten().enumerate() {
+        uses.push(PermissionUse {
+            path: service_path(service_name, &format!("cap_add.{}", index)),
+            resource: Resource::Capability(capability.to_owned()),
+            grant: registry.capability_grant(capability, permissions),
+        });
+    }
+    if service.network_mode.is_some() {
+        uses.push(PermissionUse {
+            path: service_path(service_name, "network_mode"),
+            resource: Resource::NetworkMode,
+            grant: permission_grant(Some("network"), permissions),
+        });
+    }
+    let security = service.security.clone().unwrap_or_default();
+    for (option, value) in [
+        ("no_new_privileges", security.no_new_privileges),
+        ("drop_capabilities", security.drop_capabilities),
+    ] {
+        if value == Some(false) {
+            uses.push(PermissionUse {
+                path: service_path(service_name, &format!("security.{}", option)),
+                resource: Resource::SecurityOptOut(option.to_string()),
+                grant: permission_grant(Some("privileges"), permissions),
+            });
+        }
+    }
+}
+
+/// Explain which permission allows (or would allow) everything an app uses, and find the permissions it does not need
+pub fn explain_permissions(
+    app_id: &str,
+    app: &AppYml,
+    registry: &PermissionRegistry,
+) -> PermissionReport {
+    let permissions = flatten(app.metadata.permissions.clone());
+    let mut uses = Vec::<PermissionUse>::new();
+    for (service_name, service) in &app.services {
+        find_uses(
+            app_id,
+            service_name,
+            service,
+            &permissions,
+            registry,
+            &mut uses,
+        );
+    }
+    let used = used_permissions(&uses);
+    let unused = app
+        .metadata
+        .permissions
+        .iter()
+        .filter(|permission| match permission {
+            Permissions::OneDependency(dependency) => !used.contains(dependency.as_str()),
+            Permissions::AlternativeDependency(alternatives) => alternatives
+                .iter()
+                .all(|dependency| !used.contains(dependency.as_str())),
+        })
+        .cloned()
+        .collect();
+    PermissionReport { uses, unused }
+}
+
+/// Get the permissions that grant something an app uses
+fn used_permissions(uses: &[PermissionUse]) -> BTreeSet<&str> {
+    uses.iter()
+        .filter_map(|permission_use| match &permission_use.grant {
+            Grant::Permission(permission) => Some(permission.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Warn about requested permissions the app does not need, users should not have to approve more than necessary
+pub fn check_unused_permissions(
+    app_id: &str,
+    app: &AppYml,
+    registry: &PermissionRegistry,
+) -> Vec<Diagnostic> {
+    let report = explain_permissions(app_id, app, registry);
+    let used = used_permissions(&report.uses);
+    let mut diagnostics = Vec::new();
+    for (index, permission) in app.metadata.permissions.iter().enumerate() {
+        let path = format!("metadata.permissions.{}", index);
+        let alternatives = match permission {
+            Permissions::OneDependency(dependency) => std::slice::from_ref(dependency),
+            Permissions::AlternativeDependency(alternatives) => alternatives.as_slice(),
+        };
+        let (used_alternatives, unused_alternatives): (Vec<String>, Vec<String>) = alternatives
+            .iter()
+            .cloned()
+            .partition(|alternative| used.contains(alternative.as_str()));
+        if used_alternatives.is_empty() {
+            diagnostics.push(Diagnostic::warning(
+                path,
+                Error::UnusedPermission(alternatives.join(" or ")),
+            ));
+        } else if !unused_alternatives.is_empty() {
+            diagnostics.push(Diagnostic::warning(
+                path,
+                Error::UnusedAlternatives {
+                    used: used_alternatives,
+                    unused: unused_alternatives,
+                },
+            ));
+        }
+    }
+    diagnostics
+}
+
+pub fn is_allowed_by_permissions(app_id: &str, env_var: &str, permissions: &[String]) -> bool {
+    PermissionRegistry::builtin().is_env_var_allowed(app_id, env_var, permissions)
 }
 
 #[cfg(test)]
 mod test {
-    use super::is_allowed_by_permissions;
+    use super::{
+        check_unused_permissions, explain_permissions, is_allowed_by_permissions,
+        load_permission_registry, Grant, PermissionRegistry, PermissionUse, Resource,
+    };
+    use crate::composegenerator::types::Permissions;
+    use crate::composegenerator::v4::types::AppYml;
+    use crate::diagnostics::Diagnostic;
+    use crate::error::Error;
 
     #[test]
     fn allow_access_to_own_vars() {
@@ -128,4 +511,150 @@ mod test {
             &["electrum".to_string()]
         ));
     }
+
+    #[test]
+    fn custom_registry() {
+        let registry = load_permission_registry(
+            "permissions:
+  fulcrum:
+    env_var_prefix: FULCRUM
+    env_vars:
+      - FULCRUM_IP
+    mounts:
+      - fulcrum
+"
+            .as_bytes(),
+        )
+        .unwrap();
+        let permissions = ["fulcrum".to_string()];
+        assert!(registry.is_env_var_allowed("example-app", "FULCRUM_IP", &permissions));
+        assert!(!registry.is_env_var_allowed("example-app", "FULCRUM_PASS", &permissions));
+        assert!(!registry.is_env_var_allowed("example-app", "FULCRUM_IP", &[]));
+        assert!(!registry.is_env_var_allowed("example-app", "BITCOIN_NETWORK", &[]));
+        assert_eq!(registry.mount_permission("fulcrum"), Some("fulcrum"));
+        assert_eq!(
+            PermissionRegistry::builtin().capability_permission("CAP_NET_RAW"),
+            None
+        );
+        assert_eq!(
+            PermissionRegistry::builtin().capability_permission("CAP-NET-RAW"),
+            Some("network")
+        );
+    }
+
+    #[test]
+    fn explain_app_permissions() {
+        let app: AppYml = serde_yaml::from_str(
+            "citadel_version: 4
+metadata:
+  name: Example app
+  version: 1.0.0
+  category: Example category
+  tagline: Example
+  developers:
+    Citadel team: runcitadel.space
+  description: Example
+  permissions:
+    - lnd
+    - network
+    - - electrum
+      - fulcrum
+  repo:
+    Public: https://github.com/runcitadel/example
+  support: https://t.me/citadeldevelopers
+services:
+  main:
+    image: ghcr.io/runcitadel/example:main
+    port: 3000
+    command: --lnd=$LND_IP --other=${APP_OTHER_APP_IP}
+    mounts:
+      bitcoin: /bitcoin
+",
+        )
+        .unwrap();
+        let report = explain_permissions("example-app", &app, PermissionRegistry::builtin());
+        assert_eq!(
+            report.uses,
+            vec![
+                PermissionUse {
+                    path: "services.main.command".to_string(),
+                    resource: Resource::EnvVar("APP_OTHER_APP_IP".to_string()),
+                    grant: Grant::MissingPermission(vec![
+                        "other-app".to_string(),
+                        "other".to_string()
+                    ]),
+                },
+                PermissionUse {
+                    path: "services.main.command".to_string(),
+                    resource: Resource::EnvVar("LND_IP".to_string()),
+                    grant: Grant::Permission("lnd".to_string()),
+                },
+                PermissionUse {
+                    path: "services.main.mounts.bitcoin".to_string(),
+                    resource: Resource::Mount("bitcoin".to_string()),
+                    grant: Grant::MissingPermission(vec!["bitcoind".to_string()]),
+                },
+            ]
+        );
+        assert_eq!(
+            report.uses[0].to_string(),
+            "services.main.command: Env var APP_OTHER_APP_IP needs the other-app or the other permission"
+        );
+        assert_eq!(
+            report.unused,
+            vec![
+                Permissions::OneDependency("network".to_string()),
+                Permissions::AlternativeDependency(vec![
+                    "electrum".to_string(),
+                    "fulcrum".to_string()
+                ]),
+            ]
+        );
+    }
+
+    #[test]
+    fn unused_permissions() {
+        let app: AppYml = serde_yaml::from_str(
+            "citadel_version: 4
+metadata:
+  name: Example app
+  version: 1.0.0
+  category: Example category
+  tagline: Example
+  developers:
+    Citadel team: runcitadel.space
+  description: Example
+  permissions:
+    - bitcoind
+    - - electrum
+      - fulcrum
+  repo:
+    Public: https://github.com/runcitadel/example
+  support: https://t.me/citadeldevelopers
+services:
+  main:
+    image: ghcr.io/runcitadel/example:main
+    port: 3000
+    environment:
+      ELECTRUM: $APP_ELECTRUM_IP
+",
+        )
+        .unwrap();
+        assert_eq!(
+            check_unused_permissions("example-app", &app, PermissionRegistry::builtin()),
+            vec![
+                Diagnostic::warning(
+                    "metadata.permissions.0",
+                    Error::UnusedPermission("bitcoind".to_string())
+                ),
+                Diagnostic::warning(
+                    "metadata.permissions.1",
+                    Error::UnusedAlternatives {
+                        used: vec!["electrum".to_string()],
+                        unused: vec!["fulcrum".to_string()],
+                    }
+                ),
+            ]
+        );
+    }
 }
@@ -0,0 +1,250 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use super::types::{AppYml, PortPriority};
+use crate::diagnostics::{service_path, Diagnostic};
+use crate::error::Error;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Protocol::Tcp => write!(f, "tcp"),
+            Protocol::Udp => write!(f, "udp"),
+        }
+    }
+}
+
+/// A host port an app wants to use
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PortUsage {
+    pub app: String,
+    pub service: String,
+    pub protocol: Protocol,
+    pub port: u16,
+    pub priority: PortPriority,
+    /// The key path of the port in the app's app.yml
+    pub path: String,
+}
+
+/// A host port that is wanted by more than one service
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PortConflict {
+    pub protocol: Protocol,
+    pub port: u16,
+    pub users: Vec<PortUsage>,
+    /// The next port no app in the store uses
+    pub suggestion: Option<u16>,
+}
+
+/// Get all host ports the apps want to use on a fixed port
+///
+/// Ports in required_ports are always required, the main port only counts if its port_priority is
+/// Required or Recommended, otherwise it can be mapped to any port.
+pub fn port_usage(apps: &BTreeMap<String, AppYml>) -> Vec<PortUsage> {
+    let mut usage = Vec::<PortUsage>::new();
+    for (app_name, app) in apps {
+        for (service_name, service) in &app.services {
+            if let (Some(port), Some(priority)) = (service.port, &service.port_priority) {
+                if *priority != PortPriority::Optional {
+                    usage.push(PortUsage {
+                        app: app_name.clone(),
+                        service: service_name.clone(),
+                        protocol: Protocol::Tcp,
+                        port,
+                        priority: priority.clone(),
+                        path: service_path(service_name, "port"),
+                    });
+                }
+            }
+            let required_ports = match &service.required_ports {
+                Some(required_ports) => required_ports,
+                None => continue,
+            };
+            for (protocol, ports) in [
+                (Protocol::Tcp, &required_ports.tcp),
+                (Protocol::Udp, &required_ports.udp),
+            ] {
+                for host_port in ports.iter().flat_map(|ports| ports.keys()) {
+                    usage.push(PortUsage {
+                        app: app_name.clone(),
+                        service: service_name.clone(),
+                        protocol,
+                        port: *host_port,
+                        priority: PortPriority::Required,
+                        path: service_path(
+                            service_name,
+                            &format!("required_ports.{}.{}", protocol, host_port),
+                        ),
+                    });
+                }
+            }
+        }
+    }
+    usage
+}
+
+/// Find all host ports that are wanted by multiple services in a store
+pub fn find_port_conflicts(apps: &BTreeMap<String, AppYml>) -> Vec<PortConflict> {
+    let usage = port_usage(apps);
+    let mut by_port = BTreeMap::<(Protocol, u16), Vec<PortUsage>>::new();
+    for entry in usage {
+        by_port
+            .entry((entry.protocol, entry.port))
+            .or_default()
+            .push(entry);
+    }
+    let used_ports: BTreeSet<(Protocol, u16)> = by_port.keys().copied().collect();
+    by_port
+        .into_iter()
+        .filter(|(_, users)| users.len() > 1)
+        .map(|((protocol, port), users)| PortConflict {
+            protocol,
+            port,
+            users,
+            suggestion: (port.saturating_add(1)..=u16::MAX)
+                .find(|candidate| !used_ports.contains(&(protocol, *candidate))),
+        })
+        .collect()
+}
+
+impl PortConflict {
+    /// Two services both require this port, so they can never be installed together
+    pub fn is_error(&self) -> bool {
+        self.users
+            .iter()
+            .filter(|user| user.priority == PortPriority::Required)
+            .count()
+            > 1
+    }
+
+    /// Get the diagnostics for every service that may not get this port, with the app they belong to
+    ///
+    /// Services that require the port only get a diagnostic if another service requires it too,
+    /// services that only recommend it get a warning.
+    pub fn diagnostics(&self) -> Vec<(String, Diagnostic)> {
+        let mut diagnostics = Vec::new();
+        for (index, user) in self.users.iter().enumerate() {
+            let others = self
+                .users
+                .iter()
+                .enumerate()
+                .filter(|(other_index, _)| *other_index != index)
+                .map(|(_, other)| other);
+            let error = Error::PortConflict {
+                port: self.port,
+                protocol: self.protocol.to_string(),
+                service: user.service.clone(),
+                others: others
+                    .clone()
+                    .map(|other| format!("{} ({})", other.app, other.service))
+                    .collect(),
+                suggestion: self.suggestion,
+            };
+            let diagnostic = if user.priority != PortPriority::Required {
+                Diagnostic::warning(&user.path, error)
+            } else if others
+                .clone()
+                .any(|other| other.priority == PortPriority::Required)
+            {
+                Diagnostic::error(&user.path, error)
+            } else {
+                continue;
+            };
+            diagnostics.push((user.app.clone(), diagnostic));
+        }
+        diagnostics
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use super::{find_port_conflicts, Protocol};
+    use crate::composegenerator::v4::types::{AppYml, Container, PortPriority, PortsDefinition};
+    use crate::error::Error;
+
+    fn app(port: Option<(u16, PortPriority)>, tcp: &[u16], udp: &[u16]) -> AppYml {
+        let ports = |ports: &[u16]| Some(ports.iter().map(|port| (*port, *port)).collect());
+        AppYml {
+            citadel_version: 4,
+            services: BTreeMap::from([(
+                "main".to_string(),
+                Container {
+                    port: port.as_ref().map(|(port, _)| *port),
+                    port_priority: port.map(|(_, priority)| priority),
+                    required_ports: Some(PortsDefinition {
+                        tcp: ports(tcp),
+                        udp: ports(udp),
+                    }),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn detect_required_port_conflicts() {
+        let apps = BTreeMap::from([
+            ("app-a".to_string(), app(None, &[9735], &[51820])),
+            ("app-b".to_string(), app(None, &[9735, 9736], &[])),
+            ("app-c".to_string(), app(None, &[], &[9735])),
+        ]);
+        let conflicts = find_port_conflicts(&apps);
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].protocol, Protocol::Tcp);
+        assert_eq!(conflicts[0].port, 9735);
+        assert_eq!(conflicts[0].suggestion, Some(9737));
+        assert!(conflicts[0].is_error());
+        let diagnostics = conflicts[0].diagnostics();
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].0, "app-a");
+        assert_eq!(
+            diagnostics[0].1.path,
+            "services.main.required_ports.tcp.9735"
+        );
+        assert_eq!(
+            diagnostics[0].1.error,
+            Error::PortConflict {
+                port: 9735,
+                protocol: "tcp".to_string(),
+                service: "main".to_string(),
+                others: vec!["app-b (main)".to_string()],
+                suggestion: Some(9737),
+            }
+        );
+    }
+
+    #[test]
+    fn warn_about_recommended_ports() {
+        let apps = BTreeMap::from([
+            (
+                "app-a".to_string(),
+                app(Some((3000, PortPriority::Required)), &[], &[]),
+            ),
+            (
+                "app-b".to_string(),
+                app(Some((3000, PortPriority::Recommended)), &[], &[]),
+            ),
+            (
+                "app-c".to_string(),
+                app(Some((3000, PortPriority::Optional)), &[], &[]),
+            ),
+        ]);
+        let conflicts = find_port_conflicts(&apps);
+        assert_eq!(conflicts.len(), 1);
+        assert!(!conflicts[0].is_error());
+        let diagnostics = conflicts[0].diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].0, "app-b");
+        assert_eq!(diagnostics[0].1.path, "services.main.port");
+        assert!(!diagnostics[0].1.is_error());
+    }
+}
@@ -0,0 +1,367 @@
+use std::collections::{hash_map::Entry, BTreeMap, HashMap};
+
+use super::types::{AppYml, PortMapElement, PortPriority};
+use super::utils::get_main_container;
+use crate::diagnostics::Diagnostic;
+use crate::error::Error;
+
+/// The ports of every container of an app
+pub type AppPortMap = BTreeMap<String, Vec<PortMapElement>>;
+/// The ports of every app, this is the format of the port map file
+pub type PortMap = BTreeMap<String, AppPortMap>;
+
+/// Ports used by Citadel itself, these are never assigned to apps
+pub const RESERVED_PORTS: &[u16] = &[
+    22, 53, 80, 443, 2100, 3001, 3002, 3003, 3004, 3005, 8080, 8332, 8333, 8334, 9050, 9051, 9735,
+    10009, 28332, 28333, 28334, 28335, 50001, 50002,
+];
+
+/// The first port that is used for apps which do not care about their outside port
+pub const FIRST_DYNAMIC_PORT: u16 = 4000;
+
+/// The port the main container of an app wants
+struct PortRequest {
+    app: String,
+    service: String,
+    /// The port inside the container, None if the app gets its port from an env var
+    port: Option<u16>,
+    priority: PortPriority,
+}
+
+impl PortRequest {
+    fn element(&self, public_port: u16) -> PortMapElement {
+        PortMapElement {
+            dynamic: self.port.is_none(),
+            internal_port: self.port.unwrap_or(public_port),
+            public_port,
+        }
+    }
+
+    /// The public port this container had in a previous port map, if it is still usable
+    fn previous_port(&self, previous: &PortMap) -> Option<u16> {
+        previous
+            .get(&self.app)?
+            .get(&self.service)?
+            .iter()
+            .find(|element| match self.port {
+                Some(port) => !element.dynamic && element.internal_port == port,
+                None => element.dynamic,
+            })
+            .map(|element| element.public_port)
+    }
+}
+
+fn owner_name(app: &str, service: &str) -> String {
+    format!("{} ({})", app, service)
+}
+
+/// Generate a port map for a set of installed apps
+///
+/// Required ports are always assigned to the port defined in the app.yml. Apart from that,
+/// ports assigned in the previous port map are kept, so apps do not move when other apps are
+/// installed. Recommended ports are used if they are free, everything else gets the next free port
+/// after FIRST_DYNAMIC_PORT.
+///
+/// Apps in the previous port map that are not passed keep their ports, so only some apps can be
+/// regenerated. The same applies to apps without a main container, they are skipped with a
+/// diagnostic. Returns the port map and the diagnostics of every skipped app.
+pub fn generate_port_map(
+    apps: &BTreeMap<String, AppYml>,
+    previous: &PortMap,
+    reserved_ports: &[u16],
+) -> Result<(PortMap, Vec<(String, Diagnostic)>), Error> {
+    // Which app uses which host port
+    let mut used_ports = HashMap::<u16, String>::new();
+    for port in reserved_ports {
+        used_ports.insert(*port, "Citadel".to_string());
+    }
+    let mut diagnostics = Vec::<(String, Diagnostic)>::new();
+    let mut requests = Vec::<PortRequest>::with_capacity(apps.len());
+    for (app_name, app) in apps {
+        match get_main_container(app) {
+            Ok(main_container) => {
+                let definition = app.services.get(&main_container).unwrap();
+                requests.push(PortRequest {
+                    app: app_name.clone(),
+                    service: main_container,
+                    port: definition.port,
+                    priority: match definition.port {
+                        Some(_) => definition
+                            .port_priority
+                            .clone()
+                            .unwrap_or(PortPriority::Optional),
+                        None => PortPriority::Optional,
+                    },
+                });
+            }
+            Err(error) => diagnostics.push((app_name.clone(), Diagnostic::error("", error))),
+        }
+    }
+    // Apps that are not regenerated keep their previous ports
+    let mut port_map: PortMap = previous
+        .iter()
+        .filter(|(app_name, _)| !requests.iter().any(|request| &request.app == *app_name))
+        .map(|(app_name, ports)| (app_name.clone(), ports.clone()))
+        .collect();
+    for (app_name, ports) in &port_map {
+        for (service_name, elements) in ports {
+            for element in elements {
+                used_ports
+                    .entry(element.public_port)
+                    .or_insert_with(|| owner_name(app_name, service_name));
+            }
+        }
+    }
+    for (app_name, app) in apps {
+        for (service_name, service) in &app.services {
+            let tcp_ports = service
+                .required_ports
+                .as_ref()
+                .and_then(|required_ports| required_ports.tcp.as_ref());
+            for host_port in tcp_ports.into_iter().flat_map(|ports| ports.keys()) {
+                used_ports
+                    .entry(*host_port)
+                    .or_insert_with(|| owner_name(app_name, service_name));
+            }
+        }
+    }
+
+    let mut assigned = BTreeMap::<usize, u16>::new();
+    // Required ports first, they can not be moved
+    for (index, request) in requests.iter().enumerate() {
+        if request.priority != PortPriority::Required {
+            continue;
+        }
+        let port = request.port.unwrap();
+        if let Some(owner) = used_ports.get(&port) {
+            return Err(Error::PortConflict {
+                port,
+                protocol: "tcp".to_string(),
+                service: request.service.clone(),
+                others: vec![owner.clone()],
+                suggestion: None,
+            });
+        }
+        used_ports.insert(port, owner_name(&request.app, &request.service));
+        assigned.insert(index, port);
+    }
+    // Then keep the previous assignments
+    for (index, request) in requests.iter().enumerate() {
+        if assigned.contains_key(&index) {
+            continue;
+        }
+        if let Some(port) = request.previous_port(previous) {
+            if let Entry::Vacant(entry) = used_ports.entry(port) {
+                entry.insert(owner_name(&request.app, &request.service));
+                assigned.insert(index, port);
+            }
+        }
+    }
+    // Then try to give apps their recommended ports
+    for (index, request) in requests.iter().enumerate() {
+        if assigned.contains_key(&index) || request.priority != PortPriority::Recommended {
+            continue;
+        }
+        let port = request.port.unwrap();
+        if let Entry::Vacant(entry) = used_ports.entry(port) {
+            entry.insert(owner_name(&request.app, &request.service));
+            assigned.insert(index, port);
+        }
+    }
+    // Every other app gets the next free port
+    for (index, request) in requests.iter().enumerate() {
+        if assigned.contains_key(&index) {
+            continue;
+        }
+        let port = (FIRST_DYNAMIC_PORT..=u16::MAX)
+            .find(|port| !used_ports.contains_key(port))
+            .ok_or_else(|| Error::NoFreePort {
+                service: request.service.clone(),
+            })?;
+        used_ports.insert(port, owner_name(&request.app, &request.service));
+        assigned.insert(index, port);
+    }
+
+    for (index, request) in requests.iter().enumerate() {
+        port_map.entry(request.app.clone()).or_default().insert(
+            request.service.clone(),
+            vec![request.element(assigned[&index])],
+        );
+    }
+    Ok((port_map, diagnostics))
+}
+
+/// Load a port map file
+pub fn load_port_map<R>(reader: R) -> Result<PortMap, Error>
+where
+    R: std::io::Read,
+{
+    serde_json::from_reader(reader).map_err(|error| Error::InvalidPortMap(error.to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use super::{generate_port_map, PortMap};
+    use crate::composegenerator::v4::types::{
+        AppYml, Container, PortMapElement, PortPriority, PortsDefinition,
+    };
+    use crate::diagnostics::Diagnostic;
+    use crate::error::Error;
+
+    fn app(port: Option<u16>, priority: Option<PortPriority>) -> AppYml {
+        AppYml {
+            citadel_version: 4,
+            services: BTreeMap::from([(
+                "main".to_string(),
+                Container {
+                    port,
+                    port_priority: priority,
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        }
+    }
+
+    fn element(internal_port: u16, public_port: u16, dynamic: bool) -> Vec<PortMapElement> {
+        vec![PortMapElement {
+            dynamic,
+            internal_port,
+            public_port,
+        }]
+    }
+
+    fn public_port(port_map: &PortMap, app: &str) -> u16 {
+        port_map[app]["main"][0].public_port
+    }
+
+    #[test]
+    fn assign_ports_by_priority() {
+        let apps = BTreeMap::from([
+            ("a-optional".to_string(), app(Some(3000), None)),
+            (
+                "b-recommended".to_string(),
+                app(Some(3000), Some(PortPriority::Recommended)),
+            ),
+            (
+                "c-required".to_string(),
+                app(Some(3000), Some(PortPriority::Required)),
+            ),
+            ("d-dynamic".to_string(), app(None, None)),
+        ]);
+        let (port_map, _) = generate_port_map(&apps, &PortMap::new(), &[4001]).unwrap();
+        assert_eq!(port_map["a-optional"]["main"], element(3000, 4000, false));
+        assert_eq!(public_port(&port_map, "b-recommended"), 4002);
+        assert_eq!(public_port(&port_map, "c-required"), 3000);
+        assert_eq!(port_map["d-dynamic"]["main"], element(4003, 4003, true));
+    }
+
+    #[test]
+    fn keep_previous_assignments() {
+        let apps = BTreeMap::from([
+            ("a".to_string(), app(Some(3000), None)),
+            (
+                "b".to_string(),
+                app(Some(8000), Some(PortPriority::Recommended)),
+            ),
+            ("c".to_string(), app(None, None)),
+        ]);
+        let previous = PortMap::from([
+            (
+                "b".to_string(),
+                BTreeMap::from([("main".to_string(), element(8000, 4000, false))]),
+            ),
+            (
+                "c".to_string(),
+                BTreeMap::from([("main".to_string(), element(5000, 5000, true))]),
+            ),
+        ]);
+        let (port_map, _) = generate_port_map(&apps, &previous, &[]).unwrap();
+        assert_eq!(public_port(&port_map, "a"), 4001);
+        assert_eq!(public_port(&port_map, "b"), 4000);
+        assert_eq!(port_map["c"]["main"], element(5000, 5000, true));
+    }
+
+    #[test]
+    fn avoid_reserved_and_required_ports() {
+        let mut with_required_ports = app(None, None);
+        with_required_ports
+            .services
+            .get_mut("main")
+            .unwrap()
+            .required_ports = Some(PortsDefinition {
+            tcp: Some(BTreeMap::from([(8000, 8000)])),
+            udp: None,
+        });
+        let apps = BTreeMap::from([
+            ("a".to_string(), with_required_ports),
+            (
+                "b".to_string(),
+                app(Some(8000), Some(PortPriority::Recommended)),
+            ),
+            (
+                "c".to_string(),
+                app(Some(80), Some(PortPriority::Recommended)),
+            ),
+        ]);
+        let (port_map, _) = generate_port_map(&apps, &PortMap::new(), &[80]).unwrap();
+        assert_eq!(public_port(&port_map, "b"), 4001);
+        assert_eq!(public_port(&port_map, "c"), 4002);
+    }
+
+    #[test]
+    fn reject_taken_required_ports() {
+        let apps = BTreeMap::from([("a".to_string(), app(Some(80), Some(PortPriority::Required)))]);
+        let result = generate_port_map(&apps, &PortMap::new(), &[80]);
+        assert_eq!(
+            result.err(),
+            Some(Error::PortConflict {
+                port: 80,
+                protocol: "tcp".to_string(),
+                service: "main".to_string(),
+                others: vec!["Citadel".to_string()],
+                suggestion: None,
+            })
+        );
+    }
+
+    #[test]
+    fn keep_apps_that_are_not_regenerated() {
+        // b has no main container, so it is skipped
+        let broken = AppYml {
+            citadel_version: 4,
+            services: BTreeMap::from([
+                ("one".to_string(), Container::default()),
+                ("two".to_string(), Container::default()),
+            ]),
+            ..Default::default()
+        };
+        let apps = BTreeMap::from([
+            ("a".to_string(), app(Some(3000), None)),
+            ("b".to_string(), broken),
+        ]);
+        let previous = PortMap::from([
+            (
+                "b".to_string(),
+                BTreeMap::from([("main".to_string(), element(5000, 5000, true))]),
+            ),
+            (
+                "c".to_string(),
+                BTreeMap::from([("main".to_string(), element(3000, 4000, false))]),
+            ),
+        ]);
+        let (port_map, diagnostics) = generate_port_map(&apps, &previous, &[]).unwrap();
+        assert_eq!(public_port(&port_map, "a"), 4001);
+        assert_eq!(port_map["b"]["main"], element(5000, 5000, true));
+        assert_eq!(port_map["c"]["main"], element(3000, 4000, false));
+        assert_eq!(
+            diagnostics,
+            vec![(
+                "b".to_string(),
+                Diagnostic::error("", Error::NoMainContainer)
+            )]
+        );
+    }
+}
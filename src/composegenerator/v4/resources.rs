@@ -0,0 +1,280 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+use super::types::Resources;
+use super::utils::parse_memory;
+use crate::composegenerator::output::types::{Cpus, Service};
+use crate::diagnostics::{service_path, Diagnostic};
+use crate::error::Error;
+
+/// Resource limits of a node that apply to every app container
+#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq, Eq)]
+pub struct ResourcePolicy {
+    /// Limits for containers that do not request them
+    #[serde(default)]
+    pub defaults: Resources,
+    /// The highest limits a container can request, higher requests are lowered to these
+    #[serde(default)]
+    pub maximum: Resources,
+}
+
+impl ResourcePolicy {
+    /// Limits that prevent a single app from taking down a small node, without restricting apps that request more
+    pub fn recommended() -> Self {
+        ResourcePolicy {
+            defaults: Resources {
+                memory_limit: Some("2g".to_string()),
+                pids_limit: Some(1024),
+                ..Default::default()
+            },
+            maximum: Resources::default(),
+        }
+    }
+}
+
+/// Pick the value for a resource limit: the requested value or the default, but never more than the maximum
+fn limit<T: Clone + Display>(
+    service_name: &str,
+    resource: &str,
+    requested: &Option<T>,
+    default: &Option<T>,
+    maximum: &Option<T>,
+    to_number: impl Fn(&T) -> Option<f64>,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Option<T> {
+    let path = service_path(service_name, &format!("resources.{}", resource));
+    if let Some(requested) = requested {
+        if to_number(requested).is_none() {
+            diagnostics.push(Diagnostic::error(
+                path,
+                Error::InvalidResourceLimit {
+                    service: service_name.to_string(),
+                    resource: resource.to_string(),
+                    value: requested.to_string(),
+                },
+            ));
+            return None;
+        }
+    }
+    let value = requested.as_ref().or(default.as_ref())?;
+    let is_too_high = |maximum: &T| match (to_number(value), to_number(maximum)) {
+        (Some(value), Some(maximum)) => value > maximum,
+        _ => false,
+    };
+    match maximum {
+        Some(maximum) if is_too_high(maximum) => {
+            if requested.is_some() {
+                diagnostics.push(Diagnostic::warning(
+                    path,
+                    Error::ResourceLimitTooHigh {
+                        service: service_name.to_string(),
+                        resource: resource.to_string(),
+                        requested: value.to_string(),
+                        maximum: maximum.to_string(),
+                    },
+                ));
+            }
+            Some(maximum.clone())
+        }
+        _ => Some(value.clone()),
+    }
+}
+
+/// Set the resource limits of a container, based on what the app requests and the node's policy
+pub fn apply_resource_policy(
+    service_name: &str,
+    requested: &Option<Resources>,
+    policy: &ResourcePolicy,
+    result: &mut Service,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let requested = requested.clone().unwrap_or_default();
+    let memory = |memory: &String| parse_memory(memory).map(|bytes| bytes as f64);
+    result.mem_limit = limit(
+        service_name,
+        "memory_limit",
+        &requested.memory_limit,
+        &policy.defaults.memory_limit,
+        &policy.maximum.memory_limit,
+        memory,
+        diagnostics,
+    );
+    result.mem_reservation = limit(
+        service_name,
+        "memory_reservation",
+        &requested.memory_reservation,
+        &policy.defaults.memory_reservation,
+        &policy.maximum.memory_reservation,
+        memory,
+        diagnostics,
+    );
+    // Docker refuses to start containers that reserve more memory than their limit
+    if let (Some(reservation), Some(mem_limit)) = (&result.mem_reservation, &result.mem_limit) {
+        let reserved_bytes = parse_memory(reservation).unwrap_or_default();
+        let can_raise_limit = requested.memory_limit.is_none()
+            && policy
+                .maximum
+                .memory_limit
+                .as_ref()
+                .and_then(|maximum| parse_memory(maximum))
+                .is_none_or(|maximum| reserved_bytes <= maximum);
+        if reserved_bytes > parse_memory(mem_limit).unwrap_or(u64::MAX) {
+            if can_raise_limit {
+                result.mem_limit = Some(reservation.clone());
+            } else {
+                diagnostics.push(Diagnostic::error(
+                    service_path(service_name, "resources.memory_reservation"),
+                    Error::MemoryReservationAboveLimit {
+                        service: service_name.to_string(),
+                        reservation: reservation.clone(),
+                        limit: mem_limit.clone(),
+                    },
+                ));
+            }
+        }
+    }
+    result.cpus = limit(
+        service_name,
+        "cpus",
+        &requested.cpus,
+        &policy.defaults.cpus,
+        &policy.maximum.cpus,
+        |cpus: &Cpus| Some(cpus.get()),
+        diagnostics,
+    );
+    result.pids_limit = limit(
+        service_name,
+        "pids_limit",
+        &requested.pids_limit,
+        &policy.defaults.pids_limit,
+        &policy.maximum.pids_limit,
+        |pids: &u32| Some(*pids as f64),
+        diagnostics,
+    );
+}
+
+/// Load a node's resource policy
+pub fn load_resource_policy<R>(reader: R) -> Result<ResourcePolicy, Error>
+where
+    R: std::io::Read,
+{
+    serde_yaml::from_reader(reader).map_err(|error| Error::InvalidResourcePolicy(error.to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{apply_resource_policy, ResourcePolicy};
+    use crate::composegenerator::output::types::{Cpus, Service};
+    use crate::composegenerator::v4::types::Resources;
+    use crate::diagnostics::Diagnostic;
+    use crate::error::Error;
+
+    fn policy() -> ResourcePolicy {
+        ResourcePolicy {
+            defaults: Resources {
+                memory_limit: Some("1g".to_string()),
+                pids_limit: Some(1024),
+                ..Default::default()
+            },
+            maximum: Resources {
+                memory_limit: Some("4g".to_string()),
+                cpus: Cpus::new(2.0),
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn apply_defaults() {
+        let mut result = Service::default();
+        let mut diagnostics = Vec::new();
+        apply_resource_policy("main", &None, &policy(), &mut result, &mut diagnostics);
+        assert_eq!(result.mem_limit, Some("1g".to_string()));
+        assert_eq!(result.pids_limit, Some(1024));
+        assert_eq!(result.cpus, None);
+        assert!(diagnostics.is_empty());
+    }
+
+    #[test]
+    fn clamp_requests() {
+        let requested = Resources {
+            memory_limit: Some("8g".to_string()),
+            memory_reservation: Some("lots".to_string()),
+            cpus: Cpus::new(1.5),
+            pids_limit: Some(4096),
+        };
+        let mut result = Service::default();
+        let mut diagnostics = Vec::new();
+        apply_resource_policy(
+            "main",
+            &Some(requested),
+            &policy(),
+            &mut result,
+            &mut diagnostics,
+        );
+        assert_eq!(result.mem_limit, Some("4g".to_string()));
+        assert_eq!(result.mem_reservation, None);
+        assert_eq!(result.cpus, Cpus::new(1.5));
+        assert_eq!(result.pids_limit, Some(4096));
+        assert_eq!(
+            diagnostics,
+            vec![
+                Diagnostic::warning(
+                    "services.main.resources.memory_limit",
+                    Error::ResourceLimitTooHigh {
+                        service: "main".to_string(),
+                        resource: "memory_limit".to_string(),
+                        requested: "8g".to_string(),
+                        maximum: "4g".to_string(),
+                    }
+                ),
+                Diagnostic::error(
+                    "services.main.resources.memory_reservation",
+                    Error::InvalidResourceLimit {
+                        service: "main".to_string(),
+                        resource: "memory_reservation".to_string(),
+                        value: "lots".to_string(),
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn keep_memory_reservation_below_limit() {
+        let reserve = |memory_limit: Option<&str>| {
+            let requested = Resources {
+                memory_limit: memory_limit.map(str::to_string),
+                memory_reservation: Some("3g".to_string()),
+                ..Default::default()
+            };
+            let mut result = Service::default();
+            let mut diagnostics = Vec::new();
+            apply_resource_policy(
+                "main",
+                &Some(requested),
+                &ResourcePolicy::recommended(),
+                &mut result,
+                &mut diagnostics,
+            );
+            (result.mem_limit, diagnostics)
+        };
+        // The default limit is raised to what the app reserves
+        assert_eq!(reserve(None), (Some("3g".to_string()), vec![]));
+        assert_eq!(
+            reserve(Some("2g")),
+            (
+                Some("2g".to_string()),
+                vec![Diagnostic::error(
+                    "services.main.resources.memory_reservation",
+                    Error::MemoryReservationAboveLimit {
+                        service: "main".to_string(),
+                        reservation: "3g".to_string(),
+                        limit: "2g".to_string(),
+                    }
+                )]
+            )
+        );
+    }
+}
@@ -1,24 +1,47 @@
 #[cfg(feature = "schema")]
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
 use crate::composegenerator::compose::types::{Command, StringOrIntOrBool};
+use crate::composegenerator::output::types::{Cpus, Healthcheck};
 use crate::composegenerator::types::Metadata;
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 #[cfg_attr(feature = "schema", derive(JsonSchema))]
 #[serde(untagged)]
 pub enum HiddenServices {
-    PortMap(HashMap<u16, u16>),
-    LayeredMap(HashMap<String, HashMap<u16, u16>>),
+    PortMap(BTreeMap<u16, u16>),
+    LayeredMap(BTreeMap<String, BTreeMap<u16, u16>>),
+    /// Hidden services with options like client authorization
+    Detailed(BTreeMap<String, HiddenServiceDefinition>),
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(deny_unknown_fields)]
+pub struct HiddenServiceDefinition {
+    /// Port of the hidden service -> port of the container
+    pub ports: BTreeMap<u16, u16>,
+    /// If set, only these clients can connect to the hidden service
+    /// Their keys are derived from the node's seed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub authorized_clients: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_streams: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_streams_close_circuit: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_introduction_points: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enable_intro_dos_defense: Option<bool>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq, Eq)]
 #[cfg_attr(feature = "schema", derive(JsonSchema))]
 pub struct PortsDefinition {
-    pub tcp: Option<HashMap<u16, u16>>,
-    pub udp: Option<HashMap<u16, u16>>,
+    pub tcp: Option<BTreeMap<u16, u16>>,
+    pub udp: Option<BTreeMap<u16, u16>>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
@@ -42,7 +65,42 @@ pub struct Mounts {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub c_lightning: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub data: Option<HashMap<String, String>>,
+    pub data: Option<BTreeMap<String, String>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(deny_unknown_fields)]
+pub struct Resources {
+    /// The maximum amount of memory the container can use, like 512m or 2g
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory_limit: Option<String>,
+    /// The amount of memory the container should always have available
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory_reservation: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpus: Option<Cpus>,
+    /// The maximum number of processes and threads in the container
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pids_limit: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(deny_unknown_fields)]
+pub struct Security {
+    /// Mount the container's root filesystem read-only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub read_only: Option<bool>,
+    /// Writable in-memory directories, like /tmp or /run:size=64m
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tmpfs: Vec<String>,
+    /// Set to false to allow processes to gain new privileges, requires the privileges permission
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub no_new_privileges: Option<bool>,
+    /// Set to false to keep Docker's default capabilities, requires the privileges permission
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub drop_capabilities: Option<bool>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq, Eq)]
@@ -68,15 +126,21 @@ pub struct Container {
     pub working_dir: Option<String>,
     // These need security checks
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub healthcheck: Option<Healthcheck>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resources: Option<Resources>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub entrypoint: Option<Command>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub command: Option<Command>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub environment: Option<HashMap<String, StringOrIntOrBool>>,
+    pub environment: Option<BTreeMap<String, StringOrIntOrBool>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub cap_add: Option<Vec<String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub network_mode: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub security: Option<Security>,
     // These are not directly present in a compose file and need to be converted
     #[serde(skip_serializing_if = "Option::is_none")]
     pub port: Option<u16>,
@@ -99,7 +163,7 @@ pub struct Container {
 pub struct AppYml {
     pub citadel_version: u8,
     pub metadata: Metadata,
-    pub services: HashMap<String, Container>,
+    pub services: BTreeMap<String, Container>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq, Eq)]
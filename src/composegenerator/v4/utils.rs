@@ -1,54 +1,160 @@
 use std::collections::HashMap;
+use std::time::Duration;
 
 use serde_json::{Map, Value};
 
-use super::permissions;
+use super::permissions::PermissionRegistry;
 use super::types::PortMapElement;
 use crate::composegenerator::compose::types::Command;
+use crate::diagnostics::{first_error, Diagnostic};
+use crate::error::Error;
 use crate::utils::find_env_vars;
 use hex;
 use hmac_sha256::HMAC;
 use serde_json::Value::Object;
 
 pub fn derive_entropy(seed: &str, identifier: &str) -> String {
+    hex::encode(derive_key(seed, identifier))
+}
+
+/// Derive 32 bytes of key material from the seed, used for keys that are not passed to apps as hex
+pub fn derive_key(seed: &str, identifier: &str) -> [u8; 32] {
     let mut hasher = HMAC::new(seed);
     hasher.update(identifier);
-    let result = hasher.finalize();
-    hex::encode(result)
+    hasher.finalize()
+}
+
+/// Get the env var that contains the IP address of a container
+pub fn ip_env_var(app_name: &str, service_name: &str) -> String {
+    format!(
+        "APP_{}_{}_IP",
+        app_name.to_uppercase().replace('-', "_"),
+        service_name.to_uppercase().replace('-', "_")
+    )
+}
+
+/// Parse a duration in the format docker-compose uses, like 1m30s or 500ms
+pub fn parse_duration(duration: &str) -> Option<Duration> {
+    let mut result = Duration::ZERO;
+    let mut rest = duration;
+    if rest.is_empty() {
+        return None;
+    }
+    while !rest.is_empty() {
+        let number_length = rest
+            .find(|char: char| !char.is_ascii_digit() && char != '.')
+            .unwrap_or(rest.len());
+        let number = rest[..number_length].parse::<f64>().ok()?;
+        rest = &rest[number_length..];
+        let unit_length = rest
+            .find(|char: char| char.is_ascii_digit() || char == '.')
+            .unwrap_or(rest.len());
+        let seconds_per_unit = match &rest[..unit_length] {
+            "us" => 0.000_001,
+            "ms" => 0.001,
+            "s" => 1.0,
+            "m" => 60.0,
+            "h" => 3600.0,
+            _ => return None,
+        };
+        rest = &rest[unit_length..];
+        result += Duration::try_from_secs_f64(number * seconds_per_unit).ok()?;
+    }
+    Some(result)
+}
+
+/// Parse an amount of memory in the format docker-compose uses, like 512m or 2g, into bytes
+pub fn parse_memory(memory: &str) -> Option<u64> {
+    let memory = memory.to_lowercase();
+    let memory = memory.strip_suffix('b').unwrap_or(&memory);
+    let (number, factor) = match memory.char_indices().last()? {
+        (index, 'k') => (&memory[..index], 1 << 10),
+        (index, 'm') => (&memory[..index], 1 << 20),
+        (index, 'g') => (&memory[..index], 1 << 30),
+        _ => (memory, 1),
+    };
+    number.parse::<u64>().ok()?.checked_mul(factor)
+}
+
+/// Check the env vars used in a value
+/// Returns errors for env vars the app is not allowed to access and warnings for deprecated ones
+pub fn check_env_vars(
+    app_name: &str,
+    service_name: &str,
+    path: &str,
+    value: &str,
+    permissions: &[String],
+    registry: &PermissionRegistry,
+) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for env_var in find_env_vars(value) {
+        if !registry.is_env_var_allowed(app_name, env_var, permissions) {
+            diagnostics.push(Diagnostic::error(
+                path,
+                Error::EnvVarNotAllowed {
+                    env_var: env_var.to_string(),
+                    service: service_name.to_string(),
+                },
+            ));
+        } else if let Some(message) = registry.env_var_deprecation(env_var) {
+            diagnostics.push(Diagnostic::warning(
+                path,
+                Error::DeprecatedEnvVar {
+                    env_var: env_var.to_string(),
+                    service: service_name.to_string(),
+                    message: message.to_string(),
+                },
+            ));
+        }
+    }
+    diagnostics
+}
+
+/// Check a command for env vars the app is not allowed to access or that are deprecated
+pub fn check_cmd(
+    app_name: &str,
+    service_name: &str,
+    path: &str,
+    command: &Command,
+    permissions: &[String],
+    registry: &PermissionRegistry,
+) -> Vec<Diagnostic> {
+    let values = match command {
+        Command::SimpleCommand(simple_command) => std::slice::from_ref(simple_command),
+        Command::ArrayCommand(values) => values.as_slice(),
+    };
+    values
+        .iter()
+        .flat_map(|value| {
+            check_env_vars(app_name, service_name, path, value, permissions, registry)
+        })
+        .collect()
 }
 
 pub fn validate_cmd(
     app_name: &str,
+    service_name: &str,
     command: &Command,
     permissions: &[String],
-) -> Result<(), String> {
-    match command {
-        Command::SimpleCommand(simple_command) => {
-            let env_vars = find_env_vars(simple_command);
-            for env_var in env_vars {
-                if !permissions::is_allowed_by_permissions(app_name, env_var, permissions) {
-                    return Err(format!("Env var {} not allowed by permissions", env_var));
-                }
-            }
-        }
-        Command::ArrayCommand(values) => {
-            for value in values {
-                let env_vars = find_env_vars(value);
-                for env_var in env_vars {
-                    if !permissions::is_allowed_by_permissions(app_name, env_var, permissions) {
-                        return Err(format!("Env var {} not allowed by permissions", env_var));
-                    }
-                }
-            }
-        }
+    registry: &PermissionRegistry,
+) -> Result<(), Error> {
+    match first_error(check_cmd(
+        app_name,
+        service_name,
+        "",
+        command,
+        permissions,
+        registry,
+    )) {
+        Some(error) => Err(error),
+        None => Ok(()),
     }
-    Ok(())
 }
 
 pub fn get_host_port(port_map: &[PortMapElement], internal_port: u16) -> Option<&PortMapElement> {
-    return port_map
+    port_map
         .iter()
-        .find(|&elem| elem.internal_port == internal_port);
+        .find(|&elem| elem.internal_port == internal_port)
 }
 
 pub fn validate_port_map_app(
@@ -57,7 +163,7 @@ pub fn validate_port_map_app(
     serde_json::from_value::<HashMap<String, Vec<PortMapElement>>>(Object(port_map_app.to_owned()))
 }
 
-pub fn get_main_container(spec: &super::types::AppYml) -> Result<String, String> {
+pub fn get_main_container(spec: &super::types::AppYml) -> Result<String, Error> {
     if spec.services.len() == 1 {
         return Ok(spec.services.keys().next().unwrap().clone());
     }
@@ -72,13 +178,11 @@ pub fn get_main_container(spec: &super::types::AppYml) -> Result<String, String>
             main_service_name = Some(service_name.to_string());
             break;
         } else if service_name.starts_with("main") {
-            if main_service_name.is_some() {
-                log::info!(
-                    "Container {} and {} could both be main container",
-                    service_name,
-                    main_service_name.unwrap()
-                );
-                return Err("Multiple main containers in app!".to_string());
+            if let Some(other_service_name) = main_service_name {
+                return Err(Error::MultipleMainContainers(
+                    other_service_name,
+                    service_name.to_string(),
+                ));
             }
             main_service_name = Some(service_name.to_string());
         }
@@ -86,13 +190,14 @@ pub fn get_main_container(spec: &super::types::AppYml) -> Result<String, String>
     if let Some(main_name) = main_service_name {
         Ok(main_name)
     } else {
-        Err("No main container found!".to_string())
+        Err(Error::NoMainContainer)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use serde_json::json;
+    use std::time::Duration;
 
     #[test]
     fn validate_port_map_app() {
@@ -109,6 +214,35 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn parse_duration() {
+        assert_eq!(super::parse_duration("30s"), Some(Duration::from_secs(30)));
+        assert_eq!(
+            super::parse_duration("1m30s"),
+            Some(Duration::from_secs(90))
+        );
+        assert_eq!(
+            super::parse_duration("500ms"),
+            Some(Duration::from_millis(500))
+        );
+        assert_eq!(
+            super::parse_duration("1.5h"),
+            Some(Duration::from_secs(5400))
+        );
+        assert_eq!(super::parse_duration("30"), None);
+        assert_eq!(super::parse_duration("1d"), None);
+        assert_eq!(super::parse_duration(""), None);
+    }
+
+    #[test]
+    fn parse_memory() {
+        assert_eq!(super::parse_memory("512m"), Some(512 * 1024 * 1024));
+        assert_eq!(super::parse_memory("2GB"), Some(2 * 1024 * 1024 * 1024));
+        assert_eq!(super::parse_memory("1024"), Some(1024));
+        assert_eq!(super::parse_memory("1.5g"), None);
+        assert_eq!(super::parse_memory("lots"), None);
+    }
+
     #[test]
     fn derive_entropy() {
         let result = super::derive_entropy("seed", "identifier");
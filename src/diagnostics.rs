@@ -0,0 +1,183 @@
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::error::{Error, Location};
+use crate::source_map::SourceMap;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// A single problem found while validating an app
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// The key path of the offending value, for example services.main.environment.FOO
+    /// Empty if the problem is not caused by a specific key
+    pub path: String,
+    pub error: Error,
+    /// Where in the app.yml the problem is, if known
+    pub location: Option<Location>,
+    /// How many characters at the location are affected
+    pub length: usize,
+}
+
+impl Diagnostic {
+    pub fn error(path: impl Into<String>, error: Error) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            path: path.into(),
+            location: error.location(),
+            length: 1,
+            error,
+        }
+    }
+
+    pub fn warning(path: impl Into<String>, error: Error) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            path: path.into(),
+            location: error.location(),
+            length: 1,
+            error,
+        }
+    }
+
+    /// The service this diagnostic was caused by, if any
+    pub fn service(&self) -> Option<&str> {
+        self.error.service()
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    /// Set the location of this diagnostic based on its path, unless it already has one
+    pub fn locate(&mut self, source_map: &SourceMap) {
+        if self.location.is_some() {
+            return;
+        }
+        if let Some(span) = source_map.locate(&self.path) {
+            self.location = Some(span.location);
+            self.length = span.length;
+        }
+    }
+
+    /// Render this diagnostic with a snippet of the app.yml, similar to rustc
+    pub fn render(&self, file_name: &str, source: &str) -> String {
+        let mut result = format!("{}: {}\n", self.severity, self.error);
+        let location = match self.location {
+            Some(location) => location,
+            None => {
+                if self.path.is_empty() {
+                    result += &format!("  --> {}\n", file_name);
+                } else {
+                    result += &format!("  --> {} ({})\n", file_name, self.path);
+                }
+                return result;
+            }
+        };
+        let line_number = location.line.to_string();
+        let padding = " ".repeat(line_number.len());
+        result += &format!("{}--> {}:{}\n", padding, file_name, location);
+        if let Some(line) = source.lines().nth(location.line - 1) {
+            result += &format!("{} |\n", padding);
+            result += &format!("{} | {}\n", line_number, line);
+            result += &format!(
+                "{} | {}{}\n",
+                padding,
+                " ".repeat(location.column - 1),
+                "^".repeat(self.length.max(1))
+            );
+        }
+        result
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "{}: {}", self.severity, self.error)
+        } else {
+            write!(f, "{}: {}: {}", self.severity, self.path, self.error)
+        }
+    }
+}
+
+/// Get the key path of a field of a service in an app.yml
+pub fn service_path(service_name: &str, key: &str) -> String {
+    if key.is_empty() {
+        format!("services.{}", service_name)
+    } else {
+        format!("services.{}.{}", service_name, key)
+    }
+}
+
+/// Returns the first error in a list of diagnostics, ignoring warnings
+pub fn first_error(diagnostics: Vec<Diagnostic>) -> Option<Error> {
+    diagnostics
+        .into_iter()
+        .find(Diagnostic::is_error)
+        .map(|diagnostic| diagnostic.error)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{first_error, service_path, Diagnostic};
+    use crate::error::Error;
+    use crate::source_map::SourceMap;
+
+    #[test]
+    fn format_paths() {
+        assert_eq!(service_path("main", ""), "services.main");
+        assert_eq!(
+            service_path("main", "environment.FOO"),
+            "services.main.environment.FOO"
+        );
+    }
+
+    #[test]
+    fn ignore_warnings_when_looking_for_errors() {
+        let diagnostics = vec![
+            Diagnostic::warning(
+                "services.main.network_mode",
+                Error::NetworkModeWithoutPermission {
+                    service: "main".to_string(),
+                },
+            ),
+            Diagnostic::error("services", Error::NoMainContainer),
+        ];
+        assert_eq!(first_error(diagnostics), Some(Error::NoMainContainer));
+    }
+
+    #[test]
+    fn render_snippets() {
+        let source = "citadel_version: 4\nservices:\n  main:\n    environment:\n      A: $LND_IP\n";
+        let mut diagnostic = Diagnostic::error(
+            "services.main.environment.A",
+            Error::EnvVarNotAllowed {
+                env_var: "LND_IP".to_string(),
+                service: "main".to_string(),
+            },
+        );
+        diagnostic.locate(&SourceMap::parse(source));
+        assert_eq!(
+            diagnostic.render("app.yml", source),
+            "error: Env var LND_IP not allowed by permissions (in service main)\n \
+             --> app.yml:5:7\n  |\n5 |       A: $LND_IP\n  |       ^\n"
+        );
+    }
+}
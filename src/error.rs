@@ -0,0 +1,608 @@
+use std::fmt;
+
+/// A position inside an app.yml file, both values are 1-based
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl From<serde_yaml::Location> for Location {
+    fn from(location: serde_yaml::Location) -> Self {
+        Location {
+            line: location.line(),
+            column: location.column(),
+        }
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The app definition could not be read
+    Io(String),
+    /// An app.yml.jinja or config file template could not be rendered
+    Preprocess(String),
+    /// The app definition is not valid YAML
+    Parse {
+        message: String,
+        location: Option<Location>,
+    },
+    /// The app definition is valid YAML, but not a map
+    NotAMap,
+    /// Neither citadel_version nor version is set to a number
+    MissingVersion,
+    /// The app.yml version is not supported by this version of the crate
+    UnsupportedVersion(u64),
+    /// The app definition does not match the schema of its version
+    InvalidDefinition {
+        version: u64,
+        message: String,
+        location: Option<Location>,
+    },
+    /// v3 apps can only be converted if the installed services are known
+    MissingInstalledServices,
+    /// The app could not be converted, but no error was reported
+    ConversionFailed,
+    NoMainContainer,
+    MultipleMainContainers(String, String),
+    /// A service uses an env var it does not have the permission for
+    EnvVarNotAllowed {
+        env_var: String,
+        service: String,
+    },
+    /// A service uses an env var of a deprecated permission
+    DeprecatedEnvVar {
+        env_var: String,
+        service: String,
+        message: String,
+    },
+    /// A service uses an env var that is not set in the node's env
+    UnresolvedEnvVar {
+        env_var: String,
+        service: String,
+    },
+    /// A service uses a capability that is not supported
+    UnknownCapability {
+        capability: String,
+        service: String,
+    },
+    /// A service uses a capability without requesting the permission that allows it
+    CapabilityNotAllowed {
+        capability: String,
+        service: String,
+        permission: String,
+    },
+    /// A service mounts a built-in data directory without requesting the permission for it
+    MountNotAllowed {
+        mount: String,
+        service: String,
+        permission: String,
+    },
+    /// A data mount of a service is not allowed
+    InvalidMount {
+        path: String,
+        service: String,
+    },
+    /// port: was set on a service that is not the main container
+    PortOnNonMainContainer {
+        service: String,
+    },
+    /// The port map could not be parsed
+    InvalidPortMap(String),
+    ContainerNotInPortMap {
+        service: String,
+    },
+    MainPortNotInPortMap {
+        service: String,
+    },
+    /// The main container neither defines a port nor has a dynamic port in the port map
+    MainPortRequired {
+        service: String,
+    },
+    NetworkDisabledForMainContainer {
+        service: String,
+    },
+    /// A service sets network_mode without requesting the network permission
+    NetworkModeWithoutPermission {
+        service: String,
+    },
+    /// A host port of a service is also wanted by other services in the app store
+    PortConflict {
+        port: u16,
+        protocol: String,
+        service: String,
+        others: Vec<String>,
+        suggestion: Option<u16>,
+    },
+    /// The subnet is not in the format 10.21.0.0/16
+    InvalidSubnet(String),
+    /// The saved IP address allocation could not be parsed
+    InvalidIpAllocation(String),
+    /// There is no free IP address left in the subnet to assign to a service
+    NoFreeIpAddress {
+        service: String,
+        subnet: String,
+    },
+    /// There is no free port left to assign to a service
+    NoFreePort {
+        service: String,
+    },
+    /// A torrc could not be parsed
+    InvalidTorConfig {
+        line: usize,
+        message: String,
+    },
+    /// The healthcheck of a service is not valid
+    InvalidHealthcheck {
+        service: String,
+        message: String,
+    },
+    /// A resource limit of a service is not valid
+    InvalidResourceLimit {
+        service: String,
+        resource: String,
+        value: String,
+    },
+    /// A service requests more resources than the node allows
+    ResourceLimitTooHigh {
+        service: String,
+        resource: String,
+        requested: String,
+        maximum: String,
+    },
+    /// A service reserves more memory than its memory limit
+    MemoryReservationAboveLimit {
+        service: String,
+        reservation: String,
+        limit: String,
+    },
+    /// The node's resource policy could not be parsed
+    InvalidResourcePolicy(String),
+    /// The permission registry could not be parsed
+    InvalidPermissionRegistry(String),
+    /// An app requests a permission nothing it uses needs
+    UnusedPermission(String),
+    /// An app requests alternative permissions, but only uses some of them
+    UnusedAlternatives {
+        used: Vec<String>,
+        unused: Vec<String>,
+    },
+    /// A service disables a security default without requesting the privileges permission
+    SecurityOptOutWithoutPermission {
+        option: String,
+        service: String,
+    },
+    /// A field of a docker-compose.yml could not be imported
+    ComposeFieldDropped {
+        field: String,
+        service: String,
+        reason: String,
+    },
+    /// A field of a docker-compose.yml was changed while importing it
+    ComposeFieldTransformed {
+        field: String,
+        service: String,
+        message: String,
+    },
+    /// An Umbrel app uses an env var there is no Citadel equivalent for
+    UnmappedEnvVar {
+        env_var: String,
+        service: String,
+    },
+    /// The mapping for Umbrel apps could not be parsed
+    InvalidUmbrelMapping(String),
+    /// A field of an umbrel-app.yml could not be imported
+    UmbrelFieldDropped {
+        field: String,
+        reason: String,
+    },
+    /// An umbrel-app.yml uses a newer manifest version than the importer knows
+    UnsupportedUmbrelManifestVersion(String),
+    /// A Citadel app uses an env var there is no Umbrel equivalent for
+    NoUmbrelEquivalent {
+        env_var: String,
+        service: String,
+    },
+    /// A field of an app.yml could not be exported to an Umbrel app
+    CitadelFieldDropped {
+        field: String,
+        reason: String,
+    },
+    /// A tmpfs mount of a service is not an absolute path
+    InvalidTmpfs {
+        path: String,
+        service: String,
+    },
+    /// The name of an authorized client of a hidden service can not be used as file name
+    InvalidAuthorizedClient {
+        client: String,
+        service: String,
+    },
+    /// Multiple apps use the same hidden service directory
+    DuplicateHiddenServiceDir {
+        dir: String,
+        apps: Vec<String>,
+    },
+    /// A hidden service points to a container that has no IP address
+    UnresolvedTorPlaceholder(String),
+    /// The current version of an app is not valid semver
+    InvalidAppVersion(String),
+    /// The app repo could not be parsed
+    InvalidRepo,
+    UnsupportedVersionControl(String),
+    NoUpdateFound,
+    /// An error returned by the API of a git hosting service
+    HostedGit(String),
+}
+
+impl Error {
+    /// The service this error was caused by, if any
+    pub fn service(&self) -> Option<&str> {
+        match self {
+            Error::EnvVarNotAllowed { service, .. }
+            | Error::DeprecatedEnvVar { service, .. }
+            | Error::UnresolvedEnvVar { service, .. }
+            | Error::UnknownCapability { service, .. }
+            | Error::CapabilityNotAllowed { service, .. }
+            | Error::MountNotAllowed { service, .. }
+            | Error::InvalidMount { service, .. }
+            | Error::PortOnNonMainContainer { service }
+            | Error::ContainerNotInPortMap { service }
+            | Error::MainPortNotInPortMap { service }
+            | Error::MainPortRequired { service }
+            | Error::NetworkDisabledForMainContainer { service }
+            | Error::NetworkModeWithoutPermission { service }
+            | Error::PortConflict { service, .. }
+            | Error::NoFreeIpAddress { service, .. }
+            | Error::NoFreePort { service }
+            | Error::InvalidHealthcheck { service, .. }
+            | Error::InvalidResourceLimit { service, .. }
+            | Error::ResourceLimitTooHigh { service, .. }
+            | Error::MemoryReservationAboveLimit { service, .. }
+            | Error::SecurityOptOutWithoutPermission { service, .. }
+            | Error::InvalidTmpfs { service, .. }
+            | Error::ComposeFieldDropped { service, .. }
+            | Error::ComposeFieldTransformed { service, .. }
+            | Error::UnmappedEnvVar { service, .. }
+            | Error::NoUmbrelEquivalent { service, .. }
+            | Error::InvalidAuthorizedClient { service, .. } => Some(service),
+            _ => None,
+        }
+    }
+
+    /// A stable identifier for the kind of this error, used as rule id in reports
+    pub fn rule_id(&self) -> &'static str {
+        match self {
+            Error::Io(_) => "io",
+            Error::Preprocess(_) => "preprocess",
+            Error::Parse { .. } => "parse",
+            Error::NotAMap => "not-a-map",
+            Error::MissingVersion => "missing-version",
+            Error::UnsupportedVersion(_) => "unsupported-version",
+            Error::InvalidDefinition { .. } => "invalid-definition",
+            Error::MissingInstalledServices => "missing-installed-services",
+            Error::ConversionFailed => "conversion-failed",
+            Error::NoMainContainer => "no-main-container",
+            Error::MultipleMainContainers(_, _) => "multiple-main-containers",
+            Error::EnvVarNotAllowed { .. } => "env-var-not-allowed",
+            Error::DeprecatedEnvVar { .. } => "deprecated-env-var",
+            Error::UnresolvedEnvVar { .. } => "unresolved-env-var",
+            Error::UnknownCapability { .. } => "unknown-capability",
+            Error::CapabilityNotAllowed { .. } => "capability-not-allowed",
+            Error::MountNotAllowed { .. } => "mount-not-allowed",
+            Error::InvalidMount { .. } => "invalid-mount",
+            Error::PortOnNonMainContainer { .. } => "port-on-non-main-container",
+            Error::InvalidPortMap(_) => "invalid-port-map",
+            Error::ContainerNotInPortMap { .. } => "container-not-in-port-map",
+            Error::MainPortNotInPortMap { .. } => "main-port-not-in-port-map",
+            Error::MainPortRequired { .. } => "main-port-required",
+            Error::NetworkDisabledForMainContainer { .. } => "network-disabled-for-main-container",
+            Error::NetworkModeWithoutPermission { .. } => "network-mode-without-permission",
+            Error::PortConflict { .. } => "port-conflict",
+            Error::InvalidSubnet(_) => "invalid-subnet",
+            Error::InvalidIpAllocation(_) => "invalid-ip-allocation",
+            Error::NoFreeIpAddress { .. } => "no-free-ip-address",
+            Error::NoFreePort { .. } => "no-free-port",
+            Error::InvalidTorConfig { .. } => "invalid-tor-config",
+            Error::InvalidHealthcheck { .. } => "invalid-healthcheck",
+            Error::InvalidResourceLimit { .. } => "invalid-resource-limit",
+            Error::ResourceLimitTooHigh { .. } => "resource-limit-too-high",
+            Error::MemoryReservationAboveLimit { .. } => "memory-reservation-above-limit",
+            Error::InvalidResourcePolicy(_) => "invalid-resource-policy",
+            Error::InvalidPermissionRegistry(_) => "invalid-permission-registry",
+            Error::UnusedPermission(_) => "unused-permission",
+            Error::UnusedAlternatives { .. } => "unused-alternatives",
+            Error::SecurityOptOutWithoutPermission { .. } => "security-opt-out-without-permission",
+            Error::InvalidTmpfs { .. } => "invalid-tmpfs",
+            Error::ComposeFieldDropped { .. } => "compose-field-dropped",
+            Error::ComposeFieldTransformed { .. } => "compose-field-transformed",
+            Error::UnmappedEnvVar { .. } => "unmapped-env-var",
+            Error::InvalidUmbrelMapping(_) => "invalid-umbrel-mapping",
+            Error::UmbrelFieldDropped { .. } => "umbrel-field-dropped",
+            Error::UnsupportedUmbrelManifestVersion(_) => "unsupported-umbrel-manifest-version",
+            Error::NoUmbrelEquivalent { .. } => "no-umbrel-equivalent",
+            Error::CitadelFieldDropped { .. } => "citadel-field-dropped",
+            Error::InvalidAuthorizedClient { .. } => "invalid-authorized-client",
+            Error::DuplicateHiddenServiceDir { .. } => "duplicate-hidden-service-dir",
+            Error::UnresolvedTorPlaceholder(_) => "unresolved-tor-placeholder",
+            Error::InvalidAppVersion(_) => "invalid-app-version",
+            Error::InvalidRepo => "invalid-repo",
+            Error::UnsupportedVersionControl(_) => "unsupported-version-control",
+            Error::NoUpdateFound => "no-update-found",
+            Error::HostedGit(_) => "hosted-git",
+        }
+    }
+
+    /// The location in the app definition this error was caused by, if known
+    pub fn location(&self) -> Option<Location> {
+        match self {
+            Error::Parse { location, .. } | Error::InvalidDefinition { location, .. } => *location,
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(message) => write!(f, "Failed to read app.yml: {}", message),
+            Error::Preprocess(message) => write!(f, "Failed to preprocess template: {}", message),
+            Error::Parse { message, .. } => write!(f, "Failed to parse app.yml: {}", message),
+            Error::NotAMap => write!(f, "App.yml is not a map!"),
+            Error::MissingVersion => write!(f, "Citadel file format is not set or not a number!"),
+            Error::UnsupportedVersion(version) => write!(f, "Version {} not supported", version),
+            Error::InvalidDefinition {
+                version, message, ..
+            } => write!(f, "Error loading app.yml as v{}: {}", version, message),
+            Error::MissingInstalledServices => write!(f, "No installed services defined. If you are trying to validate an app, please make sure it is an app.yml v4 or later."),
+            Error::ConversionFailed => write!(f, "Failed to convert app, but no error was reported"),
+            Error::NoMainContainer => write!(f, "No main container found!"),
+            Error::MultipleMainContainers(first, second) => write!(
+                f,
+                "Multiple main containers in app! Container {} and {} could both be main container",
+                first, second
+            ),
+            Error::EnvVarNotAllowed { env_var, service } => write!(
+                f,
+                "Env var {} not allowed by permissions (in service {})",
+                env_var, service
+            ),
+            Error::DeprecatedEnvVar {
+                env_var,
+                service,
+                message,
+            } => write!(f, "Env var {} is deprecated (in service {}): {}", env_var, service, message),
+            Error::UnresolvedEnvVar { env_var, service } => write!(
+                f,
+                "Env var {} used by service {} is not set on the node",
+                env_var, service
+            ),
+            Error::UnknownCapability {
+                capability,
+                service,
+            } => write!(
+                f,
+                "Service {} defines unknown capability: {}",
+                service, capability
+            ),
+            Error::CapabilityNotAllowed {
+                capability,
+                service,
+                permission,
+            } => write!(
+                f,
+                "Service {} defines capability {}, but the app does not request the {} permission",
+                service, capability, permission
+            ),
+            Error::MountNotAllowed {
+                mount,
+                service,
+                permission,
+            } => write!(
+                f,
+                "{} mount defined by service {} without the {} permission",
+                mount, service, permission
+            ),
+            Error::InvalidMount { path, service } => write!(
+                f,
+                "Data dir {} mounted by service {} is not allowed to contain '..'",
+                path, service
+            ),
+            Error::PortOnNonMainContainer { service } => write!(
+                f,
+                "port: is not supported for containers other than the main container (found in {})",
+                service
+            ),
+            Error::InvalidPortMap(message) => write!(f, "Invalid port map: {}", message),
+            Error::ContainerNotInPortMap { service } => write!(
+                f,
+                "Container {} not found or invalid in port map",
+                service
+            ),
+            Error::MainPortNotInPortMap { service } => write!(
+                f,
+                "Main container port not found in port map (main container is {})",
+                service
+            ),
+            Error::MainPortRequired { service } => write!(
+                f,
+                "A port is required for the main container (main container is {})",
+                service
+            ),
+            Error::NetworkDisabledForMainContainer { service } => write!(
+                f,
+                "Network can not be disabled for the main container (main container is {})",
+                service
+            ),
+            Error::NetworkModeWithoutPermission { service } => write!(
+                f,
+                "Service {} defines network_mode, but the app does not request the network permission",
+                service
+            ),
+            Error::PortConflict {
+                port,
+                protocol,
+                service,
+                others,
+                suggestion,
+            } => {
+                write!(
+                    f,
+                    "Port {}/{} of service {} is also used by {}",
+                    port,
+                    protocol,
+                    service,
+                    others.join(", ")
+                )?;
+                if let Some(suggestion) = suggestion {
+                    write!(f, ", port {} is still free", suggestion)?;
+                }
+                Ok(())
+            }
+            Error::InvalidSubnet(subnet) => write!(f, "Invalid subnet: {}", subnet),
+            Error::InvalidIpAllocation(message) => {
+                write!(f, "Invalid IP address allocation: {}", message)
+            }
+            Error::NoFreeIpAddress { service, subnet } => write!(
+                f,
+                "No free IP address left in {} for service {}",
+                subnet, service
+            ),
+            Error::NoFreePort { service } => {
+                write!(f, "No free port left for service {}", service)
+            }
+            Error::InvalidTorConfig { line, message } => {
+                write!(f, "Invalid torrc in line {}: {}", line, message)
+            }
+            Error::InvalidHealthcheck { service, message } => {
+                write!(f, "Invalid healthcheck in service {}: {}", service, message)
+            }
+            Error::InvalidResourceLimit {
+                service,
+                resource,
+                value,
+            } => write!(
+                f,
+                "Invalid {} for service {}: {}",
+                resource, service, value
+            ),
+            Error::ResourceLimitTooHigh {
+                service,
+                resource,
+                requested,
+                maximum,
+            } => write!(
+                f,
+                "Service {} requests {} {}, but this node only allows {}",
+                service, resource, requested, maximum
+            ),
+            Error::MemoryReservationAboveLimit {
+                service,
+                reservation,
+                limit,
+            } => write!(
+                f,
+                "Service {} reserves {} of memory, but its memory limit is {}",
+                service, reservation, limit
+            ),
+            Error::InvalidResourcePolicy(message) => {
+                write!(f, "Invalid resource policy: {}", message)
+            }
+            Error::InvalidPermissionRegistry(message) => {
+                write!(f, "Invalid permission registry: {}", message)
+            }
+            Error::UnusedPermission(permission) => write!(
+                f,
+                "The app requests the {} permission, but nothing it uses needs it",
+                permission
+            ),
+            Error::UnusedAlternatives { used, unused } => write!(
+                f,
+                "The app only uses {} of its alternative permissions, {} grant nothing it uses",
+                used.join(", "),
+                unused.join(", ")
+            ),
+            Error::SecurityOptOutWithoutPermission { option, service } => write!(
+                f,
+                "Service {} disables {}, but the app does not request the privileges permission",
+                service, option
+            ),
+            Error::ComposeFieldDropped {
+                field,
+                service,
+                reason,
+            } => write!(
+                f,
+                "Dropped {} of service {}, because {}",
+                field, service, reason
+            ),
+            Error::ComposeFieldTransformed {
+                field,
+                service,
+                message,
+            } => write!(f, "Changed {} of service {}: {}", field, service, message),
+            Error::UnmappedEnvVar { env_var, service } => write!(
+                f,
+                "Service {} uses env var {}, which has no Citadel equivalent",
+                service, env_var
+            ),
+            Error::InvalidUmbrelMapping(message) => {
+                write!(f, "Invalid Umbrel mapping: {}", message)
+            }
+            Error::UmbrelFieldDropped { field, reason } => {
+                write!(f, "Dropped {} of the Umbrel app, because {}", field, reason)
+            }
+            Error::UnsupportedUmbrelManifestVersion(version) => write!(
+                f,
+                "Umbrel manifest version {} is not supported, fields it added may be ignored",
+                version
+            ),
+            Error::NoUmbrelEquivalent { env_var, service } => write!(
+                f,
+                "Service {} uses env var {}, which has no Umbrel equivalent",
+                service, env_var
+            ),
+            Error::CitadelFieldDropped { field, reason } => {
+                write!(f, "Dropped {} of the Citadel app, because {}", field, reason)
+            }
+            Error::InvalidTmpfs { path, service } => write!(
+                f,
+                "Tmpfs mount {} of service {} has to be an absolute path",
+                path, service
+            ),
+            Error::InvalidAuthorizedClient { client, service } => write!(
+                f,
+                "Authorized client {} of service {} may only contain letters, numbers, - and _",
+                client, service
+            ),
+            Error::DuplicateHiddenServiceDir { dir, apps } => write!(
+                f,
+                "Hidden service directory {} is used by multiple apps: {}",
+                dir,
+                apps.join(", ")
+            ),
+            Error::UnresolvedTorPlaceholder(placeholder) => write!(
+                f,
+                "Hidden service points to <{}>, but that container has no IP address",
+                placeholder
+            ),
+            Error::InvalidAppVersion(version) => {
+                write!(f, "Could not parse current version {}", version)
+            }
+            Error::InvalidRepo => write!(f, "No repo path found"),
+            Error::UnsupportedVersionControl(vcs) => {
+                write!(f, "Version control system {} not supported", vcs)
+            }
+            Error::NoUpdateFound => write!(f, "No update found"),
+            Error::HostedGit(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -1,17 +1,21 @@
 use semver::Version;
 
+use crate::error::Error;
+
 pub async fn check_updates(
-    owner: &String,
-    repo: &String,
+    owner: &str,
+    repo: &str,
     current_version: &Version,
     include_pre: bool,
-) -> Result<String, String> {
+) -> Result<String, Error> {
     let octocrab = octocrab::instance();
-    let tags = octocrab.repos(owner, repo).list_tags().send().await;
-    if let Err(tag_error) = tags {
-        return Err(tag_error.to_string());
-    }
-    let tags = tags.unwrap().take_items();
+    let tags = octocrab
+        .repos(owner, repo)
+        .list_tags()
+        .send()
+        .await
+        .map_err(|tag_error| Error::HostedGit(tag_error.to_string()))?
+        .take_items();
     for tag in tags {
         let tag = tag.name;
         // Remove the v prefix if it exists
@@ -27,7 +31,7 @@ pub async fn check_updates(
         }
     }
 
-    Err("No update found".to_string())
+    Err(Error::NoUpdateFound)
 }
 
 // Check if a string is a valid GitHub repository path (https://github.com/owner/repo),
@@ -5,6 +5,8 @@ use url::Url;
 use gitlab::api::AsyncQuery;
 use gitlab::api::projects::repository::tags::Tags;
 
+use crate::error::Error;
+
 // The API has more data, but we only need this
 #[derive(Debug, Deserialize)]
 struct Tag {
@@ -16,17 +18,15 @@ pub async fn check_updates(
     repo: String,
     current_version: &Version,
     include_pre: bool,
-) -> Result<String, String> {
-    let endpoint = Tags::builder().project(repo).build();
-    if let Err(err) = endpoint {
-        return Err(err.to_string());
-    }
-    let endpoint = endpoint.unwrap();
-    let result = endpoint.query_async(gitlab).await;
-    if let Err(tag_error) = result {
-        return Err(tag_error.to_string());
-    }
-    let tags: Vec<Tag> = result.unwrap();
+) -> Result<String, Error> {
+    let endpoint = Tags::builder()
+        .project(repo)
+        .build()
+        .map_err(|err| Error::HostedGit(err.to_string()))?;
+    let tags: Vec<Tag> = endpoint
+        .query_async(gitlab)
+        .await
+        .map_err(|tag_error| Error::HostedGit(tag_error.to_string()))?;
     for tag in tags {
         let tag = tag.name;
         // Remove the v prefix if it exists
@@ -42,7 +42,7 @@ pub async fn check_updates(
         }
     }
 
-    Err("No update found".to_string())
+    Err(Error::NoUpdateFound)
 }
 
 // Given a GitLab repository path, return the name of the GitLab instance
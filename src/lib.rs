@@ -1,4 +1,6 @@
 pub mod composegenerator;
+pub mod diagnostics;
+pub mod error;
 #[cfg(feature = "dev-tools")]
 pub mod github;
 #[cfg(feature = "dev-tools")]
@@ -7,4 +9,8 @@ pub mod gitlab;
 pub mod updates;
 #[cfg(feature = "dev-tools")]
 pub mod hosted_git;
+#[cfg(feature = "preprocess")]
+pub mod preprocess;
+pub mod report;
+pub mod source_map;
 pub mod utils;
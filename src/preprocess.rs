@@ -0,0 +1,832 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use tera::ast::{Expr, ExprVal, FunctionCall, LogicExpr, LogicOperator, MacroDefinition, Node, WS};
+use tera::{Template, Tera, Value};
+
+pub use tera::Context;
+
+use crate::error::Error;
+
+/// The name templates are rendered as, it does not end with .html so nothing is escaped
+const TEMPLATE_NAME: &str = "template";
+
+/// How deep blocks, loops and expressions can be nested in a template
+pub const MAX_DEPTH: usize = 32;
+/// The maximum number of items range() can return
+pub const MAX_RANGE: usize = 1000;
+/// The maximum number of items all for loops of a template can iterate over together
+pub const MAX_ITERATIONS: usize = 10 * MAX_RANGE;
+/// The maximum size of a rendered template in bytes, values in the template can not be larger either
+pub const MAX_OUTPUT_SIZE: usize = 1024 * 1024;
+
+// Filters the sandbox adds to templates to enforce its limits while they are rendered
+// Templates can not use them directly, because they are not in ALLOWED_FILTERS
+
+/// Counts the items of a for loop against MAX_ITERATIONS
+const ITERATE_FILTER: &str = "__iterate";
+/// Counts a value that is written to the output against MAX_OUTPUT_SIZE
+const OUTPUT_FILTER: &str = "__output";
+/// Checks that the result of a filter is not too large
+const CHECK_SIZE_FILTER: &str = "__check_size";
+/// Appends a value to a string, string concatenations are built with it
+const APPEND_FILTER: &str = "__append";
+/// Pushes an item to an array, array literals are built with it
+const PUSH_FILTER: &str = "__push";
+/// Checks the result of replace before it is built
+const CHECK_REPLACE_FILTER: &str = "__check_replace";
+/// Checks the result of join before it is built
+const CHECK_JOIN_FILTER: &str = "__check_join";
+
+/// Filters templates can use
+const ALLOWED_FILTERS: &[&str] = &[
+    "upper",
+    "lower",
+    "trim",
+    "trim_start",
+    "trim_end",
+    "trim_start_matches",
+    "trim_end_matches",
+    "truncate",
+    "wordcount",
+    "replace",
+    "capitalize",
+    "title",
+    "escape",
+    "addslashes",
+    "split",
+    "int",
+    "float",
+    "first",
+    "last",
+    "nth",
+    "join",
+    "sort",
+    "unique",
+    "slice",
+    "filter",
+    "map",
+    "concat",
+    "round",
+    "length",
+    "reverse",
+    "json_encode",
+    "as_str",
+    "get",
+    "default",
+];
+
+/// Functions templates can use, get_env is not one of them
+const ALLOWED_FUNCTIONS: &[&str] = &["range", "throw"];
+
+/// Tests templates can use
+const ALLOWED_TESTS: &[&str] = &[
+    "defined",
+    "undefined",
+    "odd",
+    "even",
+    "string",
+    "number",
+    "divisibleby",
+    "iterable",
+    "object",
+    "starting_with",
+    "ending_with",
+    "containing",
+];
+
+/// Checks that a template only uses what the sandbox allows
+struct Checker<'a> {
+    macros: &'a HashMap<String, MacroDefinition>,
+    /// The macros each macro calls
+    calls: HashMap<&'a str, HashSet<&'a str>>,
+}
+
+impl<'a> Checker<'a> {
+    fn check_nodes(
+        &mut self,
+        nodes: &'a [Node],
+        depth: usize,
+        caller: Option<&'a str>,
+    ) -> Result<(), String> {
+        if depth > MAX_DEPTH {
+            return Err(format!(
+                "Template is nested deeper than {} levels",
+                MAX_DEPTH
+            ));
+        }
+        for node in nodes {
+            match node {
+                Node::Text(_)
+                | Node::Raw(..)
+                | Node::Comment(..)
+                | Node::Break(_)
+                | Node::Continue(_)
+                | Node::Super => {}
+                // Macros are checked on their own so recursion can be detected
+                Node::MacroDefinition(..) => {}
+                Node::VariableBlock(_, expr) => self.check_expr(expr, depth + 1, caller)?,
+                Node::Set(_, set) => self.check_expr(&set.value, depth + 1, caller)?,
+                // The result of a filter section can not be limited while it is rendered
+                Node::FilterSection(..) => {
+                    return Err(
+                        "Filter sections are not allowed, apply the filter to a value instead"
+                            .to_string(),
+                    );
+                }
+                Node::Block(_, block, _) => self.check_nodes(&block.body, depth + 1, caller)?,
+                Node::Forloop(_, forloop, _) => {
+                    self.check_loop_container(&forloop.container, depth + 1, caller)?;
+                    self.check_nodes(&forloop.body, depth + 1, caller)?;
+                    if let Some(empty_body) = &forloop.empty_body {
+                        self.check_nodes(empty_body, depth + 1, caller)?;
+                    }
+                }
+                Node::If(if_node, _) => {
+                    for (_, condition, body) in &if_node.conditions {
+                        self.check_expr(condition, depth + 1, caller)?;
+                        self.check_nodes(body, depth + 1, caller)?;
+                    }
+                    if let Some((_, body)) = &if_node.otherwise {
+                        self.check_nodes(body, depth + 1, caller)?;
+                    }
+                }
+                Node::Extends(..) | Node::Include(..) | Node::ImportMacro(..) => {
+                    return Err(
+                        "Templates can not extend, include or import other templates".to_string(),
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Check what a for loop iterates over
+    /// range() is only allowed here, so its items can not be stored and iterated over again
+    fn check_loop_container(
+        &mut self,
+        expr: &'a Expr,
+        depth: usize,
+        caller: Option<&'a str>,
+    ) -> Result<(), String> {
+        match &expr.val {
+            ExprVal::FunctionCall(call) if call.name == "range" => {
+                for filter in &expr.filters {
+                    self.check_call(filter, "filter", ALLOWED_FILTERS, depth, caller)?;
+                }
+                self.check_call(call, "function", ALLOWED_FUNCTIONS, depth, caller)
+            }
+            _ => self.check_expr(expr, depth, caller),
+        }
+    }
+
+    fn check_call(
+        &mut self,
+        call: &'a FunctionCall,
+        kind: &str,
+        allowed: &[&str],
+        depth: usize,
+        caller: Option<&'a str>,
+    ) -> Result<(), String> {
+        if !allowed.contains(&call.name.as_str()) {
+            return Err(format!("The {} {} is not allowed", kind, call.name));
+        }
+        for arg in call.args.values() {
+            self.check_expr(arg, depth + 1, caller)?;
+        }
+        Ok(())
+    }
+
+    fn check_expr(
+        &mut self,
+        expr: &'a Expr,
+        depth: usize,
+        caller: Option<&'a str>,
+    ) -> Result<(), String> {
+        if depth > MAX_DEPTH {
+            return Err(format!(
+                "Template is nested deeper than {} levels",
+                MAX_DEPTH
+            ));
+        }
+        for filter in &expr.filters {
+            self.check_call(filter, "filter", ALLOWED_FILTERS, depth, caller)?;
+        }
+        self.check_value(&expr.val, depth, caller)
+    }
+
+    fn check_value(
+        &mut self,
+        value: &'a ExprVal,
+        depth: usize,
+        caller: Option<&'a str>,
+    ) -> Result<(), String> {
+        match value {
+            ExprVal::String(_)
+            | ExprVal::Int(_)
+            | ExprVal::Float(_)
+            | ExprVal::Bool(_)
+            | ExprVal::Ident(_) => {}
+            ExprVal::Math(math) => {
+                self.check_expr(&math.lhs, depth + 1, caller)?;
+                self.check_expr(&math.rhs, depth + 1, caller)?;
+            }
+            ExprVal::Logic(logic) => {
+                self.check_expr(&logic.lhs, depth + 1, caller)?;
+                self.check_expr(&logic.rhs, depth + 1, caller)?;
+            }
+            ExprVal::In(in_expr) => {
+                self.check_expr(&in_expr.lhs, depth + 1, caller)?;
+                self.check_expr(&in_expr.rhs, depth + 1, caller)?;
+            }
+            ExprVal::Test(test) => {
+                if !ALLOWED_TESTS.contains(&test.name.as_str()) {
+                    return Err(format!("The test {} is not allowed", test.name));
+                }
+                for arg in &test.args {
+                    self.check_expr(arg, depth + 1, caller)?;
+                }
+            }
+            ExprVal::FunctionCall(call) if call.name == "range" => {
+                return Err("range() can only be used in for loops".to_string());
+            }
+            ExprVal::FunctionCall(call) => {
+                self.check_call(call, "function", ALLOWED_FUNCTIONS, depth, caller)?
+            }
+            ExprVal::MacroCall(call) => {
+                if call.namespace != "self" || !self.macros.contains_key(&call.name) {
+                    return Err(format!(
+                        "Macro {}::{} is not defined in this template",
+                        call.namespace, call.name
+                    ));
+                }
+                if let Some(caller) = caller {
+                    self.calls.entry(caller).or_default().insert(&call.name);
+                }
+                for arg in call.args.values() {
+                    self.check_expr(arg, depth + 1, caller)?;
+                }
+            }
+            ExprVal::Array(items) => {
+                for item in items {
+                    self.check_expr(item, depth + 1, caller)?;
+                }
+            }
+            ExprVal::StringConcat(concat) => {
+                for value in &concat.values {
+                    self.check_value(value, depth + 1, caller)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Check if a macro can end up calling itself
+    fn is_recursive(&self, name: &'a str, stack: &mut Vec<&'a str>) -> bool {
+        if stack.contains(&name) {
+            return true;
+        }
+        stack.push(name);
+        let recursive = self
+            .calls
+            .get(name)
+            .into_iter()
+            .flatten()
+            .any(|callee| self.is_recursive(callee, stack));
+        stack.pop();
+        recursive
+    }
+}
+
+/// Check that a template only uses whitelisted filters, functions and tests,
+/// does not include other templates and has no recursive macros
+fn check_template(template: &Template) -> Result<(), String> {
+    let mut checker = Checker {
+        macros: &template.macros,
+        calls: HashMap::new(),
+    };
+    checker.check_nodes(&template.ast, 0, None)?;
+    for (name, definition) in &template.macros {
+        for default in definition.args.values().flatten() {
+            checker.check_expr(default, 1, Some(name))?;
+        }
+        checker.check_nodes(&definition.body, 1, Some(name))?;
+    }
+    for name in template.macros.keys() {
+        if checker.is_recursive(name, &mut Vec::new()) {
+            return Err(format!("Macro {} calls itself", name));
+        }
+    }
+    Ok(())
+}
+
+/// range(), but limited to MAX_RANGE items
+fn limited_range(args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let get = |name: &str, default: Option<u64>| -> tera::Result<u64> {
+        match args.get(name) {
+            Some(value) => value.as_u64().ok_or_else(|| {
+                tera::Error::msg(format!(
+                    "range() received {}={} which is not a number",
+                    name, value
+                ))
+            }),
+            None => default
+                .ok_or_else(|| tera::Error::msg(format!("range() was called without {}", name))),
+        }
+    };
+    let start = get("start", Some(0))?;
+    let end = get("end", None)?;
+    let step_by = get("step_by", Some(1))?;
+    if step_by == 0 {
+        return Err(tera::Error::msg("range() was called with step_by=0"));
+    }
+    if start > end {
+        return Err(tera::Error::msg(
+            "range() was called with start greater than end",
+        ));
+    }
+    if (end - start) / step_by > MAX_RANGE as u64 {
+        return Err(tera::Error::msg(format!(
+            "range() can not return more than {} items",
+            MAX_RANGE
+        )));
+    }
+    Ok((start..end).step_by(step_by as usize).collect())
+}
+
+fn call(name: &str, args: HashMap<String, Expr>) -> FunctionCall {
+    FunctionCall {
+        name: name.to_string(),
+        args,
+    }
+}
+
+/// Add the filters that enforce the limits of the sandbox to a checked template
+fn add_limits(template: &mut Template) {
+    limit_nodes(&mut template.ast);
+    for block in template.blocks.values_mut() {
+        limit_nodes(&mut block.body);
+    }
+    for definition in template.macros.values_mut() {
+        for default in definition.args.values_mut().flatten() {
+            limit_expr(default);
+        }
+        limit_nodes(&mut definition.body);
+    }
+}
+
+fn limit_nodes(nodes: &mut [Node]) {
+    for node in nodes {
+        match node {
+            // Text is written like a value, so it is counted as output too
+            Node::Text(text) | Node::Raw(_, text, _) => {
+                let text = std::mem::take(text);
+                *node = Node::VariableBlock(
+                    WS::default(),
+                    Expr::with_filters(
+                        ExprVal::String(text),
+                        vec![call(OUTPUT_FILTER, HashMap::new())],
+                    ),
+                );
+            }
+            Node::VariableBlock(_, expr) => {
+                limit_expr(expr);
+                // The output of a macro is already counted while the macro is rendered
+                if !matches!(expr.val, ExprVal::MacroCall(_)) || !expr.filters.is_empty() {
+                    expr.filters.push(call(OUTPUT_FILTER, HashMap::new()));
+                }
+            }
+            Node::Set(_, set) => limit_expr(&mut set.value),
+            Node::Block(_, block, _) => limit_nodes(&mut block.body),
+            Node::Forloop(_, forloop, _) => {
+                limit_expr(&mut forloop.container);
+                forloop
+                    .container
+                    .filters
+                    .push(call(ITERATE_FILTER, HashMap::new()));
+                limit_nodes(&mut forloop.body);
+                if let Some(empty_body) = &mut forloop.empty_body {
+                    limit_nodes(empty_body);
+                }
+            }
+            Node::If(if_node, _) => {
+                for (_, condition, body) in &mut if_node.conditions {
+                    limit_condition(condition);
+                    limit_nodes(body);
+                }
+                if let Some((_, body)) = &mut if_node.otherwise {
+                    limit_nodes(body);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// limit_expr() for an expression that is used as a condition
+/// Tera ignores the filters of a string literal in a condition, but string concatenations
+/// are rewritten to one, so they are compared to "" instead
+fn limit_condition(expr: &mut Expr) {
+    if matches!(expr.val, ExprVal::StringConcat(_)) {
+        let negated = std::mem::replace(&mut expr.negated, false);
+        let concat = std::mem::replace(expr, Expr::new(ExprVal::Bool(false)));
+        *expr = Expr {
+            val: ExprVal::Logic(LogicExpr {
+                lhs: Box::new(concat),
+                rhs: Box::new(Expr::new(ExprVal::String(String::new()))),
+                operator: LogicOperator::NotEq,
+            }),
+            negated,
+            filters: Vec::new(),
+        };
+    }
+    limit_expr(expr);
+}
+
+/// Rewrite an expression so its size is checked while it is evaluated
+fn limit_expr(expr: &mut Expr) {
+    let mut filters = Vec::new();
+    match &mut expr.val {
+        // Strings and arrays are built one value at a time, so they can not grow too large
+        ExprVal::StringConcat(concat) => {
+            for value in std::mem::take(&mut concat.values) {
+                let mut value = Expr::new(value);
+                limit_expr(&mut value);
+                filters.push(call(
+                    APPEND_FILTER,
+                    HashMap::from([("value".to_string(), value)]),
+                ));
+            }
+            expr.val = ExprVal::String(String::new());
+        }
+        ExprVal::Array(items) => {
+            for mut item in std::mem::take(items) {
+                limit_expr(&mut item);
+                filters.push(call(
+                    PUSH_FILTER,
+                    HashMap::from([("item".to_string(), item)]),
+                ));
+            }
+        }
+        ExprVal::Math(math) => {
+            limit_expr(&mut math.lhs);
+            limit_expr(&mut math.rhs);
+        }
+        ExprVal::Logic(logic) => {
+            if matches!(logic.operator, LogicOperator::And | LogicOperator::Or) {
+                limit_condition(&mut logic.lhs);
+                limit_condition(&mut logic.rhs);
+            } else {
+                limit_expr(&mut logic.lhs);
+                limit_expr(&mut logic.rhs);
+            }
+        }
+        ExprVal::In(in_expr) => {
+            limit_expr(&mut in_expr.lhs);
+            limit_expr(&mut in_expr.rhs);
+        }
+        ExprVal::Test(test) => test.args.iter_mut().for_each(limit_expr),
+        ExprVal::FunctionCall(call) => call.args.values_mut().for_each(limit_expr),
+        ExprVal::MacroCall(call) => call.args.values_mut().for_each(limit_expr),
+        ExprVal::String(_)
+        | ExprVal::Int(_)
+        | ExprVal::Float(_)
+        | ExprVal::Bool(_)
+        | ExprVal::Ident(_) => {}
+    }
+    for mut filter in std::mem::take(&mut expr.filters) {
+        filter.args.values_mut().for_each(limit_expr);
+        // These filters can multiply the size of a value, so their result is checked before it is built
+        match filter.name.as_str() {
+            "replace" => filters.push(call(CHECK_REPLACE_FILTER, filter.args.clone())),
+            "join" => filters.push(call(CHECK_JOIN_FILTER, filter.args.clone())),
+            _ => {}
+        }
+        filters.push(filter);
+        filters.push(call(CHECK_SIZE_FILTER, HashMap::new()));
+    }
+    expr.filters = filters;
+}
+
+/// Roughly how many bytes a value takes when it is rendered,
+/// or None if it is nested deeper than MAX_DEPTH
+fn value_size(value: &Value, depth: usize) -> Option<usize> {
+    if depth > MAX_DEPTH {
+        return None;
+    }
+    Some(match value {
+        Value::String(string) => string.len(),
+        Value::Array(items) => {
+            let mut size = 2;
+            for item in items {
+                size += value_size(item, depth + 1)? + 1;
+            }
+            size
+        }
+        Value::Object(map) => {
+            let mut size = 2;
+            for (key, value) in map {
+                size += key.len() + value_size(value, depth + 1)? + 2;
+            }
+            size
+        }
+        // Numbers, booleans and null
+        _ => 8,
+    })
+}
+
+fn too_large() -> tera::Error {
+    tera::Error::msg(format!(
+        "A value in the template is larger than {} bytes",
+        MAX_OUTPUT_SIZE
+    ))
+}
+
+/// Check that a value is not larger than MAX_OUTPUT_SIZE and not nested deeper than MAX_DEPTH
+fn check_size(value: &Value) -> tera::Result<()> {
+    match value_size(value, 0) {
+        Some(size) if size <= MAX_OUTPUT_SIZE => Ok(()),
+        Some(_) => Err(too_large()),
+        None => Err(tera::Error::msg(format!(
+            "A value in the template is nested deeper than {} levels",
+            MAX_DEPTH
+        ))),
+    }
+}
+
+/// Take amount from what is left, or return false if not enough is left
+fn spend(left: &AtomicUsize, amount: usize) -> bool {
+    left.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| {
+        left.checked_sub(amount)
+    })
+    .is_ok()
+}
+
+/// What a template can still use while it is rendered
+struct Budget {
+    iterations: AtomicUsize,
+    output: AtomicUsize,
+}
+
+fn string_arg<'a>(args: &'a HashMap<String, Value>, name: &str) -> Option<&'a str> {
+    args.get(name).and_then(Value::as_str)
+}
+
+/// Register the filters that add_limits() uses
+fn register_limits(tera: &mut Tera) {
+    let budget = Arc::new(Budget {
+        iterations: AtomicUsize::new(MAX_ITERATIONS),
+        output: AtomicUsize::new(MAX_OUTPUT_SIZE),
+    });
+    let iterations = budget.clone();
+    tera.register_filter(
+        ITERATE_FILTER,
+        move |value: &Value, _: &HashMap<String, Value>| {
+            let items = match value {
+                Value::Array(items) => items.len(),
+                Value::Object(map) => map.len(),
+                Value::String(string) => string.chars().count(),
+                _ => 0,
+            };
+            if !spend(&iterations.iterations, items) {
+                return Err(tera::Error::msg(format!(
+                    "Loops in the template can not run more than {} times in total",
+                    MAX_ITERATIONS
+                )));
+            }
+            Ok(value.clone())
+        },
+    );
+    tera.register_filter(
+        OUTPUT_FILTER,
+        move |value: &Value, _: &HashMap<String, Value>| {
+            let size = value_size(value, 0).unwrap_or(usize::MAX);
+            if !spend(&budget.output, size) {
+                return Err(tera::Error::msg(format!(
+                    "The result is larger than {} bytes",
+                    MAX_OUTPUT_SIZE
+                )));
+            }
+            Ok(value.clone())
+        },
+    );
+    tera.register_filter(
+        CHECK_SIZE_FILTER,
+        |value: &Value, _: &HashMap<String, Value>| {
+            check_size(value)?;
+            Ok(value.clone())
+        },
+    );
+    tera.register_filter(
+        APPEND_FILTER,
+        |value: &Value, args: &HashMap<String, Value>| {
+            let mut result = value.as_str().unwrap_or_default().to_string();
+            match args.get("value") {
+                Some(Value::String(string)) => result.push_str(string),
+                Some(Value::Number(number)) => result.push_str(&number.to_string()),
+                _ => {
+                    return Err(tera::Error::msg(
+                        "Tried to concat a value that is not a string or a number",
+                    ))
+                }
+            }
+            if result.len() > MAX_OUTPUT_SIZE {
+                return Err(too_large());
+            }
+            Ok(Value::String(result))
+        },
+    );
+    tera.register_filter(
+        PUSH_FILTER,
+        |value: &Value, args: &HashMap<String, Value>| {
+            let mut items = value.as_array().cloned().unwrap_or_default();
+            items.push(args.get("item").cloned().unwrap_or_default());
+            let result = Value::Array(items);
+            check_size(&result)?;
+            Ok(result)
+        },
+    );
+    tera.register_filter(
+        CHECK_REPLACE_FILTER,
+        |value: &Value, args: &HashMap<String, Value>| {
+            // Invalid arguments are reported by replace itself
+            if let (Some(string), Some(from), Some(to)) = (
+                value.as_str(),
+                string_arg(args, "from"),
+                string_arg(args, "to"),
+            ) {
+                let matches = if from.is_empty() {
+                    string.chars().count() + 1
+                } else {
+                    string.matches(from).count()
+                };
+                let size = (string.len() - matches * from.len())
+                    .saturating_add(matches.saturating_mul(to.len()));
+                if size > MAX_OUTPUT_SIZE {
+                    return Err(too_large());
+                }
+            }
+            Ok(value.clone())
+        },
+    );
+    tera.register_filter(
+        CHECK_JOIN_FILTER,
+        |value: &Value, args: &HashMap<String, Value>| {
+            // Invalid arguments are reported by join itself
+            if let Some(items) = value.as_array() {
+                let separator = string_arg(args, "sep").unwrap_or_default();
+                let mut size = separator.len().saturating_mul(items.len());
+                for item in items {
+                    size = size.saturating_add(value_size(item, 0).unwrap_or(usize::MAX));
+                }
+                if size > MAX_OUTPUT_SIZE {
+                    return Err(too_large());
+                }
+            }
+            Ok(value.clone())
+        },
+    );
+}
+
+/// Convert a Tera error to an error message, including the errors that caused it
+fn error_message(error: tera::Error) -> String {
+    // The actual problem is usually in the source of the error
+    let mut message = error.to_string();
+    let mut source = std::error::Error::source(&error);
+    while let Some(cause) = source {
+        message += &format!(": {}", cause);
+        source = cause.source();
+    }
+    message
+}
+
+/// Render a template in a sandbox
+/// Templates can not access the environment, other files or anything not in the context.
+/// How often loops run and the size of values and of the result are limited while rendering
+pub fn render_template(template: &str, context: &Context) -> Result<String, Error> {
+    let mut parsed = Template::new(TEMPLATE_NAME, None, template)
+        .map_err(|error| Error::Preprocess(error_message(error)))?;
+    check_template(&parsed).map_err(Error::Preprocess)?;
+    add_limits(&mut parsed);
+    let mut tera = Tera::default();
+    tera.autoescape_on(vec![]);
+    tera.register_function("range", limited_range);
+    register_limits(&mut tera);
+    // The template is added as it was rewritten, add_raw_template() would parse it again
+    tera.templates.insert(TEMPLATE_NAME.to_string(), parsed);
+    tera.build_inheritance_chains()
+        .map_err(|error| Error::Preprocess(error_message(error)))?;
+    let result = tera
+        .render(TEMPLATE_NAME, context)
+        .map_err(|error| Error::Preprocess(error_message(error)))?;
+    if result.len() > MAX_OUTPUT_SIZE {
+        return Err(Error::Preprocess(format!(
+            "The result is larger than {} bytes",
+            MAX_OUTPUT_SIZE
+        )));
+    }
+    Ok(result)
+}
+
+/// Render an app.yml.jinja for an app with the given services installed
+pub fn render_app_template(
+    template: &str,
+    app_name: &str,
+    services: &[&str],
+) -> Result<String, Error> {
+    let mut context = Context::new();
+    context.insert("services", &services);
+    context.insert("app_name", &app_name);
+    render_template(template, &context)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{render_app_template, render_template, Context};
+
+    #[test]
+    fn render_app() {
+        let template = "{% if 'lnd' in services %}{{ app_name | upper }}{% endif %}\
+                        {% for i in range(end=3) %}{{ i }}{% endfor %}";
+        assert_eq!(
+            render_app_template(template, "example-app", &["lnd"]).unwrap(),
+            "EXAMPLE-APP012"
+        );
+    }
+
+    #[test]
+    fn reject_unsafe_templates() {
+        let context = Context::new();
+        for template in [
+            "{{ get_env(name='HOME') }}",
+            "{{ 'a' | filesizeformat }}",
+            "{% include 'app.yml' %}",
+            "{% macro a() %}{{ self::a() }}{% endmacro a %}{{ self::a() }}",
+            "{% macro a() %}{{ self::b() }}{% endmacro a %}\
+             {% macro b() %}{{ self::a() }}{% endmacro b %}{{ self::a() }}",
+            "{% for i in range(end=100000) %}{% endfor %}",
+            "{% for i in range(end=1, step_by=0) %}{% endfor %}",
+            "{% filter upper %}a{% endfilter %}",
+        ] {
+            assert!(
+                render_template(template, &context).is_err(),
+                "{} was rendered",
+                template
+            );
+        }
+    }
+
+    #[test]
+    fn limit_output_size() {
+        let mut context = Context::new();
+        context.insert("value", &"x".repeat(2048));
+        let template = "{% for i in range(end=1000) %}{{ value }}{% endfor %}";
+        assert!(render_template(template, &context).is_err());
+    }
+
+    #[test]
+    fn limit_nested_ranges() {
+        let context = Context::new();
+        let template = "{% for i in range(end=1000) %}{% for j in range(end=1000) %}\
+                        {% for k in range(end=1000) %}{% endfor %}{% endfor %}{% endfor %}";
+        assert!(render_template(template, &context)
+            .unwrap_err()
+            .to_string()
+            .contains("in total"));
+        let template = "{% set items = range(end=1000) %}\
+                        {% for i in items %}{% for j in items %}{% endfor %}{% endfor %}";
+        assert!(render_template(template, &context).is_err());
+        let template =
+            "{% for i in range(end=10) %}{% for j in range(end=100) %}{% endfor %}{% endfor %}";
+        assert!(render_template(template, &context).is_ok());
+    }
+
+    #[test]
+    fn limit_values_while_rendering() {
+        let mut context = Context::new();
+        context.insert("value", &"x".repeat(2048));
+        context.insert("list", &"x,".repeat(999));
+        for template in [
+            "{% set a = 'x' %}{% for i in range(end=40) %}{% set_global a = a ~ a %}{% endfor %}",
+            "{% set a = ['x'] %}{% for i in range(end=40) %}{% set_global a = [a, a] %}{% endfor %}",
+            "{% set a = value | replace(from='x', to=value) %}",
+            "{% set a = list | split(pat=',') | join(sep=value) %}",
+            "{% for a in list | split(pat=',') %}{% for b in list | split(pat=',') %}\
+             {% endfor %}{% endfor %}",
+        ] {
+            assert!(
+                render_template(template, &context).is_err(),
+                "{} was rendered",
+                template
+            );
+        }
+        let template =
+            "{% set a = 'x' %}{% for i in range(end=4) %}{% set_global a = a ~ a %}{% endfor %}\
+                        {% if a ~ 1 %}{{ a ~ 1 }}{% endif %}{% if not 'x' ~ '' %}no{% endif %}";
+        assert_eq!(
+            render_template(template, &context).unwrap(),
+            "xxxxxxxxxxxxxxxx1"
+        );
+    }
+}
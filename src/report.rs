@@ -0,0 +1,199 @@
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::diagnostics::{Diagnostic, Severity};
+
+/// A diagnostic in a form that can be serialized for other tools
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct DiagnosticReport {
+    pub severity: Severity,
+    pub rule_id: String,
+    pub message: String,
+    /// The key path of the offending value, empty if the problem is not caused by a specific key
+    pub path: String,
+    pub file: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub column: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end_column: Option<usize>,
+}
+
+impl DiagnosticReport {
+    pub fn new(file: &str, diagnostic: &Diagnostic) -> Self {
+        DiagnosticReport {
+            severity: diagnostic.severity,
+            rule_id: diagnostic.error.rule_id().to_string(),
+            message: diagnostic.error.to_string(),
+            path: diagnostic.path.clone(),
+            file: file.to_string(),
+            line: diagnostic.location.map(|location| location.line),
+            column: diagnostic.location.map(|location| location.column),
+            end_column: diagnostic
+                .location
+                .map(|location| location.column + diagnostic.length.max(1)),
+        }
+    }
+}
+
+/// The result of validating a single app
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct AppReport {
+    pub app: String,
+    pub file: String,
+    pub valid: bool,
+    pub diagnostics: Vec<DiagnosticReport>,
+}
+
+impl AppReport {
+    pub fn new(app: &str, file: &str, diagnostics: &[Diagnostic]) -> Self {
+        AppReport {
+            app: app.to_string(),
+            file: file.to_string(),
+            valid: !diagnostics.iter().any(Diagnostic::is_error),
+            diagnostics: diagnostics
+                .iter()
+                .map(|diagnostic| DiagnosticReport::new(file, diagnostic))
+                .collect(),
+        }
+    }
+}
+
+/// Get a JSON report for a list of validated apps
+pub fn to_json(reports: &[AppReport]) -> Value {
+    json!({
+        "valid": reports.iter().all(|report| report.valid),
+        "apps": reports,
+    })
+}
+
+/// Get a SARIF 2.1.0 log for a list of validated apps
+pub fn to_sarif(reports: &[AppReport]) -> Value {
+    let mut rules = Vec::<&str>::new();
+    let mut results = Vec::<Value>::new();
+    for report in reports {
+        for diagnostic in &report.diagnostics {
+            if !rules.contains(&diagnostic.rule_id.as_str()) {
+                rules.push(&diagnostic.rule_id);
+            }
+            let mut physical_location = json!({
+                "artifactLocation": { "uri": diagnostic.file },
+            });
+            if let (Some(line), Some(column), Some(end_column)) =
+                (diagnostic.line, diagnostic.column, diagnostic.end_column)
+            {
+                physical_location["region"] = json!({
+                    "startLine": line,
+                    "startColumn": column,
+                    "endColumn": end_column,
+                });
+            }
+            results.push(json!({
+                "ruleId": diagnostic.rule_id,
+                "level": diagnostic.severity.to_string(),
+                "message": { "text": diagnostic.message },
+                "locations": [{ "physicalLocation": physical_location }],
+            }));
+        }
+    }
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "app-cli",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "informationUri": "https://github.com/runcitadel/app-cli",
+                    "rules": rules.iter().map(|rule| json!({ "id": rule })).collect::<Vec<Value>>(),
+                }
+            },
+            "results": results,
+        }]
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{to_json, to_sarif, AppReport};
+    use crate::diagnostics::Diagnostic;
+    use crate::error::{Error, Location};
+    use serde_json::json;
+
+    fn example_reports() -> Vec<AppReport> {
+        let mut located = Diagnostic::error(
+            "services.main.environment.LND",
+            Error::EnvVarNotAllowed {
+                env_var: "LND_IP".to_string(),
+                service: "main".to_string(),
+            },
+        );
+        located.location = Some(Location {
+            line: 15,
+            column: 7,
+        });
+        located.length = 3;
+        vec![
+            AppReport::new("example-app", "example-app/app.yml", &[located]),
+            AppReport::new(
+                "other-app",
+                "other-app/app.yml",
+                &[Diagnostic::warning(
+                    "services.main.network_mode",
+                    Error::NetworkModeWithoutPermission {
+                        service: "main".to_string(),
+                    },
+                )],
+            ),
+        ]
+    }
+
+    #[test]
+    fn json_report() {
+        let report = to_json(&example_reports());
+        assert_eq!(report["valid"], json!(false));
+        assert_eq!(report["apps"][0]["valid"], json!(false));
+        assert_eq!(
+            report["apps"][0]["diagnostics"][0],
+            json!({
+                "severity": "error",
+                "rule_id": "env-var-not-allowed",
+                "message": "Env var LND_IP not allowed by permissions (in service main)",
+                "path": "services.main.environment.LND",
+                "file": "example-app/app.yml",
+                "line": 15,
+                "column": 7,
+                "end_column": 10,
+            })
+        );
+        assert_eq!(report["apps"][1]["valid"], json!(true));
+        assert_eq!(report["apps"][1]["diagnostics"][0].get("line"), None);
+    }
+
+    #[test]
+    fn sarif_report() {
+        let report = to_sarif(&example_reports());
+        let run = &report["runs"][0];
+        assert_eq!(
+            run["tool"]["driver"]["rules"],
+            json!([
+                { "id": "env-var-not-allowed" },
+                { "id": "network-mode-without-permission" },
+            ])
+        );
+        assert_eq!(run["results"][0]["level"], json!("error"));
+        assert_eq!(
+            run["results"][0]["locations"][0]["physicalLocation"],
+            json!({
+                "artifactLocation": { "uri": "example-app/app.yml" },
+                "region": { "startLine": 15, "startColumn": 7, "endColumn": 10 },
+            })
+        );
+        assert_eq!(run["results"][1]["level"], json!("warning"));
+        assert_eq!(
+            run["results"][1]["locations"][0]["physicalLocation"].get("region"),
+            None
+        );
+    }
+}
@@ -0,0 +1,301 @@
+use std::collections::HashMap;
+
+use crate::error::Location;
+
+/// The position and length of a key or sequence item in an app.yml
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Span {
+    pub location: Location,
+    pub length: usize,
+}
+
+/// Maps key paths (like services.main.environment.FOO) to their position in an app.yml
+///
+/// serde_yaml does not expose the location of nodes, so this does a lightweight pass over the
+/// block-style YAML app.yml files are written in. Contents of flow collections and block scalars
+/// are not indexed, their location is the one of the key they belong to.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    spans: HashMap<String, Span>,
+}
+
+struct Node {
+    indent: usize,
+    path: String,
+    is_item: bool,
+    next_index: usize,
+}
+
+fn join_path(parent: &str, key: &str) -> String {
+    if parent.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", parent, key)
+    }
+}
+
+/// Remove a trailing comment from a line, ignoring # inside quotes
+fn strip_comment(line: &str) -> &str {
+    let mut quote: Option<char> = None;
+    let mut previous = ' ';
+    for (index, character) in line.char_indices() {
+        match quote {
+            Some(quote_char) if character == quote_char => quote = None,
+            Some(_) => {}
+            None if character == '"' || character == '\'' => quote = Some(character),
+            None if character == '#' && previous.is_whitespace() => return &line[..index],
+            None => {}
+        }
+        previous = character;
+    }
+    line
+}
+
+/// Split "key: value" into the key (without quotes), the length of the key in the source and the value
+fn split_key(content: &str) -> Option<(String, usize, &str)> {
+    if let Some(quote) = content.chars().next().filter(|c| *c == '"' || *c == '\'') {
+        let end = content[1..].find(quote)? + 1;
+        let rest = content[end + 1..].trim_start();
+        let value = rest.strip_prefix(':')?;
+        if !value.is_empty() && !value.starts_with(' ') {
+            return None;
+        }
+        return Some((content[1..end].to_string(), end + 1, value.trim()));
+    }
+    if content.starts_with(['-', '[', '{', '?', '#', '|', '>']) {
+        return None;
+    }
+    let end = content
+        .find(": ")
+        .or_else(|| content.strip_suffix(':').map(|key| key.len()))?;
+    let key = content[..end].trim_end();
+    Some((key.to_string(), key.len(), content[end + 1..].trim()))
+}
+
+/// The change of nesting depth of flow collections ([...] and {...}) in a value
+fn flow_depth(value: &str) -> i64 {
+    let mut depth = 0;
+    let mut quote: Option<char> = None;
+    for character in value.chars() {
+        match quote {
+            Some(quote_char) if character == quote_char => quote = None,
+            Some(_) => {}
+            None => match character {
+                '"' | '\'' => quote = Some(character),
+                '[' | '{' => depth += 1,
+                ']' | '}' => depth -= 1,
+                _ => {}
+            },
+        }
+    }
+    depth
+}
+
+impl SourceMap {
+    pub fn parse(source: &str) -> SourceMap {
+        let mut spans = HashMap::<String, Span>::new();
+        let mut stack: Vec<Node> = Vec::new();
+        // Lines more indented than this belong to a block scalar
+        let mut block_indent: Option<usize> = None;
+        let mut open_flow: i64 = 0;
+        for (line_index, raw_line) in source.lines().enumerate() {
+            let line = strip_comment(raw_line).trim_end();
+            let content = line.trim_start();
+            let mut indent = line.len() - content.len();
+            if content.is_empty() {
+                continue;
+            }
+            if open_flow > 0 {
+                open_flow += flow_depth(content);
+                continue;
+            }
+            if let Some(scalar_indent) = block_indent {
+                if indent > scalar_indent {
+                    continue;
+                }
+                block_indent = None;
+            }
+            if content == "---" || content == "..." || content.starts_with('%') {
+                continue;
+            }
+
+            let mut content = content;
+            // A sequence item, which can also be the start of a mapping
+            while content == "-" || content.starts_with("- ") {
+                while stack.last().is_some_and(|node| {
+                    node.indent > indent || (node.indent == indent && node.is_item)
+                }) {
+                    stack.pop();
+                }
+                let parent_path = stack.last().map_or(String::new(), |node| node.path.clone());
+                let index = match stack.last_mut() {
+                    Some(parent) => {
+                        parent.next_index += 1;
+                        parent.next_index - 1
+                    }
+                    None => 0,
+                };
+                let path = join_path(&parent_path, &index.to_string());
+                let item = content[1..].trim_start();
+                let item_indent = indent + (content.len() - item.len());
+                spans.insert(
+                    path.clone(),
+                    Span {
+                        location: Location {
+                            line: line_index + 1,
+                            column: item_indent + 1,
+                        },
+                        length: item.len().max(1),
+                    },
+                );
+                stack.push(Node {
+                    indent,
+                    path,
+                    is_item: true,
+                    next_index: 0,
+                });
+                content = item;
+                indent = item_indent;
+            }
+            if content.is_empty() {
+                continue;
+            }
+
+            let value = if let Some((key, length, value)) = split_key(content) {
+                while stack.last().is_some_and(|node| node.indent >= indent) {
+                    stack.pop();
+                }
+                let parent_path = stack.last().map_or(String::new(), |node| node.path.clone());
+                let path = join_path(&parent_path, &key);
+                spans.insert(
+                    path.clone(),
+                    Span {
+                        location: Location {
+                            line: line_index + 1,
+                            column: indent + 1,
+                        },
+                        length,
+                    },
+                );
+                stack.push(Node {
+                    indent,
+                    path,
+                    is_item: false,
+                    next_index: 0,
+                });
+                value
+            } else {
+                content
+            };
+
+            if value.starts_with('|') || value.starts_with('>') {
+                block_indent = Some(stack.last().map_or(0, |node| node.indent));
+            } else if value.starts_with('[') || value.starts_with('{') {
+                open_flow = flow_depth(value).max(0);
+            }
+        }
+        SourceMap { spans }
+    }
+
+    /// Get the span of exactly this path
+    pub fn get(&self, path: &str) -> Option<Span> {
+        self.spans.get(path).copied()
+    }
+
+    /// Get the span of this path or, if it does not exist, of its closest parent
+    pub fn locate(&self, path: &str) -> Option<Span> {
+        if path.is_empty() {
+            return None;
+        }
+        if let Some(span) = self.get(path) {
+            return Some(span);
+        }
+        // Keys can contain dots themselves, so try every prefix ending before a dot
+        path.rmatch_indices('.')
+            .find_map(|(index, _)| self.get(&path[..index]))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{SourceMap, Span};
+    use crate::error::Location;
+
+    const APP_YML: &str = r#"citadel_version: 4
+# A comment
+metadata:
+  name: "Example: app" # another comment
+  description: |
+    key: not a key
+  developers: {a: b}
+services:
+  main:
+    command:
+      - run
+      - --flag
+    environment:
+      "QUOTED": value
+    mounts:
+      data:
+        ../escape: /data
+  database:
+    depends_on:
+    - main
+"#;
+
+    fn span(line: usize, column: usize, length: usize) -> Option<Span> {
+        Some(Span {
+            location: Location { line, column },
+            length,
+        })
+    }
+
+    #[test]
+    fn locate_keys() {
+        let source_map = SourceMap::parse(APP_YML);
+        assert_eq!(source_map.get("citadel_version"), span(1, 1, 15));
+        assert_eq!(source_map.get("metadata.name"), span(4, 3, 4));
+        assert_eq!(
+            source_map.get("services.main.environment.QUOTED"),
+            span(14, 7, 8)
+        );
+        assert_eq!(
+            source_map.get("services.main.mounts.data.../escape"),
+            span(17, 9, 9)
+        );
+        assert_eq!(source_map.get("services.database"), span(18, 3, 8));
+    }
+
+    #[test]
+    fn locate_sequence_items() {
+        let source_map = SourceMap::parse(APP_YML);
+        assert_eq!(source_map.get("services.main.command.0"), span(11, 9, 3));
+        assert_eq!(source_map.get("services.main.command.1"), span(12, 9, 6));
+        assert_eq!(
+            source_map.get("services.database.depends_on.0"),
+            span(20, 7, 4)
+        );
+    }
+
+    #[test]
+    fn skip_scalars_and_flow_collections() {
+        let source_map = SourceMap::parse(APP_YML);
+        assert_eq!(source_map.get("metadata.description.key"), None);
+        assert_eq!(source_map.get("metadata.developers.a"), None);
+        assert_eq!(source_map.get("metadata.developers"), span(7, 3, 10));
+    }
+
+    #[test]
+    fn fall_back_to_parents() {
+        let source_map = SourceMap::parse(APP_YML);
+        assert_eq!(
+            source_map.locate("services.main.environment.MISSING"),
+            span(13, 5, 11)
+        );
+        assert_eq!(
+            source_map.locate("services.main.mounts.data.../escape.x"),
+            span(17, 9, 9)
+        );
+        assert_eq!(source_map.locate(""), None);
+    }
+}
@@ -41,7 +41,7 @@ pub async fn update_app(app: &mut AppYmlFile, include_pre: bool) -> Result<(), (
             }
         }
         AppYmlFile::V3(app) => {
-            let update_containers = vec!["main", "web"];
+            let update_containers = ["main", "web"];
             let repo = match &app.metadata.repo {
                 crate::composegenerator::v3::types::RepoDefinition::RepoUrl(url) => {
                     get_repo_path(url)
@@ -1,14 +1,8 @@
-use lazy_static::lazy_static;
-use regex::Regex;
+use std::collections::HashMap;
+use std::ops::Range;
 
 use crate::composegenerator::types::Permissions;
 
-lazy_static! {
-    // This should have been the following regex originally: \$(\{.*?}|[A-z1-9]+)
-    // However, it lead to a double match of ${VAR} and {VAR} getting matched for some reason
-    static ref ENV_VAR_REGEX: Regex = Regex::new(r"\$\{.*?}|\$[A-z1-9]+").unwrap();
-}
-
 #[macro_export]
 macro_rules! map(
     { $($key:expr => $value:expr),+ } => {
@@ -35,27 +29,277 @@ macro_rules! bmap(
      };
 );
 
+/// What a modifier of an env var does if the env var is not set
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ModifierKind {
+    /// ${VAR:-default} uses the default instead
+    Default,
+    /// ${VAR:?error} fails with the error
+    Required,
+    /// ${VAR:+alternative} uses the alternative if the env var is set, and nothing otherwise
+    Alternative,
+}
+
+/// The part of an env var reference after its name, for example :-default
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Modifier<'a> {
+    pub kind: ModifierKind,
+    /// True if an empty env var is treated like a missing one (the modifier starts with a colon)
+    pub if_empty: bool,
+    /// The default, error message or alternative, it can contain env vars itself
+    pub value: &'a str,
+}
+
+/// An env var used in a string, following the interpolation rules of docker-compose
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EnvVarReference<'a> {
+    pub name: &'a str,
+    pub modifier: Option<Modifier<'a>>,
+    /// Where the reference is in the string, including the $ and braces
+    pub span: Range<usize>,
+    /// Where the name is in the string
+    pub name_span: Range<usize>,
+}
+
+impl<'a> EnvVarReference<'a> {
+    /// The env vars used in the modifier's value
+    pub fn nested(&self) -> Vec<EnvVarReference<'a>> {
+        let modifier = match &self.modifier {
+            Some(modifier) => modifier,
+            None => return Vec::new(),
+        };
+        // The value is a slice of the same string, so spans can be moved to be relative to it
+        let offset = self.span.end - 1 - modifier.value.len();
+        lex_env_vars(modifier.value)
+            .into_iter()
+            .map(|reference| EnvVarReference {
+                span: reference.span.start + offset..reference.span.end + offset,
+                name_span: reference.name_span.start + offset..reference.name_span.end + offset,
+                ..reference
+            })
+            .collect()
+    }
+}
+
+fn is_name_start(byte: u8) -> bool {
+    byte.is_ascii_alphabetic() || byte == b'_'
+}
+
+fn is_name_char(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || byte == b'_'
+}
+
+/// Find the end of the value of a modifier, which is the index of the closing brace
+fn find_closing_brace(bytes: &[u8], start: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut index = start;
+    while index < bytes.len() {
+        match (bytes[index], bytes.get(index + 1)) {
+            (b'$', Some(b'$')) => index += 1,
+            (b'$', Some(b'{')) => {
+                depth += 1;
+                index += 1;
+            }
+            (b'}', _) if depth == 0 => return Some(index),
+            (b'}', _) => depth -= 1,
+            _ => {}
+        }
+        index += 1;
+    }
+    None
+}
+
+/// Parse a ${...} reference starting at the $, returns None if it is not valid
+fn lex_braced(string: &str, start: usize) -> Option<EnvVarReference<'_>> {
+    let bytes = string.as_bytes();
+    let name_start = start + 2;
+    if !bytes.get(name_start).copied().is_some_and(is_name_start) {
+        return None;
+    }
+    let mut name_end = name_start;
+    while bytes.get(name_end).copied().is_some_and(is_name_char) {
+        name_end += 1;
+    }
+    let mut reference = EnvVarReference {
+        name: &string[name_start..name_end],
+        modifier: None,
+        span: start..name_end + 1,
+        name_span: name_start..name_end,
+    };
+    if *bytes.get(name_end)? == b'}' {
+        return Some(reference);
+    }
+    let if_empty = bytes[name_end] == b':';
+    let kind_index = if if_empty { name_end + 1 } else { name_end };
+    let kind = match bytes.get(kind_index)? {
+        b'-' => ModifierKind::Default,
+        b'?' => ModifierKind::Required,
+        b'+' => ModifierKind::Alternative,
+        _ => return None,
+    };
+    let end = find_closing_brace(bytes, kind_index + 1)?;
+    reference.modifier = Some(Modifier {
+        kind,
+        if_empty,
+        value: &string[kind_index + 1..end],
+    });
+    reference.span = start..end + 1;
+    Some(reference)
+}
+
+/// Find the env vars used in a string
+/// $$ is an escaped $, invalid references like ${} or $1 are not env vars and kept as they are
+/// Env vars in the values of modifiers are not included, see EnvVarReference::nested
+pub fn lex_env_vars(string: &str) -> Vec<EnvVarReference<'_>> {
+    let bytes = string.as_bytes();
+    let mut result = Vec::new();
+    let mut index = 0;
+    while index < bytes.len() {
+        if bytes[index] != b'$' {
+            index += 1;
+            continue;
+        }
+        match bytes.get(index + 1) {
+            Some(b'$') => index += 2,
+            Some(b'{') => match lex_braced(string, index) {
+                Some(reference) => {
+                    index = reference.span.end;
+                    result.push(reference);
+                }
+                None => index += 1,
+            },
+            Some(byte) if is_name_start(*byte) => {
+                let mut end = index + 1;
+                while bytes.get(end).copied().is_some_and(is_name_char) {
+                    end += 1;
+                }
+                result.push(EnvVarReference {
+                    name: &string[index + 1..end],
+                    modifier: None,
+                    span: index..end,
+                    name_span: index + 1..end,
+                });
+                index = end;
+            }
+            _ => index += 1,
+        }
+    }
+    result
+}
+
+/// Find the names of all env vars used in a string, including the ones in defaults
 pub fn find_env_vars(string: &str) -> Vec<&str> {
-    let mut result: Vec<&str> = Vec::new();
-    let matches = ENV_VAR_REGEX.captures_iter(string);
-    for captures in matches {
-        for element in captures.iter().flatten() {
-            let matched = element.as_str();
-            // If the env var starts with ${, remove it and the closing }
-            // Otherwise, just remove the $
-            if matched.starts_with("${") {
-                result.push(&matched[2..matched.len() - 1])
-            } else {
-                result.push(&matched[1..matched.len()]);
-            };
+    let mut result = Vec::new();
+    let mut references = lex_env_vars(string);
+    while !references.is_empty() {
+        let mut nested = Vec::new();
+        for reference in references {
+            result.push(reference.name);
+            nested.extend(reference.nested());
+        }
+        references = nested;
+    }
+    result
+}
+
+/// Replace all env vars in a string with their values
+/// Env vars that are not set are kept as they are and returned in the second value,
+/// unless a modifier says what to use instead
+/// $ in values is escaped as $$, so docker-compose does not interpolate the result again
+pub fn substitute_env_vars(string: &str, env: &HashMap<String, String>) -> (String, Vec<String>) {
+    substitute(string, env, true)
+}
+
+/// Replace the env vars in a string that have a value in values
+/// All other env vars are kept exactly as they are written, including their modifiers
+pub fn substitute_known_env_vars(string: &str, values: &HashMap<String, String>) -> String {
+    substitute(string, values, false).0
+}
+
+/// Substitute env vars, if env_is_complete is false, env vars that are not in env are not touched
+/// and values are inserted as they are, because they can refer to other env vars
+fn substitute(
+    string: &str,
+    env: &HashMap<String, String>,
+    env_is_complete: bool,
+) -> (String, Vec<String>) {
+    let mut missing = Vec::<String>::new();
+    let mut result = String::with_capacity(string.len());
+    let mut last_end = 0;
+    for reference in lex_env_vars(string) {
+        result.push_str(&string[last_end..reference.span.start]);
+        last_end = reference.span.end;
+        let value = env.get(reference.name);
+        if value.is_none() && !env_is_complete {
+            result.push_str(&string[reference.span]);
+            continue;
+        }
+        let is_set = |if_empty: bool| value.is_some_and(|value| !if_empty || !value.is_empty());
+        let mut substitute = |value: &str| {
+            let (value, nested_missing) = substitute(value, env, env_is_complete);
+            missing.extend(nested_missing);
+            value
+        };
+        match (reference.modifier, value) {
+            (Some(modifier), _) if modifier.kind == ModifierKind::Alternative => {
+                if is_set(modifier.if_empty) {
+                    result.push_str(&substitute(modifier.value));
+                }
+            }
+            (Some(modifier), _)
+                if modifier.kind == ModifierKind::Default && !is_set(modifier.if_empty) =>
+            {
+                result.push_str(&substitute(modifier.value));
+            }
+            (Some(modifier), _) if !is_set(modifier.if_empty) => {
+                missing.push(reference.name.to_string());
+                result.push_str(&string[reference.span]);
+            }
+            (_, Some(value)) if env_is_complete => result.push_str(&value.replace('$', "$$")),
+            (_, Some(value)) => result.push_str(value),
+            (_, None) => {
+                missing.push(reference.name.to_string());
+                result.push_str(&string[reference.span]);
+            }
         }
     }
+    result.push_str(&string[last_end..]);
+    (result, missing)
+}
+
+/// Rename env vars in a string, without changing how they are used
+/// renames maps the old names to the new ones, env vars that are not in it are kept
+pub fn rename_env_vars(string: &str, renames: &HashMap<String, String>) -> String {
+    let mut name_spans = Vec::new();
+    let mut references = lex_env_vars(string);
+    while !references.is_empty() {
+        let mut nested = Vec::new();
+        for reference in references {
+            if let Some(new_name) = renames.get(reference.name) {
+                name_spans.push((reference.name_span.clone(), new_name));
+            }
+            nested.extend(reference.nested());
+        }
+        references = nested;
+    }
+    name_spans.sort_by_key(|(span, _)| span.start);
+    let mut result = String::with_capacity(string.len());
+    let mut last_end = 0;
+    for (span, new_name) in name_spans {
+        result.push_str(&string[last_end..span.start]);
+        result.push_str(new_name);
+        last_end = span.end;
+    }
+    result.push_str(&string[last_end..]);
     result
 }
 
 #[cfg(test)]
 mod test_env_vars {
-    use crate::utils::find_env_vars;
+    use crate::utils::{
+        find_env_vars, lex_env_vars, rename_env_vars, substitute_env_vars,
+        substitute_known_env_vars, Modifier, ModifierKind,
+    };
 
     #[test]
     fn handle_empty_properly() {
@@ -76,12 +320,130 @@ mod test_env_vars {
     }
 
     #[test]
+    #[allow(clippy::useless_vec)]
     fn find_syntax_combined() {
         let result = find_env_vars("something $BITCOIN_IP something ${LND_IP} $ANOTHER_THING");
         let expected = vec!["BITCOIN_IP", "LND_IP", "ANOTHER_THING"];
 
         assert!(expected.iter().all(|item| result.contains(item)));
     }
+
+    #[test]
+    fn substitute_both_syntaxes() {
+        let env = crate::map! {
+            "BITCOIN_IP" => "10.21.21.8".to_string(),
+            "APP_DATA_DIR" => "/home/citadel/app-data/example".to_string()
+        };
+        let result = substitute_env_vars("$BITCOIN_IP:${APP_DATA_DIR}/data:$MISSING", &env);
+        assert_eq!(
+            result,
+            (
+                "10.21.21.8:/home/citadel/app-data/example/data:$MISSING".to_string(),
+                vec!["MISSING".to_string()]
+            )
+        );
+    }
+
+    #[test]
+    fn find_all_names() {
+        let result = find_env_vars("$0 $1 $$ESCAPED $APP_SEED_0 ${BITCOIN_RPC_PASS:-x} $[ ${}");
+        assert_eq!(result, vec!["APP_SEED_0", "BITCOIN_RPC_PASS"]);
+    }
+
+    #[test]
+    fn lex_modifiers() {
+        let string = "${A:-${B}} ${C?missing C} ${D:+}";
+        let result = lex_env_vars(string);
+        assert_eq!(
+            result
+                .iter()
+                .map(|reference| (
+                    reference.name,
+                    reference.modifier,
+                    &string[reference.span.clone()]
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    "A",
+                    Some(Modifier {
+                        kind: ModifierKind::Default,
+                        if_empty: true,
+                        value: "${B}"
+                    }),
+                    "${A:-${B}}"
+                ),
+                (
+                    "C",
+                    Some(Modifier {
+                        kind: ModifierKind::Required,
+                        if_empty: false,
+                        value: "missing C"
+                    }),
+                    "${C?missing C}"
+                ),
+                (
+                    "D",
+                    Some(Modifier {
+                        kind: ModifierKind::Alternative,
+                        if_empty: true,
+                        value: ""
+                    }),
+                    "${D:+}"
+                ),
+            ]
+        );
+        let nested = result[0].nested();
+        assert_eq!(nested[0].name, "B");
+        assert_eq!(&string[nested[0].span.clone()], "${B}");
+    }
+
+    #[test]
+    fn substitute_modifiers() {
+        let env = crate::map! {
+            "EMPTY" => String::new(),
+            "SET" => "value".to_string()
+        };
+        let result = substitute_env_vars(
+            "${EMPTY:-default} ${EMPTY-default} ${MISSING:-$SET} ${SET:+alt} ${MISSING:+alt} ${MISSING:?error} $$SET",
+            &env,
+        );
+        assert_eq!(
+            result,
+            (
+                "default  value alt  ${MISSING:?error} $$SET".to_string(),
+                vec!["MISSING".to_string()]
+            )
+        );
+    }
+
+    #[test]
+    fn substitute_only_known() {
+        let values = crate::map! {
+            "SET" => "value".to_string()
+        };
+        let result = substitute_known_env_vars(
+            "${UNKNOWN:-d} ${UNKNOWN:+a} $UNKNOWN ${SET:-d} ${SET:+$UNKNOWN} ${UNKNOWN:-$SET}",
+            &values,
+        );
+        assert_eq!(
+            result,
+            "${UNKNOWN:-d} ${UNKNOWN:+a} $UNKNOWN value $UNKNOWN ${UNKNOWN:-$SET}"
+        );
+    }
+
+    #[test]
+    fn rename_keeps_modifiers() {
+        let renames = crate::map! {
+            "APP_PASSWORD" => "APP_SEED".to_string(),
+            "APP_SEED" => "APP_SEED_2".to_string()
+        };
+        let result = rename_env_vars(
+            "$APP_PASSWORD ${APP_SEED:-${APP_PASSWORD}} $APP_SEED_1",
+            &renames,
+        );
+        assert_eq!(result, "$APP_SEED ${APP_SEED_2:-${APP_SEED}} $APP_SEED_1");
+    }
 }
 
 pub fn flatten(perms: Vec<Permissions>) -> Vec<String> {
@@ -0,0 +1,69 @@
+#![cfg(all(feature = "cli", feature = "dev-tools"))]
+
+use std::path::PathBuf;
+use std::process::Command;
+
+use serde_json::Value;
+
+/// An app that uses deprecated env vars, so validating it produces warnings
+const APP_YML: &str = "citadel_version: 4
+metadata:
+  name: Example app
+  version: 1.0.0
+  category: Example
+  tagline: An example app
+  developers:
+    Citadel: https://runcitadel.space
+  permissions:
+    - electrum
+  repo:
+    Example repo: https://github.com/runcitadel/app-cli
+  support: https://t.me/citadeldevelopers
+  description: An example app
+services:
+  main:
+    image: ghcr.io/runcitadel/example:main
+    port: 3000
+    environment:
+      ELECTRUM: $ELECTRUM_IP:$ELECTRUM_PORT
+";
+
+fn write_app(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("app-cli-{}-{}.yml", name, std::process::id()));
+    std::fs::write(&path, APP_YML).unwrap();
+    path
+}
+
+fn validate(app: &PathBuf, format: &str) -> Value {
+    let output = Command::new(env!("CARGO_BIN_EXE_app-cli"))
+        .args(["validate", "--app-name", "example-app", "--format", format])
+        .arg(app)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    serde_json::from_slice(&output.stdout).expect("Output is not valid JSON")
+}
+
+#[test]
+fn validate_json_output() {
+    let app = write_app("json");
+    let report = validate(&app, "json");
+    std::fs::remove_file(app).unwrap();
+    assert_eq!(report["valid"], true);
+    let rules: Vec<&str> = report["apps"][0]["diagnostics"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|diagnostic| diagnostic["rule_id"].as_str().unwrap())
+        .collect();
+    assert_eq!(rules, vec!["deprecated-env-var", "deprecated-env-var"]);
+}
+
+#[test]
+fn validate_sarif_output() {
+    let app = write_app("sarif");
+    let report = validate(&app, "sarif");
+    std::fs::remove_file(app).unwrap();
+    assert_eq!(report["version"], "2.1.0");
+    assert_eq!(report["runs"][0]["results"].as_array().unwrap().len(), 2);
+}